@@ -0,0 +1,104 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::OpenOptions;
+use std::io::{ErrorKind, Write};
+use std::path::Path;
+use std::{fs, io};
+
+/// Hidden, so it is not served by the artifacts file service.
+const ACCESS_FILE: &str = ".access.json";
+
+/// Workspace ownership, the token itself is never stored.
+#[derive(Serialize, Deserialize)]
+struct WorkspaceAccess {
+    token_hash: String,
+    /// User account, that created the workspace.
+    owner: Option<String>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Access {
+    Granted,
+    Denied,
+    /// Workspace created before the access tokens, only the admin's compile claims it.
+    Unclaimed,
+}
+
+/// Tokens are random and long enough, fast hash is fine.
+fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token))
+}
+
+/// Stores the workspace token hash, fails with `AlreadyExists` if the workspace was claimed already.
+pub fn issue(workspace_path: &Path, token: &str, owner: Option<&str>) -> io::Result<()> {
+    fs::create_dir_all(workspace_path)?;
+    let access = WorkspaceAccess {
+        token_hash: hash_token(token),
+        owner: owner.map(String::from),
+    };
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(workspace_path.join(ACCESS_FILE))?
+        .write_all(serde_json::to_string(&access)?.as_bytes())
+}
+
+/// Checks the workspace token, or the user account owning the workspace.
+pub fn check(
+    workspace_path: &Path,
+    token: Option<&str>,
+    user_id: Option<&str>,
+) -> io::Result<Access> {
+    let content = match fs::read_to_string(workspace_path.join(ACCESS_FILE)) {
+        Ok(content) => content,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Access::Unclaimed),
+        Err(err) => return Err(err),
+    };
+    let access: WorkspaceAccess = serde_json::from_str(&content).map_err(io::Error::other)?;
+    let owned = user_id.is_some() && access.owner.as_deref() == user_id;
+    let valid_token = token.is_some_and(|token| hash_token(token) == access.token_hash);
+    Ok(if owned || valid_token {
+        Access::Granted
+    } else {
+        Access::Denied
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_issue_and_check() {
+        let workspace_path =
+            std::env::temp_dir().join(format!("sheeet-access-test-{}", rand::random::<u32>()));
+        assert_eq!(
+            check(&workspace_path, None, None).unwrap(),
+            Access::Unclaimed
+        );
+
+        issue(&workspace_path, "token", Some("alice")).unwrap();
+        let err = issue(&workspace_path, "other", None).expect_err("expected claimed");
+        assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+
+        assert_eq!(
+            check(&workspace_path, Some("token"), None).unwrap(),
+            Access::Granted
+        );
+        assert_eq!(
+            check(&workspace_path, None, Some("alice")).unwrap(),
+            Access::Granted
+        );
+        assert_eq!(
+            check(&workspace_path, Some("other"), None).unwrap(),
+            Access::Denied
+        );
+        assert_eq!(
+            check(&workspace_path, None, Some("bob")).unwrap(),
+            Access::Denied
+        );
+        assert_eq!(check(&workspace_path, None, None).unwrap(), Access::Denied);
+
+        fs::remove_dir_all(&workspace_path).unwrap();
+    }
+}
@@ -0,0 +1,490 @@
+use crate::AppConfig;
+use crate::build_cache::BuildCache;
+use crate::builds::{self, Build};
+use crate::crate_mirror;
+use crate::functions::Function;
+use crate::git;
+use crate::lint::{Diagnostic, LintSummary};
+use crate::metrics::FailureReason;
+use crate::policy;
+use crate::source::{self, Source};
+use crate::testing::{TestResult, TestSummary};
+use crate::workspaces;
+use actix_web::Error;
+use bytes::Bytes;
+use futures_util::Stream;
+use futures_util::stream::{self, StreamExt};
+use log::{debug, info, warn};
+use serde::Serialize;
+use std::io::{BufRead, BufReader, ErrorKind, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{Mutex, MutexGuard, TryLockError};
+use std::thread;
+use std::time::Instant;
+use std::{env, fs};
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+
+const CARGO_LOCK: &str = "Cargo.lock";
+
+#[derive(Serialize)]
+pub struct DownloadInfo {
+    js_download_url: String,
+    wasm_download_url: String,
+    workspace_id: String,
+    /// Source revision the artifacts were built from.
+    revision: u32,
+    build_id: String,
+    /// Functions exported by the build the sheet can call, unknown for the builds published
+    /// before the manifests.
+    functions: Option<Vec<Function>>,
+}
+
+impl DownloadInfo {
+    pub fn new(workspace_id: String, build: Build, functions: Option<Vec<Function>>) -> Self {
+        DownloadInfo {
+            js_download_url: builds::download_url(
+                &workspace_id,
+                &build.build_id,
+                builds::ARTIFACTS[0],
+            ),
+            wasm_download_url: builds::download_url(
+                &workspace_id,
+                &build.build_id,
+                builds::ARTIFACTS[1],
+            ),
+            workspace_id,
+            revision: build.revision,
+            build_id: build.build_id,
+            functions,
+        }
+    }
+}
+
+/// Sent once, when the compile creates or claims the workspace.
+#[derive(Serialize)]
+pub struct WorkspaceAccess {
+    pub workspace_id: String,
+    /// Required by all the following workspace requests, the server stores only its hash.
+    pub workspace_token: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StreamEvent {
+    StdoutLine(String),
+    StderrLine(String),
+    Error(String),
+    Log(String),
+    DownloadInfo(DownloadInfo),
+    WorkspaceAccess(WorkspaceAccess),
+    TestResult(TestResult),
+    TestSummary(TestSummary),
+    Diagnostic(Diagnostic),
+    LintSummary(LintSummary),
+}
+
+pub struct StreamingResponder {
+    receiver: Option<UnboundedReceiver<StreamEvent>>,
+    sender: UnboundedSender<StreamEvent>,
+}
+
+impl StreamingResponder {
+    pub fn new() -> Self {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        StreamingResponder {
+            receiver: Some(receiver),
+            sender,
+        }
+    }
+
+    pub fn send_event(&self, stream_event: StreamEvent) {
+        _ = self.sender.send(stream_event);
+    }
+
+    pub fn sender(&self) -> UnboundedSender<StreamEvent> {
+        self.sender.clone()
+    }
+
+    pub fn log(&self, line: String) {
+        _ = self.sender.send(StreamEvent::Log(line));
+    }
+
+    pub fn terminate_error(&self, err: impl ToString) {
+        _ = self.sender.send(StreamEvent::Error(err.to_string()));
+    }
+
+    pub fn stream_command(&self, command: &mut Command) -> Result<std::process::Child, Error> {
+        let mut child = command
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|err| {
+                actix_web::error::ErrorInternalServerError(format!(
+                    "failed to spawn command: {err}",
+                ))
+            })?;
+
+        let stdout = child.stdout.take().ok_or_else(|| {
+            actix_web::error::ErrorInternalServerError("failed to capture stdout")
+        })?;
+        let stderr = child.stderr.take().ok_or_else(|| {
+            actix_web::error::ErrorInternalServerError("failed to capture stderr")
+        })?;
+
+        self.spawn_buff_line_reading(stdout, StreamEvent::StdoutLine);
+        self.spawn_buff_line_reading(stderr, StreamEvent::StderrLine);
+
+        Ok(child)
+    }
+
+    pub fn spawn_buff_line_reading(
+        &self,
+        pipe: impl Read + Send + 'static,
+        line_constructor: fn(String) -> StreamEvent,
+    ) {
+        let sender = self.sender.clone();
+        // TODO: Shouldn't this be async?
+        thread::spawn(move || {
+            let reader = BufReader::new(pipe);
+            for line in reader.lines().map_while(Result::ok) {
+                let _ = sender.send(line_constructor(line.trim().into()));
+            }
+        });
+    }
+
+    /// Consumes the responder and logs all the events, used for builds without a client.
+    pub fn log_events(mut self) {
+        let mut receiver = self.receiver.take().unwrap();
+        drop(self);
+        while let Some(event) = receiver.blocking_recv() {
+            match event {
+                StreamEvent::Error(err) => warn!("build failed: {err}"),
+                StreamEvent::DownloadInfo(download_info) => info!(
+                    "built workspace ID {} revision {}",
+                    download_info.workspace_id, download_info.revision
+                ),
+                StreamEvent::WorkspaceAccess(access) => {
+                    info!("issued token for workspace ID {}", access.workspace_id)
+                }
+                StreamEvent::TestResult(result) => {
+                    debug!("test {} {:?}", result.name, result.outcome)
+                }
+                StreamEvent::TestSummary(summary) => info!("tests finished: {summary:?}"),
+                StreamEvent::Diagnostic(diagnostic) => {
+                    debug!("{} {}", diagnostic.level, diagnostic.message)
+                }
+                StreamEvent::LintSummary(summary) => info!("lint finished: {summary:?}"),
+                StreamEvent::StdoutLine(line)
+                | StreamEvent::StderrLine(line)
+                | StreamEvent::Log(line) => debug!("{line}"),
+            }
+        }
+    }
+
+    // TODO: Update docs.
+    /// Consumes the responder and produces stream of all messages that receiver will receive.
+    pub fn produce_stream(&mut self) -> impl Stream<Item = Result<Bytes, Error>> + 'static {
+        stream::unfold(self.receiver.take().unwrap(), |mut receiver| async move {
+            receiver.recv().await.map(|event| (event, receiver))
+        })
+        .map(|event: StreamEvent| {
+            Ok(Bytes::from(format!(
+                "data: {}\n",
+                serde_json::to_string(&event)?
+            )))
+        })
+    }
+}
+
+/// Writes the source to the workspace and builds it with trunk, streaming the progress
+/// to the responder.
+pub fn build_workspace(
+    config: &AppConfig,
+    workspace_id: String,
+    workspace_path: &Path,
+    body: &Source,
+    responder: &StreamingResponder,
+) {
+    let started = Instant::now();
+    let result = run_build(config, workspace_id, workspace_path, body, responder);
+    config.metrics.record_build(started.elapsed(), result.err());
+}
+
+/// Reports the failure to the responder and returns its reason for the metrics.
+fn run_build(
+    config: &AppConfig,
+    workspace_id: String,
+    workspace_path: &Path,
+    body: &Source,
+    responder: &StreamingResponder,
+) -> Result<(), FailureReason> {
+    let revision = prepare_workspace(config, workspace_path, body, responder)?;
+    with_build_cache(config, workspace_path, responder, |target_dir| {
+        run_trunk(
+            config,
+            workspace_id,
+            workspace_path,
+            revision,
+            target_dir,
+            responder,
+        )
+    })
+}
+
+/// Initializes the workspace, writes the source into it and checks it may be built. Returns
+/// the saved source revision.
+pub fn prepare_workspace(
+    config: &AppConfig,
+    workspace_path: &Path,
+    body: &Source,
+    responder: &StreamingResponder,
+) -> Result<u32, FailureReason> {
+    // The workspace directory can exist already, holding just the access token.
+    if !fs::exists(workspace_path.join(source::CARGO_TOML)).unwrap_or(false) {
+        if let Err(err) = config.build_cache.init_workspace(workspace_path) {
+            responder.terminate_error(format!("init workspace '{workspace_path:?}': {err}"));
+            return Err(FailureReason::Workspace);
+        };
+        // Committed right away, so the new workspace can be cloned before it compiles.
+        if let Err(err) = git::commit_all(workspace_path, "Initialize workspace") {
+            responder.terminate_error(format!("init workspace repository: {err}"));
+            return Err(FailureReason::Workspace);
+        };
+        responder.log("Workspace initialized.".into());
+    }
+
+    if let Err(err) = workspaces::touch(workspace_path) {
+        responder.terminate_error(format!("mark workspace used: {err}"));
+        return Err(FailureReason::Workspace);
+    }
+
+    // Checked before anything is written, the workspace and its revisions only ever hold
+    // the sources that passed.
+    if let Some(crate_mirror) = &config.crate_mirror
+        && let Err(err) = crate_mirror.check_manifest(body.cargo_toml())
+    {
+        responder.terminate_error(format!("offline build: {err}"));
+        return Err(FailureReason::CrateMirror);
+    }
+    if !config.dependency_policy.is_permissive() {
+        responder.log("Checking dependency policy.".into());
+        if let Err(err) = check_dependency_policy(config, workspace_path, body) {
+            responder.terminate_error(format!("dependency policy: {err}"));
+            return Err(FailureReason::DependencyPolicy);
+        }
+    }
+
+    let revision = match source::save_revision(workspace_path, body) {
+        Ok(revision) => {
+            responder.log(format!("Source saved as revision {revision}."));
+            revision
+        }
+        Err(err) => {
+            responder.terminate_error(format!("save source revision: {err}"));
+            return Err(FailureReason::Workspace);
+        }
+    };
+
+    if let Err(err) = fs::write(workspace_path.join("index.html"), include_str!("user.html")) {
+        responder.terminate_error(err);
+        return Err(FailureReason::Workspace);
+    };
+    if let Err(err) = source::write_source(workspace_path, body) {
+        responder.terminate_error(format!("write source: {err}"));
+        return Err(FailureReason::Workspace);
+    };
+
+    if let Err(err) =
+        crate_mirror::configure_workspace(workspace_path, config.crate_mirror.as_ref())
+    {
+        responder.terminate_error(format!("configure cargo: {err}"));
+        return Err(FailureReason::Workspace);
+    }
+
+    Ok(revision)
+}
+
+/// Checks the source's manifest and its resolved dependencies. They are resolved in a scratch
+/// copy of the source, its `Cargo.lock` is then kept, so the build uses the checked dependencies.
+fn check_dependency_policy(
+    config: &AppConfig,
+    workspace_path: &Path,
+    body: &Source,
+) -> Result<(), String> {
+    config.dependency_policy.check_manifest(body.cargo_toml())?;
+
+    let scratch_path = env::temp_dir().join(format!("sheeet-policy-{}", rand::random::<u32>()));
+    let checked = resolve_scratch(config, workspace_path, &scratch_path, body)
+        .and_then(|metadata| config.dependency_policy.check_metadata(&metadata))
+        .and_then(|_| {
+            fs::copy(
+                scratch_path.join(CARGO_LOCK),
+                workspace_path.join(CARGO_LOCK),
+            )
+            .map(|_| ())
+            .map_err(|err| format!("keep resolved {CARGO_LOCK}: {err}"))
+        });
+    _ = fs::remove_dir_all(&scratch_path);
+    checked
+}
+
+fn resolve_scratch(
+    config: &AppConfig,
+    workspace_path: &Path,
+    scratch_path: &Path,
+    body: &Source,
+) -> Result<policy::Metadata, String> {
+    source::write_source(scratch_path, body).map_err(|err| format!("write source: {err}"))?;
+    // Resolved the same as the last build, not to the latest versions.
+    match fs::copy(
+        workspace_path.join(CARGO_LOCK),
+        scratch_path.join(CARGO_LOCK),
+    ) {
+        Err(err) if err.kind() != ErrorKind::NotFound => {
+            return Err(format!("copy {CARGO_LOCK}: {err}"));
+        }
+        _ => {}
+    }
+    crate_mirror::configure_workspace(scratch_path, config.crate_mirror.as_ref())
+        .map_err(|err| format!("configure cargo: {err}"))?;
+    policy::resolve(scratch_path)
+}
+
+/// Runs `build` holding the workspace's build lock, with the cargo target directory shared
+/// by its dependency set (holding its lock too), or its own one.
+pub fn with_build_cache<T>(
+    config: &AppConfig,
+    workspace_path: &Path,
+    responder: &StreamingResponder,
+    build: impl FnOnce(PathBuf) -> Result<T, FailureReason>,
+) -> Result<T, FailureReason> {
+    let workspace_lock = config.build_cache.lock(workspace_path);
+    let _workspace_guard = acquire(
+        config,
+        &workspace_lock,
+        "Waiting for another build of the workspace.",
+        responder,
+    )?;
+    let target_dir = match BuildCache::dependency_key(workspace_path) {
+        Ok(Some(key)) => {
+            responder.log(format!("Building with the shared dependencies {key}."));
+            config.build_cache.shared_target_dir(&key)
+        }
+        Ok(None) => {
+            responder.log(
+                "The workspace runs its own code while building, it builds its own dependencies."
+                    .into(),
+            );
+            BuildCache::target_dir(workspace_path)
+        }
+        // The build just compiles all the dependencies itself.
+        Err(err) => {
+            responder.log(format!("Failed to share the dependencies: {err}"));
+            BuildCache::target_dir(workspace_path)
+        }
+    };
+    let target_lock = config.build_cache.lock(&target_dir);
+    let _target_guard = acquire(
+        config,
+        &target_lock,
+        "Waiting for another build with the same dependencies.",
+        responder,
+    )?;
+    if let Err(err) = fs::create_dir_all(&target_dir).and_then(|_| workspaces::touch(&target_dir)) {
+        responder.terminate_error(format!("create target directory: {err}"));
+        return Err(FailureReason::BuildCache);
+    }
+    build(target_dir)
+}
+
+fn acquire<'a>(
+    config: &AppConfig,
+    lock: &'a Mutex<()>,
+    waiting: &str,
+    responder: &StreamingResponder,
+) -> Result<MutexGuard<'a, ()>, FailureReason> {
+    match lock.try_lock() {
+        Ok(guard) => Ok(guard),
+        Err(TryLockError::WouldBlock) => {
+            responder.log(waiting.into());
+            let _queued = config.metrics.queued();
+            lock.lock().map_err(|err| {
+                responder.terminate_error(format!("build cache lock: {err}"));
+                FailureReason::BuildCache
+            })
+        }
+        Err(TryLockError::Poisoned(err)) => {
+            responder.terminate_error(format!("build cache lock: {err}"));
+            Err(FailureReason::BuildCache)
+        }
+    }
+}
+
+fn run_trunk(
+    config: &AppConfig,
+    workspace_id: String,
+    workspace_path: &Path,
+    revision: u32,
+    target_dir: PathBuf,
+    responder: &StreamingResponder,
+) -> Result<(), FailureReason> {
+    let mut trunk_build = Command::new("trunk");
+    trunk_build
+        .arg("build")
+        .current_dir(workspace_path)
+        .env("RUST_LOG", "info")
+        .env("RUST_LOG_STYLE", "never")
+        .env("CARGO_TARGET_DIR", target_dir);
+
+    let _active = config.metrics.active();
+    let mut child = match responder.stream_command(&mut trunk_build) {
+        Ok(child) => child,
+        Err(err) => {
+            responder.terminate_error(err);
+            return Err(FailureReason::Internal);
+        }
+    };
+
+    match child.wait() {
+        Ok(status) if status.success() => {
+            let build =
+                match builds::publish(workspace_path, &workspace_path.join("dist"), revision) {
+                    Ok(build) => build,
+                    Err(err) => {
+                        responder.terminate_error(format!("publish build: {err}"));
+                        return Err(FailureReason::Workspace);
+                    }
+                };
+            responder.log(format!("Published build {}.", build.build_id));
+            if let Err(err) =
+                git::commit_all(workspace_path, &format!("Compile revision {revision}"))
+            {
+                responder.log(format!(
+                    "Failed to commit to the workspace repository: {err}"
+                ));
+            }
+            let functions =
+                builds::load_functions(&builds::build_path(workspace_path, &build.build_id))
+                    .unwrap_or_else(|err| {
+                        responder.log(format!("Failed to load the function manifest: {err}"));
+                        None
+                    });
+            responder.send_event(StreamEvent::DownloadInfo(DownloadInfo::new(
+                workspace_id,
+                build,
+                functions,
+            )));
+            Ok(())
+        }
+        Ok(status) => {
+            responder.terminate_error(format!("build failed: {status}"));
+            Err(FailureReason::Build)
+        }
+        Err(err) => {
+            responder.terminate_error(format!("build failed with err: {err}"));
+            Err(FailureReason::Internal)
+        }
+    }
+}
@@ -0,0 +1,318 @@
+use crate::crate_mirror::{self, CrateMirror};
+use crate::source::CARGO_TOML;
+use crate::workspaces;
+use log::{info, warn};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use std::{fs, io};
+
+const CARGO_LOCK: &str = "Cargo.lock";
+/// Shared target directories, named by the dependency sets' keys.
+const TARGETS_DIR: &str = "targets";
+/// Hex prefix of the dependency set hash.
+const KEY_LENGTH: usize = 16;
+
+/// Cargo.toml of the pre-warmed template workspace, matches the GUI default.
+pub const TEMPLATE_CARGO_TOML: &str = include_str!("user_cargo.toml");
+/// src/lib.rs of the pre-warmed template workspace, matches the GUI default.
+pub const TEMPLATE_LIB_RS: &str = include_str!("user_lib.rs");
+
+/// Shares compiled dependencies between workspaces.
+///
+/// Workspaces with the same dependency set (the resolved `Cargo.lock` and the manifest's
+/// dependency tables) build into the same cargo target directory, keyed by its hash, so only
+/// the first of them compiles `wasm-bindgen`, `sheeet-funcs` etc. and the others just read
+/// the compiled dependencies. The template workspace warms the default dependency set.
+/// Workspaces running their own code while building (their build script or path dependencies)
+/// could tamper with the shared artifacts, they build into their own target directory.
+/// Builds of the same target directory are serialized, cargo would block on its own build
+/// directory lock anyway and the final `sheeet_lib.wasm` artifact would be clobbered by
+/// concurrent builds.
+pub struct BuildCache {
+    path: PathBuf,
+    locks: Mutex<HashMap<PathBuf, Arc<Mutex<()>>>>,
+}
+
+impl BuildCache {
+    pub fn new(path: PathBuf) -> Self {
+        BuildCache {
+            path,
+            locks: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Own cargo target directory of the workspace, pruned and evicted with its other artifacts.
+    pub fn target_dir(workspace_path: &Path) -> PathBuf {
+        workspace_path.join("target")
+    }
+
+    /// Cargo target directory shared by the workspaces with the dependency set's key.
+    pub fn shared_target_dir(&self, key: &str) -> PathBuf {
+        self.path.join(TARGETS_DIR).join(key)
+    }
+
+    pub fn template_path(&self) -> PathBuf {
+        self.path.join("template")
+    }
+
+    /// Returns lock guarding the given workspace or target directory.
+    pub fn lock(&self, path: &Path) -> Arc<Mutex<()>> {
+        self.locks
+            .lock()
+            .expect("build cache locks poisoned")
+            .entry(path.into())
+            .or_default()
+            .clone()
+    }
+
+    /// Key of the workspace's dependency set, resolving its `Cargo.lock` first if it has none.
+    /// `None` if the workspace runs its own code while building.
+    pub fn dependency_key(workspace_path: &Path) -> Result<Option<String>, String> {
+        if !fs::exists(workspace_path.join(CARGO_LOCK)).unwrap_or(false) {
+            let output = Command::new("cargo")
+                .arg("generate-lockfile")
+                .current_dir(workspace_path)
+                .output()
+                .map_err(|err| format!("failed to run cargo generate-lockfile: {err}"))?;
+            if !output.status.success() {
+                return Err(format!(
+                    "failed to resolve dependencies: {}\n{}",
+                    output.status,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ));
+            }
+        }
+        let read = |file: &str| {
+            fs::read_to_string(workspace_path.join(file))
+                .map_err(|err| format!("read {file}: {err}"))?
+                .parse::<toml::Table>()
+                .map_err(|err| format!("parse {file}: {err}"))
+        };
+        Ok(dependency_key(read(CARGO_TOML)?, &read(CARGO_LOCK)?))
+    }
+
+    /// Removes the shared target directories unused for longer than `idle`.
+    pub fn prune_targets(&self, idle: Duration) -> io::Result<()> {
+        let entries = match fs::read_dir(self.path.join(TARGETS_DIR)) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err),
+        };
+        for entry in entries {
+            let target_dir = entry?.path();
+            let lock = self.lock(&target_dir);
+            let _guard = lock.lock().expect("build cache lock poisoned");
+            if workspaces::last_used(&target_dir)?
+                .elapsed()
+                .unwrap_or_default()
+                > idle
+            {
+                info!("pruning idle shared target directory {target_dir:?}");
+                fs::remove_dir_all(&target_dir)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the template workspace sources to the given path.
+    pub fn write_template(path: &Path) -> io::Result<()> {
+        fs::create_dir_all(path.join("src"))?;
+        fs::write(path.join("Cargo.toml"), TEMPLATE_CARGO_TOML)?;
+        fs::write(path.join("src/lib.rs"), TEMPLATE_LIB_RS)?;
+        fs::write(path.join("index.html"), include_str!("user.html"))?;
+        Ok(())
+    }
+
+    /// Initializes new workspace by copying the template workspace sources.
+    pub fn init_workspace(&self, workspace_path: &Path) -> io::Result<()> {
+        let template_path = self.template_path();
+        if !fs::exists(template_path.join("Cargo.toml"))? {
+            return Self::write_template(workspace_path);
+        }
+        fs::create_dir_all(workspace_path.join("src"))?;
+        for file in ["Cargo.toml", "src/lib.rs", "index.html"] {
+            fs::copy(template_path.join(file), workspace_path.join(file))?;
+        }
+        Ok(())
+    }
+
+    /// Builds the template workspace, so the default dependency set is compiled before
+    /// the first user arrives. Populates the crate mirror first, if it is configured.
+    pub fn warm_template(&self, crate_mirror: Option<&CrateMirror>) {
+        let template_path = self.template_path();
+        if let Err(err) = Self::write_template(&template_path) {
+            warn!("failed to write template workspace {template_path:?}: {err}");
+            return;
+        }
+        if let Some(crate_mirror) = crate_mirror {
+            crate_mirror.populate(&template_path);
+        }
+        if let Err(err) = crate_mirror::configure_workspace(&template_path, crate_mirror) {
+            warn!("failed to configure template workspace {template_path:?}: {err}");
+            return;
+        }
+
+        // Copied to the workspaces by the versions before the shared target directories.
+        _ = fs::remove_dir_all(Self::target_dir(&template_path));
+        let target_dir = match Self::dependency_key(&template_path) {
+            Ok(Some(key)) => self.shared_target_dir(&key),
+            Ok(None) => {
+                warn!("template workspace runs its own code while building, nothing to share");
+                return;
+            }
+            Err(err) => {
+                warn!("failed to warm build cache: {err}");
+                return;
+            }
+        };
+
+        let lock = self.lock(&target_dir);
+        let _guard = lock.lock().expect("build cache lock poisoned");
+        if let Err(err) =
+            fs::create_dir_all(&target_dir).and_then(|_| workspaces::touch(&target_dir))
+        {
+            warn!("failed to create shared target directory {target_dir:?}: {err}");
+            return;
+        }
+
+        info!("warming build cache with template workspace into {target_dir:?}");
+        match Command::new("trunk")
+            .arg("build")
+            .current_dir(&template_path)
+            .env("CARGO_TARGET_DIR", &target_dir)
+            .env("RUST_LOG", "info")
+            .env("RUST_LOG_STYLE", "never")
+            .output()
+        {
+            Ok(output) if output.status.success() => {
+                info!("build cache warmed");
+            }
+            Ok(output) => {
+                warn!(
+                    "failed to warm build cache: {}\n{}",
+                    output.status,
+                    String::from_utf8_lossy(&output.stderr)
+                );
+            }
+            Err(err) => {
+                warn!("failed to warm build cache: {err}");
+            }
+        }
+    }
+}
+
+/// Hash of the `Cargo.lock` packages from the registries and git, and of the manifest's
+/// tables other than `[package]` and `[lib]`, which only affect the workspace's own crate.
+fn dependency_key(mut manifest: toml::Table, lock: &toml::Table) -> Option<String> {
+    let package = manifest.remove("package");
+    if package
+        .as_ref()
+        .and_then(|package| package.get("build"))
+        .is_some_and(|build| build.as_bool() != Some(false))
+    {
+        return None;
+    }
+    manifest.remove("lib");
+
+    let packages = lock.get("package")?.as_array()?;
+    // The workspace's own crate and the path dependencies have no source.
+    let (sourced, local): (Vec<_>, Vec<_>) = packages
+        .iter()
+        .partition(|package| package.get("source").is_some());
+    if local.len() > 1 {
+        return None;
+    }
+    let mut hasher = Sha256::new();
+    hasher.update(manifest.to_string());
+    for package in sourced {
+        hasher.update(package.to_string());
+    }
+    Some(hex::encode(hasher.finalize())[..KEY_LENGTH].to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dependency_key() {
+        let key = |manifest: &str, lock: &str| {
+            dependency_key(manifest.parse().unwrap(), &lock.parse().unwrap())
+        };
+        let manifest = r#"
+            [package]
+            name = "sheeet_lib"
+            [lib]
+            crate-type = ["cdylib"]
+            [dependencies]
+            sheeet-funcs = "0.2.0"
+        "#;
+        let lock = r#"
+            [[package]]
+            name = "sheeet_lib"
+            version = "0.1.0"
+            [[package]]
+            name = "sheeet-funcs"
+            version = "0.2.0"
+            source = "registry+https://github.com/rust-lang/crates.io-index"
+        "#;
+        let template = key(manifest, lock).unwrap();
+        assert_eq!(template.len(), KEY_LENGTH);
+
+        // The workspace's own crate doesn't change the key.
+        let renamed = key(
+            &manifest.replace(r#"name = "sheeet_lib""#, r#"name = "other""#),
+            &lock.replace(r#"name = "sheeet_lib""#, r#"name = "other""#),
+        );
+        assert_eq!(renamed.as_ref(), Some(&template));
+
+        // The dependencies' features and versions do.
+        let with_features = manifest.replace(
+            r#"sheeet-funcs = "0.2.0""#,
+            r#"sheeet-funcs = { version = "0.2.0", features = ["decimal"] }"#,
+        );
+        assert_ne!(key(&with_features, lock).unwrap(), template);
+        let upgraded = lock.replace(r#"version = "0.2.0""#, r#"version = "0.2.1""#);
+        assert_ne!(key(manifest, &upgraded).unwrap(), template);
+
+        // The workspaces running their own code while building share nothing.
+        let with_build_script = manifest.replace(
+            r#"name = "sheeet_lib""#,
+            "name = \"sheeet_lib\"\nbuild = \"src/build.rs\"",
+        );
+        assert_eq!(key(&with_build_script, lock), None);
+        let with_path_dependency =
+            format!("{lock}\n[[package]]\nname = \"local\"\nversion = \"0.1.0\"\n");
+        assert_eq!(key(manifest, &with_path_dependency), None);
+    }
+
+    #[test]
+    fn test_prune_targets() {
+        let cache_path =
+            std::env::temp_dir().join(format!("sheeet-build-cache-test-{}", rand::random::<u32>()));
+        let build_cache = BuildCache::new(cache_path.clone());
+        let idle = build_cache.shared_target_dir("idle");
+        let used = build_cache.shared_target_dir("used");
+        for target_dir in [&idle, &used] {
+            fs::create_dir_all(target_dir.join("debug/deps")).unwrap();
+            workspaces::touch(target_dir).unwrap();
+        }
+        fs::File::options()
+            .write(true)
+            .open(idle.join(workspaces::LAST_USED_FILE))
+            .unwrap()
+            .set_modified(std::time::SystemTime::now() - Duration::from_secs(3600))
+            .unwrap();
+
+        build_cache.prune_targets(Duration::from_secs(60)).unwrap();
+        assert!(!fs::exists(&idle).unwrap());
+        assert!(fs::exists(used.join("debug/deps")).unwrap());
+
+        fs::remove_dir_all(&cache_path).unwrap();
+    }
+}
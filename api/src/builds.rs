@@ -0,0 +1,219 @@
+use crate::functions::{self, Function};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{fs, io};
+
+/// Published builds of the workspace, served by the artifacts file service. Each build lives
+/// in its own directory named by the hash of its artifacts, so it never changes once written.
+pub const BUILDS_DIR: &str = "builds";
+/// Hidden, so the manifest is served only through the API.
+const MANIFEST_FILE: &str = ".manifest.json";
+/// Artifacts of a trunk build, relative to its `dist` directory.
+pub const ARTIFACTS: [&str; 2] = ["sheeet-lib.js", "sheeet-lib_bg.wasm"];
+/// Manifest of the functions exported by the build, generated from its JS glue.
+pub const FUNCTIONS_FILE: &str = "functions.json";
+/// Older builds are deleted, the current one is always kept.
+const MAX_BUILDS: usize = 10;
+const BUILD_ID_LENGTH: usize = 16;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Build {
+    /// Hex prefix of the artifacts hash.
+    pub build_id: String,
+    /// Source revision the artifacts were built from.
+    pub revision: u32,
+    /// Unix timestamp in seconds.
+    pub built_at: u64,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct BuildManifest {
+    /// Build the GUI loads, the latest one unless rolled back.
+    pub current: Option<String>,
+    /// Recent builds, oldest first.
+    pub builds: Vec<Build>,
+}
+
+pub fn is_valid_build_id(build_id: &str) -> bool {
+    build_id.len() == BUILD_ID_LENGTH
+        && build_id
+            .chars()
+            .all(|c| c.is_ascii_digit() || matches!(c, 'a'..='f'))
+}
+
+fn builds_path(workspace_path: &Path) -> PathBuf {
+    workspace_path.join(BUILDS_DIR)
+}
+
+pub fn build_path(workspace_path: &Path, build_id: &str) -> PathBuf {
+    builds_path(workspace_path).join(build_id)
+}
+
+/// Download URL of the build artifact, relative to the API URL prefix.
+pub fn download_url(workspace_id: &str, build_id: &str, artifact: &str) -> String {
+    format!("/workspaces/{workspace_id}/{BUILDS_DIR}/{build_id}/{artifact}")
+}
+
+pub fn load_manifest(workspace_path: &Path) -> io::Result<BuildManifest> {
+    match fs::read_to_string(builds_path(workspace_path).join(MANIFEST_FILE)) {
+        Ok(content) => serde_json::from_str(&content).map_err(io::Error::other),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(BuildManifest::default()),
+        Err(err) => Err(err),
+    }
+}
+
+/// Replaces the manifest atomically, so the API never reads a partially written one.
+fn save_manifest(workspace_path: &Path, manifest: &BuildManifest) -> io::Result<()> {
+    let path = builds_path(workspace_path).join(MANIFEST_FILE);
+    let tmp_path = path.with_extension(format!("tmp-{}", rand::random::<u32>()));
+    fs::write(&tmp_path, serde_json::to_string_pretty(manifest)?)?;
+    fs::rename(tmp_path, path)
+}
+
+/// Publishes the artifacts in `dist_path` as the workspace's current build.
+pub fn publish(workspace_path: &Path, dist_path: &Path, revision: u32) -> io::Result<Build> {
+    let mut hasher = Sha256::new();
+    let mut artifacts = Vec::new();
+    for artifact in ARTIFACTS {
+        let content = fs::read(dist_path.join(artifact))?;
+        hasher.update((content.len() as u64).to_le_bytes());
+        hasher.update(&content);
+        artifacts.push((artifact, content));
+    }
+    let build_id = hex::encode(hasher.finalize())[..BUILD_ID_LENGTH].to_string();
+
+    let path = build_path(workspace_path, &build_id);
+    if !fs::exists(&path)? {
+        // Written aside and renamed into place, the build directory is complete or missing.
+        let tmp_path =
+            builds_path(workspace_path).join(format!(".tmp-{build_id}-{}", rand::random::<u32>()));
+        fs::create_dir_all(&tmp_path)?;
+        for (artifact, content) in &artifacts {
+            fs::write(tmp_path.join(artifact), content)?;
+        }
+        let js = String::from_utf8_lossy(&artifacts[0].1);
+        fs::write(
+            tmp_path.join(FUNCTIONS_FILE),
+            serde_json::to_string(&functions::parse_manifest(&js))?,
+        )?;
+        if let Err(err) = fs::rename(&tmp_path, &path) {
+            fs::remove_dir_all(&tmp_path)?;
+            // Concurrent build of the same artifacts won.
+            if !fs::exists(&path)? {
+                return Err(err);
+            }
+        }
+    }
+
+    let build = Build {
+        build_id: build_id.clone(),
+        revision,
+        built_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default(),
+    };
+    let mut manifest = load_manifest(workspace_path)?;
+    manifest.builds.retain(|build| build.build_id != build_id);
+    manifest.builds.push(build.clone());
+    manifest.current = Some(build_id.clone());
+    while manifest.builds.len() > MAX_BUILDS {
+        let evicted = manifest.builds.remove(0);
+        match fs::remove_dir_all(build_path(workspace_path, &evicted.build_id)) {
+            Ok(()) => {}
+            Err(err) if err.kind() == ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+    }
+    save_manifest(workspace_path, &manifest)?;
+    Ok(build)
+}
+
+/// Makes the recent build current again, returns `None` if there is no such build.
+pub fn rollback(workspace_path: &Path, build_id: &str) -> io::Result<Option<Build>> {
+    let mut manifest = load_manifest(workspace_path)?;
+    let Some(build) = manifest
+        .builds
+        .iter()
+        .find(|build| build.build_id == build_id)
+        .cloned()
+    else {
+        return Ok(None);
+    };
+    manifest.current = Some(build.build_id.clone());
+    save_manifest(workspace_path, &manifest)?;
+    Ok(Some(build))
+}
+
+/// Functions exported by the build, `None` for the builds published before the manifests.
+pub fn load_functions(build_path: &Path) -> io::Result<Option<Vec<Function>>> {
+    match fs::read_to_string(build_path.join(FUNCTIONS_FILE)) {
+        Ok(content) => serde_json::from_str(&content).map_err(io::Error::other),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// The current build, `None` if the workspace has no published build.
+pub fn current(workspace_path: &Path) -> io::Result<Option<Build>> {
+    let manifest = load_manifest(workspace_path)?;
+    Ok(manifest.current.and_then(|build_id| {
+        manifest
+            .builds
+            .into_iter()
+            .find(|build| build.build_id == build_id)
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_publish_and_rollback() {
+        let workspace_path =
+            std::env::temp_dir().join(format!("sheeet-builds-test-{}", rand::random::<u32>()));
+        let dist_path = workspace_path.join("dist");
+        fs::create_dir_all(&dist_path).unwrap();
+        let mut build_ids = Vec::new();
+        for revision in 1..=MAX_BUILDS as u32 + 2 {
+            fs::write(
+                dist_path.join(ARTIFACTS[0]),
+                format!("export function f{revision}(a) {{}}"),
+            )
+            .unwrap();
+            fs::write(dist_path.join(ARTIFACTS[1]), "wasm").unwrap();
+            let build = publish(&workspace_path, &dist_path, revision).unwrap();
+            assert!(is_valid_build_id(&build.build_id));
+            build_ids.push(build.build_id);
+        }
+        // Same artifacts are the same build.
+        let republished = publish(&workspace_path, &dist_path, 100).unwrap();
+        assert_eq!(&republished.build_id, build_ids.last().unwrap());
+
+        let manifest = load_manifest(&workspace_path).unwrap();
+        assert_eq!(manifest.builds.len(), MAX_BUILDS);
+        assert_eq!(manifest.current.as_ref(), build_ids.last());
+        assert!(!fs::exists(build_path(&workspace_path, &build_ids[0])).unwrap());
+        let current_build = current(&workspace_path).unwrap().unwrap();
+        assert_eq!(current_build, republished);
+        let current_path = build_path(&workspace_path, &current_build.build_id);
+        assert_eq!(
+            fs::read_to_string(current_path.join(ARTIFACTS[0])).unwrap(),
+            format!("export function f{}(a) {{}}", MAX_BUILDS + 2)
+        );
+        let functions = load_functions(&current_path).unwrap().unwrap();
+        assert_eq!(functions.len(), 1);
+        assert_eq!(functions[0].name, format!("f{}", MAX_BUILDS + 2));
+
+        let rolled_back = rollback(&workspace_path, &build_ids[5]).unwrap().unwrap();
+        assert_eq!(rolled_back.revision, 6);
+        assert_eq!(current(&workspace_path).unwrap(), Some(rolled_back));
+        assert_eq!(rollback(&workspace_path, &build_ids[0]).unwrap(), None);
+
+        fs::remove_dir_all(&workspace_path).unwrap();
+    }
+}
@@ -0,0 +1,450 @@
+use crate::policy::{self, DependencyPolicy};
+use crate::rate_limit::RateLimit;
+use crate::testing::TestRunner;
+use rustls_pki_types::pem::PemObject;
+use rustls_pki_types::{CertificateDer, PrivateKeyDer};
+use serde::Deserialize;
+use std::net::ToSocketAddrs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::{env, fs};
+
+/// HTTP server configuration, read from the optional TOML file (`SHEEET_CONFIG_PATH`)
+/// and overridden by the environment variables.
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub bind_address: String,
+    pub port: u16,
+    pub workers: usize,
+    /// Origins allowed by CORS, e.g. `https://sheeet.example.com`, any origin if empty.
+    pub allowed_origins: Vec<String>,
+    /// Serves HTTPS directly, if both the certificate chain and the key PEM files are set.
+    pub tls_cert_path: Option<PathBuf>,
+    pub tls_key_path: Option<PathBuf>,
+    /// Limit of the JSON request bodies (compile sources, shares) in bytes.
+    pub json_limit_bytes: usize,
+    /// Limit of the git push request bodies in bytes, pushes are sent in one request.
+    pub git_payload_limit_bytes: usize,
+    /// Path prefix of all the API routes, empty to serve them at the root.
+    pub url_prefix: String,
+    /// Compiles budget of each client IP address, unlimited if unset.
+    pub compile_rate_limit_per_ip: Option<RateLimit>,
+    /// Compiles budget of each existing workspace, unlimited if unset.
+    pub compile_rate_limit_per_workspace: Option<RateLimit>,
+    /// Client IP address is taken from the `X-Forwarded-For` header, set only behind a proxy.
+    pub trust_proxy_headers: bool,
+    /// Anonymous compiles need a proof of work with this many leading zero bits, if set.
+    pub proof_of_work_difficulty: Option<u32>,
+    /// Workspaces directory, `$HOME/workspaces` if unset.
+    pub workspaces_path: Option<PathBuf>,
+    /// Template build cache, `<workspaces_path>/.cache` if unset.
+    pub build_cache_path: Option<PathBuf>,
+    /// User accounts file, `<workspaces_path>/.users.json` if unset.
+    pub users_path: Option<PathBuf>,
+    pub crate_mirror_path: Option<PathBuf>,
+    /// Makes the instance private, only the admin and the users can compile.
+    pub secret_api_key: Option<String>,
+    /// Allowed direct dependencies, e.g. `wasm-bindgen@0.2, sheeet-funcs@0.1`, any if unset.
+    pub allowed_dependencies: Option<String>,
+    /// Dependency policy rules, unset ones are allowed only on the private instances.
+    pub allow_build_scripts: Option<bool>,
+    pub allow_proc_macros: Option<bool>,
+    pub allow_git_dependencies: Option<bool>,
+    pub allow_path_dependencies: Option<bool>,
+    /// Private instances run the tests natively, public ones in `wasmtime`, if unset.
+    pub test_runner: Option<TestRunner>,
+    /// Whole test run is killed afterwards.
+    pub test_timeout_secs: u64,
+    /// Language server sessions at once, 0 disables the language server.
+    pub lsp_max_sessions: usize,
+    pub lsp_idle_timeout_secs: u64,
+    /// Workspaces janitor runs this often, if any of its limits is set.
+    pub janitor_interval_secs: u64,
+    pub workspace_prune_after_secs: Option<u64>,
+    pub workspace_evict_after_secs: Option<u64>,
+    pub workspaces_disk_budget_bytes: Option<u64>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            bind_address: "0.0.0.0".into(),
+            port: 8080,
+            workers: 4,
+            allowed_origins: Vec::new(),
+            tls_cert_path: None,
+            tls_key_path: None,
+            json_limit_bytes: 2 * 1024 * 1024,
+            git_payload_limit_bytes: 64 * 1024 * 1024,
+            url_prefix: "/api".into(),
+            compile_rate_limit_per_ip: None,
+            compile_rate_limit_per_workspace: None,
+            trust_proxy_headers: false,
+            proof_of_work_difficulty: None,
+            workspaces_path: None,
+            build_cache_path: None,
+            users_path: None,
+            crate_mirror_path: None,
+            secret_api_key: None,
+            allowed_dependencies: None,
+            allow_build_scripts: None,
+            allow_proc_macros: None,
+            allow_git_dependencies: None,
+            allow_path_dependencies: None,
+            test_runner: None,
+            test_timeout_secs: 600,
+            lsp_max_sessions: 4,
+            lsp_idle_timeout_secs: 1800,
+            janitor_interval_secs: 3600,
+            workspace_prune_after_secs: None,
+            workspace_evict_after_secs: None,
+            workspaces_disk_budget_bytes: None,
+        }
+    }
+}
+
+fn parse<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, String>
+where
+    T::Err: std::fmt::Display,
+{
+    value
+        .parse()
+        .map_err(|err| format!("invalid {name} value '{value}': {err}"))
+}
+
+fn parse_flag(name: &str, value: &str) -> Result<bool, String> {
+    match value {
+        "1" | "true" => Ok(true),
+        "0" | "false" => Ok(false),
+        _ => Err(format!("invalid {name} value '{value}', expected 1 or 0")),
+    }
+}
+
+impl ServerConfig {
+    pub fn load() -> Result<Self, String> {
+        let mut config = match env::var("SHEEET_CONFIG_PATH") {
+            Ok(path) => Self::from_file(Path::new(&path))?,
+            Err(_) => ServerConfig::default(),
+        };
+        config.apply_env(|name| env::var(name).ok())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn from_file(path: &Path) -> Result<Self, String> {
+        let content =
+            fs::read_to_string(path).map_err(|err| format!("read config file {path:?}: {err}"))?;
+        toml::from_str(&content).map_err(|err| format!("invalid config file {path:?}: {err}"))
+    }
+
+    fn apply_env(&mut self, var: impl Fn(&str) -> Option<String>) -> Result<(), String> {
+        if let Some(value) = var("SHEEET_BIND_ADDRESS") {
+            self.bind_address = value;
+        }
+        if let Some(value) = var("SHEEET_PORT") {
+            self.port = parse("SHEEET_PORT", &value)?;
+        }
+        if let Some(value) = var("SHEEET_WORKERS") {
+            self.workers = parse("SHEEET_WORKERS", &value)?;
+        }
+        if let Some(value) = var("SHEEET_ALLOWED_ORIGINS") {
+            self.allowed_origins = value
+                .split(',')
+                .map(str::trim)
+                .filter(|origin| !origin.is_empty())
+                .map(String::from)
+                .collect();
+        }
+        if let Some(value) = var("SHEEET_TLS_CERT_PATH") {
+            self.tls_cert_path = Some(value.into());
+        }
+        if let Some(value) = var("SHEEET_TLS_KEY_PATH") {
+            self.tls_key_path = Some(value.into());
+        }
+        if let Some(value) = var("SHEEET_JSON_LIMIT_BYTES") {
+            self.json_limit_bytes = parse("SHEEET_JSON_LIMIT_BYTES", &value)?;
+        }
+        if let Some(value) = var("SHEEET_GIT_PAYLOAD_LIMIT_BYTES") {
+            self.git_payload_limit_bytes = parse("SHEEET_GIT_PAYLOAD_LIMIT_BYTES", &value)?;
+        }
+        if let Some(value) = var("SHEEET_URL_PREFIX") {
+            self.url_prefix = value;
+        }
+        if let Some(value) = var("SHEEET_COMPILE_RATE_LIMIT_PER_IP") {
+            self.compile_rate_limit_per_ip =
+                Some(parse("SHEEET_COMPILE_RATE_LIMIT_PER_IP", &value)?);
+        }
+        if let Some(value) = var("SHEEET_COMPILE_RATE_LIMIT_PER_WORKSPACE") {
+            self.compile_rate_limit_per_workspace =
+                Some(parse("SHEEET_COMPILE_RATE_LIMIT_PER_WORKSPACE", &value)?);
+        }
+        if let Some(value) = var("SHEEET_TRUST_PROXY_HEADERS") {
+            self.trust_proxy_headers = parse_flag("SHEEET_TRUST_PROXY_HEADERS", &value)?;
+        }
+        if let Some(value) = var("SHEEET_PROOF_OF_WORK_DIFFICULTY") {
+            self.proof_of_work_difficulty = Some(parse("SHEEET_PROOF_OF_WORK_DIFFICULTY", &value)?);
+        }
+        if let Some(value) = var("SHEEET_WORKSPACES_PATH") {
+            self.workspaces_path = Some(value.into());
+        }
+        if let Some(value) = var("SHEEET_BUILD_CACHE_PATH") {
+            self.build_cache_path = Some(value.into());
+        }
+        if let Some(value) = var("SHEEET_USERS_PATH") {
+            self.users_path = Some(value.into());
+        }
+        if let Some(value) = var("SHEEET_CRATE_MIRROR_PATH") {
+            self.crate_mirror_path = Some(value.into());
+        }
+        if let Some(value) = var("SHEEET_SECRET_API_KEY") {
+            self.secret_api_key = Some(value);
+        }
+        if let Some(value) = var("SHEEET_ALLOWED_DEPENDENCIES") {
+            self.allowed_dependencies = Some(value);
+        }
+        for (name, flag) in [
+            ("SHEEET_ALLOW_BUILD_SCRIPTS", &mut self.allow_build_scripts),
+            ("SHEEET_ALLOW_PROC_MACROS", &mut self.allow_proc_macros),
+            (
+                "SHEEET_ALLOW_GIT_DEPENDENCIES",
+                &mut self.allow_git_dependencies,
+            ),
+            (
+                "SHEEET_ALLOW_PATH_DEPENDENCIES",
+                &mut self.allow_path_dependencies,
+            ),
+        ] {
+            if let Some(value) = var(name) {
+                *flag = Some(parse_flag(name, &value)?);
+            }
+        }
+        if let Some(value) = var("SHEEET_TEST_RUNNER") {
+            self.test_runner = Some(parse("SHEEET_TEST_RUNNER", &value)?);
+        }
+        if let Some(value) = var("SHEEET_TEST_TIMEOUT_SECS") {
+            self.test_timeout_secs = parse("SHEEET_TEST_TIMEOUT_SECS", &value)?;
+        }
+        if let Some(value) = var("SHEEET_LSP_MAX_SESSIONS") {
+            self.lsp_max_sessions = parse("SHEEET_LSP_MAX_SESSIONS", &value)?;
+        }
+        if let Some(value) = var("SHEEET_LSP_IDLE_TIMEOUT_SECS") {
+            self.lsp_idle_timeout_secs = parse("SHEEET_LSP_IDLE_TIMEOUT_SECS", &value)?;
+        }
+        if let Some(value) = var("SHEEET_JANITOR_INTERVAL_SECS") {
+            self.janitor_interval_secs = parse("SHEEET_JANITOR_INTERVAL_SECS", &value)?;
+        }
+        if let Some(value) = var("SHEEET_WORKSPACE_PRUNE_AFTER_SECS") {
+            self.workspace_prune_after_secs =
+                Some(parse("SHEEET_WORKSPACE_PRUNE_AFTER_SECS", &value)?);
+        }
+        if let Some(value) = var("SHEEET_WORKSPACE_EVICT_AFTER_SECS") {
+            self.workspace_evict_after_secs =
+                Some(parse("SHEEET_WORKSPACE_EVICT_AFTER_SECS", &value)?);
+        }
+        if let Some(value) = var("SHEEET_WORKSPACES_DISK_BUDGET_BYTES") {
+            self.workspaces_disk_budget_bytes =
+                Some(parse("SHEEET_WORKSPACES_DISK_BUDGET_BYTES", &value)?);
+        }
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if let Err(err) = (self.bind_address.as_str(), self.port).to_socket_addrs() {
+            return Err(format!(
+                "invalid bind address '{}:{}': {err}",
+                self.bind_address, self.port
+            ));
+        }
+        if self.workers == 0 {
+            return Err("workers must be at least 1".into());
+        }
+        for origin in &self.allowed_origins {
+            let Some(host) = origin
+                .strip_prefix("http://")
+                .or(origin.strip_prefix("https://"))
+            else {
+                return Err(format!(
+                    "invalid allowed origin '{origin}', expected 'http://' or 'https://' scheme"
+                ));
+            };
+            if host.is_empty() || host.contains('/') {
+                return Err(format!(
+                    "invalid allowed origin '{origin}', expected just the scheme, host and port"
+                ));
+            }
+        }
+        match (&self.tls_cert_path, &self.tls_key_path) {
+            (Some(_), None) => return Err("TLS certificate set without the key".into()),
+            (None, Some(_)) => return Err("TLS key set without the certificate".into()),
+            _ => {}
+        }
+        if self.json_limit_bytes == 0 || self.git_payload_limit_bytes == 0 {
+            return Err("request body limits must be positive".into());
+        }
+        if !self.url_prefix.is_empty()
+            && (!self.url_prefix.starts_with('/') || self.url_prefix.ends_with('/'))
+        {
+            return Err(format!(
+                "invalid URL prefix '{}', expected e.g. '/api' or empty",
+                self.url_prefix
+            ));
+        }
+        if self
+            .proof_of_work_difficulty
+            .is_some_and(|difficulty| !(1..=32).contains(&difficulty))
+        {
+            return Err("proof of work difficulty must be between 1 and 32 bits".into());
+        }
+        self.dependency_policy()?;
+        if self.test_timeout_secs == 0 || self.janitor_interval_secs == 0 {
+            return Err("test timeout and janitor interval must be positive".into());
+        }
+        Ok(())
+    }
+
+    /// Private instances allow everything unless configured otherwise.
+    pub fn is_private(&self) -> bool {
+        self.secret_api_key.is_some()
+    }
+
+    pub fn dependency_policy(&self) -> Result<DependencyPolicy, String> {
+        let private = self.is_private();
+        Ok(DependencyPolicy {
+            allowlist: match &self.allowed_dependencies {
+                Some(value) => Some(policy::parse_allowlist(value)?),
+                None => None,
+            },
+            allow_build_scripts: self.allow_build_scripts.unwrap_or(private),
+            allow_proc_macros: self.allow_proc_macros.unwrap_or(private),
+            allow_git_dependencies: self.allow_git_dependencies.unwrap_or(private),
+            allow_path_dependencies: self.allow_path_dependencies.unwrap_or(private),
+        })
+    }
+
+    pub fn test_runner(&self) -> TestRunner {
+        match self.test_runner {
+            Some(test_runner) => test_runner,
+            None if self.is_private() => TestRunner::Native,
+            None => TestRunner::Wasmtime,
+        }
+    }
+
+    /// Loads the certificate chain and the key, if TLS is configured.
+    pub fn tls_config(&self) -> Result<Option<rustls::ServerConfig>, String> {
+        let (Some(cert_path), Some(key_path)) = (&self.tls_cert_path, &self.tls_key_path) else {
+            return Ok(None);
+        };
+        let certs = CertificateDer::pem_file_iter(cert_path)
+            .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+            .map_err(|err| format!("read TLS certificate {cert_path:?}: {err}"))?;
+        if certs.is_empty() {
+            return Err(format!("no certificates in {cert_path:?}"));
+        }
+        let key = PrivateKeyDer::from_pem_file(key_path)
+            .map_err(|err| format!("read TLS key {key_path:?}: {err}"))?;
+        rustls::ServerConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()
+        .and_then(|builder| builder.with_no_client_auth().with_single_cert(certs, key))
+        .map(Some)
+        .map_err(|err| format!("invalid TLS certificate or key: {err}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn with_env(config: &str, vars: &[(&str, &str)]) -> Result<ServerConfig, String> {
+        let mut config: ServerConfig = toml::from_str(config).map_err(|err| err.to_string())?;
+        let vars: HashMap<&str, &str> = vars.iter().copied().collect();
+        config.apply_env(|name| vars.get(name).map(|value| value.to_string()))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    #[test]
+    fn test_load() {
+        assert_eq!(with_env("", &[]).unwrap(), ServerConfig::default());
+
+        let config = with_env(
+            "port = 9000\nworkers = 2\nallowed_origins = [\"https://sheeet.example.com\"]",
+            &[("SHEEET_PORT", "9090"), ("SHEEET_URL_PREFIX", "")],
+        )
+        .unwrap();
+        assert_eq!(config.port, 9090);
+        assert_eq!(config.workers, 2);
+        assert_eq!(config.allowed_origins, ["https://sheeet.example.com"]);
+        assert_eq!(config.url_prefix, "");
+
+        let config = with_env(
+            "compile_rate_limit_per_ip = \"10/60\"",
+            &[
+                ("SHEEET_TRUST_PROXY_HEADERS", "1"),
+                ("SHEEET_PROOF_OF_WORK_DIFFICULTY", "16"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            config.compile_rate_limit_per_ip,
+            Some("10/60".parse().unwrap())
+        );
+        assert!(config.trust_proxy_headers);
+        assert_eq!(config.proof_of_work_difficulty, Some(16));
+
+        let config = with_env(
+            "test_runner = \"native\"\nallow_git_dependencies = true",
+            &[
+                (
+                    "SHEEET_ALLOWED_DEPENDENCIES",
+                    "wasm-bindgen@0.2, sheeet-funcs",
+                ),
+                ("SHEEET_ALLOW_PROC_MACROS", "1"),
+                ("SHEEET_LSP_MAX_SESSIONS", "0"),
+                ("SHEEET_WORKSPACE_EVICT_AFTER_SECS", "7776000"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(config.test_runner(), TestRunner::Native);
+        let policy = config.dependency_policy().unwrap();
+        assert_eq!(policy.allowlist.map(|allowlist| allowlist.len()), Some(2));
+        assert!(!policy.allow_build_scripts);
+        assert!(policy.allow_proc_macros);
+        assert!(policy.allow_git_dependencies);
+        assert_eq!(config.lsp_max_sessions, 0);
+        assert_eq!(config.workspace_evict_after_secs, Some(7776000));
+
+        // Private instances allow everything and run the tests natively by default.
+        let config = with_env("", &[("SHEEET_SECRET_API_KEY", "secret")]).unwrap();
+        assert!(config.dependency_policy().unwrap().is_permissive());
+        assert_eq!(config.test_runner(), TestRunner::Native);
+        assert_eq!(ServerConfig::default().test_runner(), TestRunner::Wasmtime);
+
+        for (file, vars) in [
+            ("prt = 9000", vec![]),
+            ("", vec![("SHEEET_PORT", "http")]),
+            ("workers = 0", vec![]),
+            ("allowed_origins = [\"sheeet.example.com\"]", vec![]),
+            (
+                "",
+                vec![("SHEEET_ALLOWED_ORIGINS", "https://sheeet.example.com/app")],
+            ),
+            ("tls_cert_path = \"/etc/cert.pem\"", vec![]),
+            ("url_prefix = \"api/\"", vec![]),
+            ("compile_rate_limit_per_ip = \"10\"", vec![]),
+            ("", vec![("SHEEET_TRUST_PROXY_HEADERS", "yes")]),
+            ("proof_of_work_difficulty = 64", vec![]),
+            ("", vec![("SHEEET_ALLOWED_DEPENDENCIES", "serde@latest")]),
+            ("", vec![("SHEEET_ALLOW_BUILD_SCRIPTS", "yes")]),
+            ("test_runner = \"docker\"", vec![]),
+            ("", vec![("SHEEET_TEST_RUNNER", "docker")]),
+            ("", vec![("SHEEET_TEST_TIMEOUT_SECS", "-1")]),
+            ("janitor_interval_secs = 0", vec![]),
+        ] {
+            with_env(file, &vars).expect_err(file);
+        }
+    }
+}
@@ -0,0 +1,191 @@
+use crate::policy;
+use log::{info, warn};
+use semver::{Version, VersionReq};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::{fs, io};
+
+/// Server managed cargo configuration inside the workspace, rewritten before every build.
+const CARGO_CONFIG: &str = ".cargo/config.toml";
+/// Crate the mirror has to contain, otherwise not even the default user crate builds.
+const REQUIRED_CRATE: &str = "sheeet-funcs";
+
+/// Local directory registry (as produced by `cargo vendor`) replacing crates.io, so the user
+/// crates build without network access.
+#[derive(Clone, Debug)]
+pub struct CrateMirror {
+    path: PathBuf,
+}
+
+impl CrateMirror {
+    pub fn new(path: PathBuf) -> Self {
+        CrateMirror { path }
+    }
+
+    /// Mirrored crate names with their available versions.
+    fn crates(&self) -> io::Result<HashMap<String, Vec<Version>>> {
+        let mut crates: HashMap<String, Vec<Version>> = HashMap::new();
+        for entry in fs::read_dir(&self.path)? {
+            let Ok(content) = fs::read_to_string(entry?.path().join("Cargo.toml")) else {
+                continue;
+            };
+            let Ok(manifest) = toml::from_str::<toml::Table>(&content) else {
+                continue;
+            };
+            let package = manifest.get("package");
+            let name = package.and_then(|package| package.get("name")?.as_str());
+            let version = package
+                .and_then(|package| package.get("version")?.as_str())
+                .and_then(|version| Version::parse(version).ok());
+            if let (Some(name), Some(version)) = (name, version) {
+                crates.entry(name.into()).or_default().push(version);
+            }
+        }
+        Ok(crates)
+    }
+
+    /// Checks all registry dependencies of the submitted Cargo.toml are mirrored. Missing
+    /// transitive dependencies are reported by cargo itself.
+    pub fn check_manifest(&self, cargo_toml: &str) -> Result<(), String> {
+        let manifest: toml::Table =
+            toml::from_str(cargo_toml).map_err(|err| format!("invalid Cargo.toml: {err}"))?;
+        let crates = self
+            .crates()
+            .map_err(|err| format!("read crate mirror {:?}: {err}", self.path))?;
+
+        for (key, value) in policy::dependency_tables(&manifest).into_iter().flatten() {
+            if value.get("git").is_some() || value.get("path").is_some() {
+                continue;
+            }
+            let name = value
+                .get("package")
+                .and_then(toml::Value::as_str)
+                .unwrap_or(key);
+            let requirement = match value {
+                toml::Value::String(requirement) => requirement.as_str(),
+                _ => value
+                    .get("version")
+                    .and_then(toml::Value::as_str)
+                    .unwrap_or("*"),
+            };
+            let requirement = VersionReq::parse(requirement).map_err(|err| {
+                format!("invalid version requirement '{requirement}' of crate '{name}': {err}")
+            })?;
+            let Some(versions) = crates.get(name) else {
+                return Err(format!(
+                    "crate '{name}' is not available in the offline crate mirror"
+                ));
+            };
+            if !versions.iter().any(|version| requirement.matches(version)) {
+                let available: Vec<String> = versions.iter().map(Version::to_string).collect();
+                return Err(format!(
+                    "crate '{name}' has no version matching '{requirement}' in the offline crate mirror, available: {}",
+                    available.join(", ")
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn cargo_config(&self) -> String {
+        let directory = toml::Value::String(self.path.to_string_lossy().into());
+        format!(
+            "[source.crates-io]\nreplace-with = \"sheeet-mirror\"\n\n[source.sheeet-mirror]\ndirectory = {directory}\n\n[net]\noffline = true\n"
+        )
+    }
+
+    /// Vendors the template workspace dependencies into the mirror, if it doesn't contain
+    /// `sheeet-funcs` yet. Needs network access, deployments without it ship the mirror prepopulated.
+    pub fn populate(&self, template_path: &Path) {
+        match self.crates() {
+            Ok(crates) if crates.contains_key(REQUIRED_CRATE) => return,
+            Ok(_) => {}
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => {
+                warn!("failed to read crate mirror {:?}: {err}", self.path);
+                return;
+            }
+        }
+
+        info!("populating crate mirror {:?}", self.path);
+        // Vendoring must resolve from crates.io, not from the (incomplete) mirror itself.
+        if let Err(err) = configure_workspace(template_path, None) {
+            warn!("failed to configure template workspace: {err}");
+            return;
+        }
+        match Command::new("cargo")
+            .args(["vendor", "--versioned-dirs", "--no-delete"])
+            .arg(&self.path)
+            .current_dir(template_path)
+            .output()
+        {
+            Ok(output) if output.status.success() => {
+                info!("crate mirror {:?} populated", self.path);
+            }
+            Ok(output) => {
+                warn!(
+                    "failed to populate crate mirror {:?}: {}\n{}",
+                    self.path,
+                    output.status,
+                    String::from_utf8_lossy(&output.stderr)
+                );
+            }
+            Err(err) => {
+                warn!("failed to populate crate mirror {:?}: {err}", self.path);
+            }
+        }
+    }
+}
+
+/// Writes the workspace cargo configuration, pointing crates.io to the mirror. Without the mirror
+/// the configuration is removed, it is managed only by the server.
+pub fn configure_workspace(workspace_path: &Path, mirror: Option<&CrateMirror>) -> io::Result<()> {
+    let config_path = workspace_path.join(CARGO_CONFIG);
+    match mirror {
+        Some(mirror) => {
+            fs::create_dir_all(workspace_path.join(".cargo"))?;
+            fs::write(config_path, mirror.cargo_config())
+        }
+        None => match fs::remove_file(config_path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_manifest() {
+        let path =
+            std::env::temp_dir().join(format!("sheeet-mirror-test-{}", rand::random::<u32>()));
+        for (name, version) in [("wasm-bindgen", "0.2.100"), ("sheeet-funcs", "0.1.4")] {
+            let crate_path = path.join(format!("{name}-{version}"));
+            fs::create_dir_all(&crate_path).unwrap();
+            fs::write(
+                crate_path.join("Cargo.toml"),
+                format!("[package]\nname = \"{name}\"\nversion = \"{version}\"\n"),
+            )
+            .unwrap();
+        }
+        let mirror = CrateMirror::new(path.clone());
+        let cargo_toml = include_str!("user_cargo.toml");
+        assert_eq!(mirror.check_manifest(cargo_toml), Ok(()));
+
+        let err = mirror
+            .check_manifest(&format!("{cargo_toml}serde = \"1\"\n"))
+            .expect_err("expected missing crate");
+        assert!(err.contains("'serde' is not available"), "{err}");
+        let err = mirror
+            .check_manifest(&cargo_toml.replace("0.2.100", "0.3"))
+            .expect_err("expected missing version");
+        assert!(err.contains("available: 0.2.100"), "{err}");
+        let local = format!("{cargo_toml}local = {{ path = \"../local\" }}\n");
+        assert_eq!(mirror.check_manifest(&local), Ok(()));
+
+        fs::remove_dir_all(&path).unwrap();
+    }
+}
@@ -0,0 +1,377 @@
+use serde::{Deserialize, Serialize};
+
+/// Function exported by the user crate, as described by the wasm-bindgen generated JS glue.
+/// Read from the compiled glue, so the functions of the dependencies (e.g. the `sheeet-funcs`
+/// prelude) and the `js_name` renames are included.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Function {
+    /// Name the sheet calls the function by.
+    pub name: String,
+    pub params: Vec<Param>,
+    /// JS type of the returned value, `None` if the function returns nothing.
+    pub return_type: Option<String>,
+    /// Returns a promise, e.g. it's an `async fn`.
+    pub is_async: bool,
+    /// Doc comment of the Rust function.
+    pub doc: String,
+    /// Recalculated on every sheet change, declared by `#[sheeet::function(volatile)]`.
+    #[serde(default)]
+    pub volatile: bool,
+    /// The last parameter collects all the remaining arguments, declared by
+    /// `#[sheeet::function(variadic)]`.
+    #[serde(default)]
+    pub variadic: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Param {
+    pub name: String,
+    /// JS type of the parameter, e.g. `number` or `Float32Array` for `Vec<f32>`.
+    pub ty: String,
+    /// Can be left out, e.g. the `Option` parameters.
+    pub optional: bool,
+}
+
+const EXPORT_PREFIX: &str = "export function ";
+/// Exports of the wasm-bindgen runtime and the `sheeet-funcs` internals, not sheet functions.
+const INTERNAL_PREFIXES: [&str; 2] = ["__wbg", "__sheeet"];
+/// Tags the `#[sheeet::function]` wrappers add to their doc comments.
+const VOLATILE_TAG: &str = "@volatile";
+const VARIADIC_TAG: &str = "@variadic";
+const UNKNOWN_TYPE: &str = "any";
+
+#[derive(Default)]
+struct JsDoc {
+    doc: Vec<String>,
+    /// Parameter name, type and whether it's optional.
+    params: Vec<(String, String, bool)>,
+    returns: Option<String>,
+    volatile: bool,
+    variadic: bool,
+}
+
+impl JsDoc {
+    fn line(&mut self, line: &str) {
+        if let Some(param) = line.strip_prefix("@param ") {
+            let (ty, name) = split_type(param);
+            let (name, optional) = match name.strip_prefix('[') {
+                Some(name) => (name.trim_end_matches(']'), true),
+                None => (name, false),
+            };
+            // Defaults are written as `[name=value]`.
+            let name = name.split('=').next().unwrap_or_default();
+            // The `#[sheeet::function]` wrappers declare the optional params' types.
+            let optional = optional || ty.ends_with("| undefined");
+            self.params.push((name.into(), ty.into(), optional));
+        } else if let Some(returns) = line.strip_prefix("@returns ") {
+            self.returns = Some(split_type(returns).0.into());
+        } else if line == VOLATILE_TAG {
+            self.volatile = true;
+        } else if line == VARIADIC_TAG {
+            self.variadic = true;
+        } else if !line.starts_with('@') {
+            self.doc.push(line.into());
+        }
+    }
+}
+
+/// Splits the `{type} rest` of the JSDoc tag.
+fn split_type(tag: &str) -> (&str, &str) {
+    match tag
+        .strip_prefix('{')
+        .and_then(|tag| tag.split_once("} "))
+        .or_else(|| tag.strip_prefix('{')?.strip_suffix('}').map(|ty| (ty, "")))
+    {
+        Some((ty, rest)) => (ty.trim(), rest.trim()),
+        None => (UNKNOWN_TYPE, tag.trim()),
+    }
+}
+
+/// Reads the exported functions from the wasm-bindgen generated JS glue, in the order
+/// they are declared there.
+pub fn parse_manifest(js: &str) -> Vec<Function> {
+    let mut functions = Vec::new();
+    let mut js_doc: Option<JsDoc> = None;
+    let mut in_js_doc = false;
+    for line in js.lines().map(str::trim) {
+        if in_js_doc {
+            if line.starts_with("*/") {
+                in_js_doc = false;
+            } else if let Some(js_doc) = &mut js_doc {
+                js_doc.line(line.trim_start_matches('*').trim());
+            }
+            continue;
+        }
+        if line.starts_with("/**") {
+            in_js_doc = !line.ends_with("*/");
+            js_doc = Some(JsDoc::default());
+            continue;
+        }
+        if let Some(function) = line
+            .strip_prefix(EXPORT_PREFIX)
+            .and_then(|signature| parse_function(signature, js_doc.take().unwrap_or_default()))
+        {
+            functions.push(function);
+        }
+        // The doc comment belongs to the declaration right after it.
+        js_doc = None;
+    }
+    functions
+}
+
+fn parse_function(signature: &str, js_doc: JsDoc) -> Option<Function> {
+    let (name, rest) = signature.split_once('(')?;
+    let (params, _) = rest.split_once(')')?;
+    let name = name.trim();
+    if INTERNAL_PREFIXES
+        .iter()
+        .any(|prefix| name.starts_with(prefix))
+    {
+        return None;
+    }
+    let params = params
+        .split(',')
+        .map(str::trim)
+        .filter(|param| !param.is_empty())
+        .map(|param| {
+            let documented = js_doc.params.iter().find(|(name, ..)| name == param);
+            Param {
+                name: param.into(),
+                ty: documented.map_or(UNKNOWN_TYPE.into(), |(_, ty, _)| ty.clone()),
+                optional: documented.is_some_and(|(.., optional)| *optional),
+            }
+        })
+        .collect();
+    let is_async = js_doc
+        .returns
+        .as_ref()
+        .is_some_and(|returns| returns.starts_with("Promise"));
+    Some(Function {
+        name: name.into(),
+        params,
+        return_type: js_doc.returns,
+        is_async,
+        doc: js_doc.doc.join("\n").trim().into(),
+        volatile: js_doc.volatile,
+        variadic: js_doc.variadic,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_manifest() {
+        let js = r#"
+let wasm;
+
+function passArrayF32ToWasm0(arg, malloc) {
+    const ptr = malloc(arg.length * 4, 4) >>> 0;
+    return ptr;
+}
+/**
+ * @param {number} a
+ * @param {number} b
+ * @returns {number}
+ */
+export function add(a, b) {
+    const ret = wasm.add(a, b);
+    return ret;
+}
+
+/**
+ * Discrete implementation of median.
+ * @param {Float32Array} vec
+ * @returns {number}
+ */
+export function med(vec) {
+    const ptr0 = passArrayF32ToWasm0(vec, wasm.__wbindgen_malloc);
+    const len0 = WASM_VECTOR_LEN;
+    const ret = wasm.med(ptr0, len0);
+    return ret;
+}
+
+/**
+ * Fetches the JSON and reads the value at the path.
+ *
+ * Uses the browser's fetch.
+ * @param {string} url
+ * @param {string | null} [path]
+ * @returns {Promise<any>}
+ */
+export function fetchJson(url, path) {
+    return ret;
+}
+
+export function ping() {
+    wasm.ping();
+}
+
+/**
+ * Joins the texts with the separator.
+ * @variadic
+ * @param {string} sep
+ * @param {string[]} texts
+ * @returns {string}
+ */
+export function join(sep, texts) {
+    const ret = wasm.join(sep, texts);
+    return ret;
+}
+
+/**
+ * Random number between 0 and 1.
+ * @volatile
+ * @returns {number}
+ */
+export function random() {
+    const ret = wasm.random();
+    return ret;
+}
+
+/**
+ * @param {number} a
+ * @param {number | undefined} digits
+ * @returns {number}
+ */
+export function round_to(a, digits) {
+    const ret = wasm.round_to(a, digits);
+    return ret;
+}
+
+/**
+ * @returns {string | undefined}
+ */
+export function __sheeet_take_panic() {
+    const ret = wasm.__sheeet_take_panic();
+    return ret;
+}
+
+function __wbg_get_imports() {
+    const imports = {};
+    imports.wbg = {};
+    return imports;
+}
+
+export { initSync };
+export default __wbg_init;
+"#;
+        assert_eq!(
+            parse_manifest(js),
+            [
+                Function {
+                    name: "add".into(),
+                    params: vec![
+                        Param {
+                            name: "a".into(),
+                            ty: "number".into(),
+                            optional: false,
+                        },
+                        Param {
+                            name: "b".into(),
+                            ty: "number".into(),
+                            optional: false,
+                        },
+                    ],
+                    return_type: Some("number".into()),
+                    is_async: false,
+                    doc: String::new(),
+                    volatile: false,
+                    variadic: false,
+                },
+                Function {
+                    name: "med".into(),
+                    params: vec![Param {
+                        name: "vec".into(),
+                        ty: "Float32Array".into(),
+                        optional: false,
+                    }],
+                    return_type: Some("number".into()),
+                    is_async: false,
+                    doc: "Discrete implementation of median.".into(),
+                    volatile: false,
+                    variadic: false,
+                },
+                Function {
+                    name: "fetchJson".into(),
+                    params: vec![
+                        Param {
+                            name: "url".into(),
+                            ty: "string".into(),
+                            optional: false,
+                        },
+                        Param {
+                            name: "path".into(),
+                            ty: "string | null".into(),
+                            optional: true,
+                        },
+                    ],
+                    return_type: Some("Promise<any>".into()),
+                    is_async: true,
+                    doc: "Fetches the JSON and reads the value at the path.\n\nUses the browser's fetch.".into(),
+                    volatile: false,
+                    variadic: false,
+                },
+                Function {
+                    name: "ping".into(),
+                    params: Vec::new(),
+                    return_type: None,
+                    is_async: false,
+                    doc: String::new(),
+                    volatile: false,
+                    variadic: false,
+                },
+                Function {
+                    name: "join".into(),
+                    params: vec![
+                        Param {
+                            name: "sep".into(),
+                            ty: "string".into(),
+                            optional: false,
+                        },
+                        Param {
+                            name: "texts".into(),
+                            ty: "string[]".into(),
+                            optional: false,
+                        },
+                    ],
+                    return_type: Some("string".into()),
+                    is_async: false,
+                    doc: "Joins the texts with the separator.".into(),
+                    volatile: false,
+                    variadic: true,
+                },
+                Function {
+                    name: "random".into(),
+                    params: Vec::new(),
+                    return_type: Some("number".into()),
+                    is_async: false,
+                    doc: "Random number between 0 and 1.".into(),
+                    volatile: true,
+                    variadic: false,
+                },
+                Function {
+                    name: "round_to".into(),
+                    params: vec![
+                        Param {
+                            name: "a".into(),
+                            ty: "number".into(),
+                            optional: false,
+                        },
+                        Param {
+                            name: "digits".into(),
+                            ty: "number | undefined".into(),
+                            optional: true,
+                        },
+                    ],
+                    return_type: Some("number".into()),
+                    is_async: false,
+                    doc: String::new(),
+                    volatile: false,
+                    variadic: false,
+                },
+            ]
+        );
+    }
+}
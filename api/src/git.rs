@@ -0,0 +1,555 @@
+use crate::source::Source;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::{fs, thread};
+
+/// Branch whose pushes are compiled.
+const MAIN_BRANCH: &str = "refs/heads/main";
+/// Object name of a missing ref in the push commands.
+const ZERO_ID: &str = "0000000000000000000000000000000000000000";
+
+/// Build outputs and server managed files, that don't belong to the user's repository.
+const GITIGNORE: &str =
+    "/target\n/dist\n/builds\n/.revisions\n/.cargo\n/.access.json\n/.last-used\n/index.html\n";
+
+fn git(workspace_path: &Path) -> Command {
+    let mut command = Command::new("git");
+    command
+        .current_dir(workspace_path)
+        .arg("-c")
+        .arg("user.name=Sheeet!")
+        .arg("-c")
+        .arg("user.email=sheeet@localhost");
+    command
+}
+
+fn run(command: &mut Command) -> io::Result<()> {
+    let output = command.output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "{:?} failed: {}\n{}",
+            command,
+            output.status,
+            String::from_utf8_lossy(&output.stderr)
+        )));
+    }
+    Ok(())
+}
+
+/// Initializes the workspace git repository, if it isn't already.
+///
+/// Pushes only update the refs (`receive.denyCurrentBranch=ignore`), never the checked out
+/// working tree. The pushed code gets into the workspace as the compiled source, checked as
+/// the submitted one, see [`receive_pack`].
+pub fn ensure_repository(workspace_path: &Path) -> io::Result<()> {
+    if fs::exists(workspace_path.join(".git"))? {
+        return Ok(());
+    }
+    fs::write(workspace_path.join(".gitignore"), GITIGNORE)?;
+    run(git(workspace_path).args(["init", "--initial-branch=main"]))?;
+    run(git(workspace_path).args(["config", "receive.denyCurrentBranch", "ignore"]))?;
+    run(git(workspace_path).args(["config", "http.receivepack", "true"]))?;
+    Ok(())
+}
+
+/// Commits all workspace changes, does nothing if there are none.
+pub fn commit_all(workspace_path: &Path, message: &str) -> io::Result<()> {
+    ensure_repository(workspace_path)?;
+    // Repositories created before some of the server files existed don't ignore them.
+    fs::write(workspace_path.join(".git/info/exclude"), GITIGNORE)?;
+    run(git(workspace_path).args(["add", "--all"]))?;
+    let staged = git(workspace_path)
+        .args(["diff", "--cached", "--quiet"])
+        .status()?;
+    if staged.success() {
+        return Ok(());
+    }
+    run(git(workspace_path).args(["commit", "--quiet", "--message", message]))
+}
+
+/// CGI request for the `git http-backend`.
+pub struct HttpBackendRequest<'a> {
+    pub method: &'a str,
+    /// Path inside the workspaces root, e.g. `/abcdefghijkl/info/refs`.
+    pub path_info: &'a str,
+    pub query_string: &'a str,
+    pub content_type: Option<&'a str>,
+    pub content_encoding: Option<&'a str>,
+    pub git_protocol: Option<&'a str>,
+    pub body: &'a [u8],
+}
+
+#[derive(Debug)]
+pub struct HttpBackendResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+/// Serves git smart HTTP protocol for the workspace repositories via `git http-backend`.
+pub fn http_backend(
+    workspaces_path: &Path,
+    request: HttpBackendRequest,
+) -> io::Result<HttpBackendResponse> {
+    let mut envs = HashMap::from([
+        (
+            "GIT_PROJECT_ROOT",
+            workspaces_path.to_string_lossy().to_string(),
+        ),
+        // Overrides the repositories created when pushes updated the working tree.
+        ("GIT_CONFIG_COUNT", "1".into()),
+        ("GIT_CONFIG_KEY_0", "receive.denyCurrentBranch".into()),
+        ("GIT_CONFIG_VALUE_0", "ignore".into()),
+        ("GIT_HTTP_EXPORT_ALL", "1".into()),
+        ("REQUEST_METHOD", request.method.into()),
+        ("PATH_INFO", request.path_info.into()),
+        ("QUERY_STRING", request.query_string.into()),
+        ("CONTENT_LENGTH", request.body.len().to_string()),
+    ]);
+    if let Some(content_type) = request.content_type {
+        envs.insert("CONTENT_TYPE", content_type.into());
+    }
+    if let Some(content_encoding) = request.content_encoding {
+        envs.insert("HTTP_CONTENT_ENCODING", content_encoding.into());
+    }
+    if let Some(git_protocol) = request.git_protocol {
+        envs.insert("GIT_PROTOCOL", git_protocol.into());
+    }
+
+    let mut child = Command::new("git")
+        .arg("http-backend")
+        .envs(envs)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // Write the body in a separate thread, the backend could start responding before reading it all.
+    let mut stdin = child.stdin.take().expect("expected piped stdin");
+    let body = request.body.to_vec();
+    let writer = thread::spawn(move || stdin.write_all(&body));
+
+    let mut stdout = Vec::new();
+    child
+        .stdout
+        .take()
+        .expect("expected piped stdout")
+        .read_to_end(&mut stdout)?;
+    let output = child.wait_with_output()?;
+    writer
+        .join()
+        .map_err(|_| io::Error::other("git http-backend stdin writer panicked"))??;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "git http-backend failed: {}\n{}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr)
+        )));
+    }
+
+    parse_cgi_response(&stdout)
+}
+
+/// Ref update of the push, e.g. of `refs/heads/main` from the `old` to the `new` commit.
+#[derive(Debug, PartialEq)]
+struct RefUpdate {
+    old: String,
+    new: String,
+    name: String,
+}
+
+/// Serves the push like [`http_backend`], then checks the source of every pushed commit with
+/// `check`. A push with a rejected commit has all its refs restored and is reported to the client
+/// as rejected. Returns the response and the source pushed to the main branch.
+pub fn receive_pack(
+    workspaces_path: &Path,
+    workspace_path: &Path,
+    request: HttpBackendRequest,
+    check: impl Fn(&Source) -> Result<(), String>,
+) -> io::Result<(HttpBackendResponse, Option<Source>)> {
+    // Compressed requests would need decompressing, git doesn't compress pushes.
+    let parsed = match request.content_encoding {
+        None => parse_push_commands(request.body),
+        Some(_) => None,
+    };
+    let Some((updates, capabilities)) = parsed else {
+        return Ok((text_response(400, "Unsupported push request"), None));
+    };
+
+    let response = http_backend(workspaces_path, request)?;
+    if response.status != 200 {
+        return Ok((response, None));
+    }
+
+    let mut main_source = None;
+    for update in updates.iter().filter(|update| update.new != ZERO_ID) {
+        let checked = tree_source(workspace_path, &update.new).and_then(|source| {
+            check(&source)?;
+            Ok(source)
+        });
+        match checked {
+            Ok(source) if update.name == MAIN_BRANCH => main_source = Some(source),
+            Ok(_) => {}
+            Err(reason) => {
+                for update in &updates {
+                    restore_ref(workspace_path, update)?;
+                }
+                return Ok((rejected_push(&updates, &capabilities, &reason), None));
+            }
+        }
+    }
+    Ok((response, main_source))
+}
+
+/// Parses the ref updates and the client capabilities, the pkt-lines preceding the pack.
+fn parse_push_commands(body: &[u8]) -> Option<(Vec<RefUpdate>, Vec<String>)> {
+    let mut updates = Vec::new();
+    let mut capabilities = Vec::new();
+    let mut rest = body;
+    loop {
+        let length = usize::from_str_radix(std::str::from_utf8(rest.get(..4)?).ok()?, 16).ok()?;
+        if length == 0 {
+            return Some((updates, capabilities));
+        }
+        let line = std::str::from_utf8(rest.get(4..length)?).ok()?;
+        rest = &rest[length..];
+        let line = line.trim_end_matches('\n');
+        let line = match line.split_once('\0') {
+            Some((line, caps)) => {
+                capabilities.extend(caps.split(' ').map(String::from));
+                line
+            }
+            None => line,
+        };
+        if line.starts_with("shallow ") {
+            continue;
+        }
+        // Also the push certificates, they aren't advertised.
+        let mut parts = line.splitn(3, ' ');
+        let (Some(old), Some(new), Some(name)) = (parts.next(), parts.next(), parts.next()) else {
+            return None;
+        };
+        updates.push(RefUpdate {
+            old: old.into(),
+            new: new.into(),
+            name: name.into(),
+        });
+    }
+}
+
+/// Reads the user crate source of the commit. Symlinks and submodules are rejected, so
+/// nothing of the commit points outside of it.
+fn tree_source(workspace_path: &Path, commit: &str) -> Result<Source, String> {
+    let output = git(workspace_path)
+        .args(["ls-tree", "-r", "-z", "--full-tree", commit])
+        .output()
+        .map_err(|err| format!("list pushed files: {err}"))?;
+    if !output.status.success() {
+        return Err(format!("list pushed files: {}", output.status));
+    }
+    let mut entries = Vec::new();
+    for entry in output
+        .stdout
+        .split(|byte| *byte == 0)
+        .filter(|entry| !entry.is_empty())
+    {
+        let entry = String::from_utf8_lossy(entry);
+        let Some((meta, path)) = entry.split_once('\t') else {
+            return Err(format!("invalid tree entry '{entry}'"));
+        };
+        // Server managed, recreated on every commit.
+        if path == ".gitignore" {
+            continue;
+        }
+        match meta.split(' ').collect::<Vec<_>>().as_slice() {
+            ["120000", _, _] => return Err(format!("symlink '{path}' not allowed")),
+            [_, "blob", id] => entries.push((path.to_string(), id.to_string())),
+            _ => return Err(format!("'{path}' not allowed, only files")),
+        }
+    }
+
+    let mut files = BTreeMap::new();
+    let contents = read_blobs(workspace_path, entries.iter().map(|(_, id)| id.as_str()))
+        .map_err(|err| format!("read pushed files: {err}"))?;
+    for ((path, _), content) in entries.into_iter().zip(contents) {
+        let content =
+            String::from_utf8(content).map_err(|_| format!("file '{path}' is not UTF-8"))?;
+        files.insert(path, content);
+    }
+    Ok(Source { files })
+}
+
+/// Reads the blobs with a single `git cat-file --batch`.
+fn read_blobs<'a>(
+    workspace_path: &Path,
+    ids: impl Iterator<Item = &'a str>,
+) -> io::Result<Vec<Vec<u8>>> {
+    let mut child = git(workspace_path)
+        .args(["cat-file", "--batch"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+    let mut stdin = child.stdin.take().expect("expected piped stdin");
+    let input: String = ids.map(|id| format!("{id}\n")).collect();
+    let writer = thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let output = child.wait_with_output()?;
+    writer
+        .join()
+        .map_err(|_| io::Error::other("git cat-file stdin writer panicked"))??;
+
+    // Every blob is `<id> blob <size>\n<content>\n`.
+    let mut blobs = Vec::new();
+    let mut rest = output.stdout.as_slice();
+    while let Some(header_end) = rest.iter().position(|byte| *byte == b'\n') {
+        let header = String::from_utf8_lossy(&rest[..header_end]);
+        let size: usize = match header.split(' ').collect::<Vec<_>>().as_slice() {
+            [_, "blob", size] => size.parse().map_err(io::Error::other)?,
+            _ => return Err(io::Error::other(format!("unexpected object '{header}'"))),
+        };
+        let content = rest
+            .get(header_end + 1..header_end + 1 + size)
+            .ok_or_else(|| io::Error::other("truncated git cat-file output"))?;
+        blobs.push(content.to_vec());
+        rest = rest.get(header_end + 2 + size..).unwrap_or_default();
+    }
+    Ok(blobs)
+}
+
+/// Sets the ref back to its value before the push.
+fn restore_ref(workspace_path: &Path, update: &RefUpdate) -> io::Result<()> {
+    match update.old.as_str() {
+        ZERO_ID => run(git(workspace_path).args(["update-ref", "-d", &update.name])),
+        old => run(git(workspace_path).args(["update-ref", &update.name, old])),
+    }
+}
+
+fn pkt_line(data: &[u8]) -> Vec<u8> {
+    let mut line = format!("{:04x}", data.len() + 4).into_bytes();
+    line.extend_from_slice(data);
+    line
+}
+
+fn text_response(status: u16, body: &str) -> HttpBackendResponse {
+    HttpBackendResponse {
+        status,
+        headers: vec![("Content-Type".into(), "text/plain".into())],
+        body: body.as_bytes().to_vec(),
+    }
+}
+
+/// Reports all the ref updates as rejected, git shows the reason next to the refs.
+fn rejected_push(
+    updates: &[RefUpdate],
+    capabilities: &[String],
+    reason: &str,
+) -> HttpBackendResponse {
+    let has = |capability: &str| capabilities.iter().any(|c| c == capability);
+    let reason = reason.replace('\n', " ");
+    if !has("report-status") && !has("report-status-v2") {
+        return text_response(403, &format!("Push rejected: {reason}"));
+    }
+    let mut report = pkt_line(b"unpack ok\n");
+    for update in updates {
+        report.extend(pkt_line(
+            format!("ng {} {reason}\n", update.name).as_bytes(),
+        ));
+    }
+    report.extend_from_slice(b"0000");
+
+    let body = match has("side-band-64k") || has("side-band") {
+        // The report goes in the band 1, the plain side-band allows 1000 bytes long packets.
+        true => {
+            let mut body = Vec::new();
+            for chunk in report.chunks(990) {
+                body.extend(pkt_line(&[&[1], chunk].concat()));
+            }
+            body.extend_from_slice(b"0000");
+            body
+        }
+        false => report,
+    };
+    HttpBackendResponse {
+        status: 200,
+        headers: vec![
+            (
+                "Content-Type".into(),
+                "application/x-git-receive-pack-result".into(),
+            ),
+            ("Cache-Control".into(), "no-cache".into()),
+        ],
+        body,
+    }
+}
+
+fn parse_cgi_response(output: &[u8]) -> io::Result<HttpBackendResponse> {
+    let (head, body) = match output.windows(4).position(|window| window == b"\r\n\r\n") {
+        Some(position) => (&output[..position], &output[position + 4..]),
+        None => match output.windows(2).position(|window| window == b"\n\n") {
+            Some(position) => (&output[..position], &output[position + 2..]),
+            None => return Err(io::Error::other("invalid CGI response, missing headers")),
+        },
+    };
+
+    let mut response = HttpBackendResponse {
+        status: 200,
+        headers: Vec::new(),
+        body: body.to_vec(),
+    };
+    for line in String::from_utf8_lossy(head).lines() {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("Status") {
+            response.status = value
+                .split_whitespace()
+                .next()
+                .and_then(|status| status.parse().ok())
+                .ok_or_else(|| io::Error::other(format!("invalid CGI status '{value}'")))?;
+        } else {
+            response.headers.push((name.into(), value.into()));
+        }
+    }
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cgi_response() {
+        let response = parse_cgi_response(
+            b"Status: 404 Not Found\r\nContent-Type: text/plain\r\n\r\nRepository not found",
+        )
+        .unwrap();
+        assert_eq!(response.status, 404);
+        assert_eq!(
+            response.headers,
+            vec![("Content-Type".to_string(), "text/plain".to_string())]
+        );
+        assert_eq!(response.body, b"Repository not found");
+
+        let response = parse_cgi_response(b"Content-Type: x\n\nbody").unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, b"body");
+
+        parse_cgi_response(b"no headers").expect_err("expected invalid response");
+    }
+
+    fn git_output(path: &Path, args: &[&str]) -> String {
+        let output = git(path).args(args).output().unwrap();
+        assert!(output.status.success(), "{args:?}");
+        String::from_utf8(output.stdout).unwrap().trim().into()
+    }
+
+    /// Commits the client's changes and pushes them to the workspace the way the git HTTP
+    /// client does, returns the response and the pushed main branch source.
+    fn push(
+        workspaces_path: &Path,
+        client_path: &Path,
+        check: impl Fn(&Source) -> Result<(), String>,
+    ) -> (HttpBackendResponse, Option<Source>) {
+        let workspace_path = workspaces_path.join("abcdefghijkl");
+        let old = git_output(&workspace_path, &["rev-parse", MAIN_BRANCH]);
+        git_output(client_path, &["add", "--all"]);
+        git_output(client_path, &["commit", "--quiet", "--message", "Change"]);
+        let new = git_output(client_path, &["rev-parse", "HEAD"]);
+
+        let mut pack_objects = git(client_path)
+            .args(["pack-objects", "--revs", "--stdout", "--quiet"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        write!(pack_objects.stdin.take().unwrap(), "{new}\n^{old}\n").unwrap();
+        let pack = pack_objects.wait_with_output().unwrap().stdout;
+        let mut body = pkt_line(
+            format!("{old} {new} {MAIN_BRANCH}\0report-status side-band-64k\n").as_bytes(),
+        );
+        body.extend_from_slice(b"0000");
+        body.extend(pack);
+
+        let request = HttpBackendRequest {
+            method: "POST",
+            path_info: "/abcdefghijkl/git-receive-pack",
+            query_string: "",
+            content_type: Some("application/x-git-receive-pack-request"),
+            content_encoding: None,
+            git_protocol: None,
+            body: &body,
+        };
+        receive_pack(workspaces_path, &workspace_path, request, check).unwrap()
+    }
+
+    #[test]
+    fn test_receive_pack() {
+        let workspaces_path =
+            std::env::temp_dir().join(format!("sheeet-git-test-{}", rand::random::<u32>()));
+        let workspace_path = workspaces_path.join("abcdefghijkl");
+        fs::create_dir_all(workspace_path.join("src")).unwrap();
+        fs::write(workspace_path.join("Cargo.toml"), "[package]").unwrap();
+        fs::write(workspace_path.join("src/lib.rs"), "// lib").unwrap();
+        commit_all(&workspace_path, "Compile revision 1").unwrap();
+        let pushed = git_output(&workspace_path, &["rev-parse", MAIN_BRANCH]);
+
+        let client_path = workspaces_path.join("client");
+        git_output(
+            &workspaces_path,
+            &["clone", "--quiet", "abcdefghijkl", "client"],
+        );
+        let check = |source: &Source| source.validate();
+
+        // Build script would run on the next build.
+        fs::write(client_path.join("build.rs"), "fn main() {}").unwrap();
+        let (response, source) = push(&workspaces_path, &client_path, check);
+        let report = String::from_utf8_lossy(&response.body);
+        assert!(
+            report.contains("ng refs/heads/main file path 'build.rs' not allowed"),
+            "{report}"
+        );
+        assert_eq!(source, None);
+        assert_eq!(
+            git_output(&workspace_path, &["rev-parse", MAIN_BRANCH]),
+            pushed
+        );
+
+        // Symlinked directory would point the writes outside of the workspace.
+        git_output(&client_path, &["reset", "--quiet", "--hard", "HEAD~1"]);
+        std::os::unix::fs::symlink("/", client_path.join("src/root")).unwrap();
+        let (response, source) = push(&workspaces_path, &client_path, check);
+        let report = String::from_utf8_lossy(&response.body);
+        assert!(
+            report.contains("ng refs/heads/main symlink 'src/root' not allowed"),
+            "{report}"
+        );
+        assert_eq!(source, None);
+        assert_eq!(
+            git_output(&workspace_path, &["rev-parse", MAIN_BRANCH]),
+            pushed
+        );
+
+        git_output(&client_path, &["reset", "--quiet", "--hard", "HEAD~1"]);
+        fs::write(client_path.join("src/lib.rs"), "// pushed").unwrap();
+        let (response, source) = push(&workspaces_path, &client_path, check);
+        assert!(String::from_utf8_lossy(&response.body).contains("ok refs/heads/main"));
+        assert_eq!(
+            source.unwrap().files.get("src/lib.rs").unwrap(),
+            "// pushed"
+        );
+        assert_eq!(
+            git_output(&workspace_path, &["rev-parse", MAIN_BRANCH]),
+            git_output(&client_path, &["rev-parse", "HEAD"])
+        );
+        // The working tree gets the pushed source only with the compile.
+        assert_eq!(
+            fs::read_to_string(workspace_path.join("src/lib.rs")).unwrap(),
+            "// lib"
+        );
+
+        fs::remove_dir_all(&workspaces_path).unwrap();
+    }
+}
@@ -0,0 +1,49 @@
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+#[derive(Serialize)]
+pub struct Check {
+    pub name: &'static str,
+    /// `None` if the check passed.
+    pub error: Option<String>,
+}
+
+fn check_command(program: &str) -> Option<String> {
+    match Command::new(program).arg("--version").output() {
+        Ok(output) if output.status.success() => None,
+        Ok(output) => Some(format!(
+            "{program} --version failed: {}\n{}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )),
+        Err(err) => Some(format!("{program} not runnable: {err}")),
+    }
+}
+
+fn check_writable(path: &Path) -> Option<String> {
+    let probe = path.join(format!(".readyz-{}", rand::random::<u32>()));
+    fs::write(&probe, "")
+        .and_then(|_| fs::remove_file(&probe))
+        .err()
+        .map(|err| format!("{path:?} not writable: {err}"))
+}
+
+/// Checks the build tooling and the workspaces directory are usable.
+pub fn readiness_checks(workspaces_path: &Path) -> Vec<Check> {
+    vec![
+        Check {
+            name: "cargo",
+            error: check_command("cargo"),
+        },
+        Check {
+            name: "trunk",
+            error: check_command("trunk"),
+        },
+        Check {
+            name: "workspaces_path",
+            error: check_writable(workspaces_path),
+        },
+    ]
+}
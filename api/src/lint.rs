@@ -0,0 +1,260 @@
+use crate::AppConfig;
+use crate::build::{self, StreamEvent, StreamingResponder};
+use crate::metrics::FailureReason;
+use crate::source::Source;
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+
+/// Edition cargo assumes, when the manifest doesn't set one.
+const DEFAULT_EDITION: &str = "2015";
+
+/// Formats the Rust files of the source with rustfmt, the other files are returned as they are.
+/// Fails with the rustfmt error, if any of the files doesn't parse.
+pub fn format_source(source: &Source) -> Result<Source, String> {
+    let edition = edition(source.cargo_toml());
+    let mut formatted = source.clone();
+    for (path, content) in formatted.files.iter_mut() {
+        if path.ends_with(".rs") {
+            *content = rustfmt(path, content, &edition)?;
+        }
+    }
+    Ok(formatted)
+}
+
+fn edition(cargo_toml: &str) -> String {
+    toml::from_str::<toml::Table>(cargo_toml)
+        .ok()
+        .and_then(|manifest| {
+            manifest
+                .get("package")?
+                .get("edition")?
+                .as_str()
+                .map(String::from)
+        })
+        .unwrap_or_else(|| DEFAULT_EDITION.into())
+}
+
+fn rustfmt(path: &str, content: &str, edition: &str) -> Result<String, String> {
+    let mut child = Command::new("rustfmt")
+        .args(["--edition", edition, "--color", "never"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| format!("failed to spawn rustfmt: {err}"))?;
+    // Written aside, rustfmt may fill the stdout pipe before reading all of its input.
+    let mut stdin = child.stdin.take().expect("rustfmt stdin is piped");
+    let input = content.to_string();
+    let writer = thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let output = child
+        .wait_with_output()
+        .map_err(|err| format!("rustfmt failed with err: {err}"))?;
+    _ = writer.join();
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr)
+            .replace("<stdin>", path)
+            .trim()
+            .into());
+    }
+    String::from_utf8(output.stdout).map_err(|err| format!("rustfmt output: {err}"))
+}
+
+/// Line and column numbers are 1-based, the end column is exclusive.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct DiagnosticSpan {
+    pub file_name: String,
+    pub line_start: u32,
+    pub column_start: u32,
+    pub line_end: u32,
+    pub column_end: u32,
+}
+
+/// Compiler or clippy diagnostic of the user crate.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Diagnostic {
+    /// `error`, `warning`, `note` or `help`.
+    pub level: String,
+    /// Lint or error code, e.g. `clippy::needless_return` or `E0308`.
+    pub code: Option<String>,
+    pub message: String,
+    /// Primary span, `None` for the crate level diagnostics.
+    pub span: Option<DiagnosticSpan>,
+    /// Message as the compiler prints it, with the code snippet and suggestions.
+    pub rendered: String,
+}
+
+/// Sent last, once clippy finished.
+#[derive(Serialize, Default, Debug, PartialEq)]
+pub struct LintSummary {
+    pub errors: u32,
+    pub warnings: u32,
+}
+
+/// Line of the cargo `--message-format json` output, only the compiler messages are read.
+#[derive(Deserialize)]
+struct CargoMessage {
+    reason: String,
+    message: Option<CompilerMessage>,
+}
+
+#[derive(Deserialize)]
+struct CompilerMessage {
+    message: String,
+    level: String,
+    code: Option<CompilerMessageCode>,
+    spans: Vec<CompilerMessageSpan>,
+    rendered: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CompilerMessageCode {
+    code: String,
+}
+
+#[derive(Deserialize)]
+struct CompilerMessageSpan {
+    is_primary: bool,
+    #[serde(flatten)]
+    span: DiagnosticSpan,
+}
+
+fn parse_diagnostic(line: &str) -> Option<Diagnostic> {
+    let message = serde_json::from_str::<CargoMessage>(line).ok()?;
+    if message.reason != "compiler-message" {
+        return None;
+    }
+    let message = message.message?;
+    let span = message
+        .spans
+        .into_iter()
+        .find(|span| span.is_primary)
+        .map(|span| span.span);
+    // Summaries like "aborting due to 2 previous errors" or "3 warnings emitted".
+    if span.is_none() && message.code.is_none() && message.level != "error" {
+        return None;
+    }
+    if message.message.starts_with("aborting due to") {
+        return None;
+    }
+    Some(Diagnostic {
+        level: message.level,
+        code: message.code.map(|code| code.code),
+        message: message.message,
+        span,
+        rendered: message.rendered.unwrap_or_default(),
+    })
+}
+
+/// Writes the source to the workspace and runs clippy on it, streaming the diagnostics
+/// to the responder.
+pub fn run_lint(
+    config: &AppConfig,
+    workspace_path: &Path,
+    body: &Source,
+    responder: &StreamingResponder,
+) {
+    if build::prepare_workspace(config, workspace_path, body, responder).is_err() {
+        return;
+    }
+    _ = build::with_build_cache(config, workspace_path, responder, |target_dir| {
+        run_clippy(config, workspace_path, target_dir, responder)
+    });
+}
+
+fn run_clippy(
+    config: &AppConfig,
+    workspace_path: &Path,
+    target_dir: PathBuf,
+    responder: &StreamingResponder,
+) -> Result<(), FailureReason> {
+    let mut clippy = Command::new("cargo");
+    clippy
+        // Checked for the target trunk builds, the crate may use wasm only APIs.
+        .args(["clippy", "--target", "wasm32-unknown-unknown"])
+        .args(["--message-format", "json", "--color", "never"])
+        .current_dir(workspace_path)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    clippy.env("CARGO_TARGET_DIR", target_dir);
+
+    let _active = config.metrics.active();
+    let mut child = match clippy.spawn() {
+        Ok(child) => child,
+        Err(err) => {
+            responder.terminate_error(format!("failed to spawn cargo clippy: {err}"));
+            return Err(FailureReason::Internal);
+        }
+    };
+    if let Some(stderr) = child.stderr.take() {
+        responder.spawn_buff_line_reading(stderr, StreamEvent::StderrLine);
+    }
+    let mut summary = LintSummary::default();
+    if let Some(stdout) = child.stdout.take() {
+        for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+            let Some(diagnostic) = parse_diagnostic(&line) else {
+                continue;
+            };
+            match diagnostic.level.as_str() {
+                "error" => summary.errors += 1,
+                "warning" => summary.warnings += 1,
+                _ => {}
+            }
+            responder.send_event(StreamEvent::Diagnostic(diagnostic));
+        }
+    }
+
+    match child.wait() {
+        Ok(status) if !status.success() && summary.errors == 0 => {
+            responder.terminate_error(format!("clippy failed: {status}"));
+            Err(FailureReason::Build)
+        }
+        Ok(_) => {
+            responder.send_event(StreamEvent::LintSummary(summary));
+            Ok(())
+        }
+        Err(err) => {
+            responder.terminate_error(format!("clippy failed with err: {err}"));
+            Err(FailureReason::Internal)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_diagnostic() {
+        let warning = r#"{"reason":"compiler-message","package_id":"path+file:///tmp/ws#sheeet-lib@0.0.0","manifest_path":"/tmp/ws/Cargo.toml","target":{"kind":["cdylib","rlib"],"name":"sheeet_lib"},"message":{"rendered":"warning: unneeded `return` statement\n --> src/lib.rs:3:5\n","$message_type":"diagnostic","children":[],"level":"warning","message":"unneeded `return` statement","spans":[{"byte_end":40,"byte_start":30,"column_end":15,"column_start":5,"expansion":null,"file_name":"src/lib.rs","is_primary":true,"label":null,"line_end":3,"line_start":3,"suggested_replacement":null,"suggestion_applicability":null,"text":[]}],"code":{"code":"clippy::needless_return","explanation":null}}}"#;
+        assert_eq!(
+            parse_diagnostic(warning),
+            Some(Diagnostic {
+                level: "warning".into(),
+                code: Some("clippy::needless_return".into()),
+                message: "unneeded `return` statement".into(),
+                span: Some(DiagnosticSpan {
+                    file_name: "src/lib.rs".into(),
+                    line_start: 3,
+                    column_start: 5,
+                    line_end: 3,
+                    column_end: 15,
+                }),
+                rendered: "warning: unneeded `return` statement\n --> src/lib.rs:3:5\n".into(),
+            })
+        );
+
+        let emitted = r#"{"reason":"compiler-message","message":{"rendered":"warning: 1 warning emitted\n","children":[],"level":"warning","message":"1 warning emitted","spans":[],"code":null}}"#;
+        assert_eq!(parse_diagnostic(emitted), None);
+        let artifact = r#"{"reason":"compiler-artifact","package_id":"sheeet-lib"}"#;
+        assert_eq!(parse_diagnostic(artifact), None);
+
+        assert_eq!(
+            edition("[package]\nname = \"sheeet-lib\"\nedition = \"2024\""),
+            "2024"
+        );
+        assert_eq!(edition("[package]\nname = \"sheeet-lib\""), DEFAULT_EDITION);
+    }
+}
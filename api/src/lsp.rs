@@ -0,0 +1,490 @@
+use crate::policy::DependencyPolicy;
+use actix_ws::{AggregatedMessage, AggregatedMessageStream};
+use futures_util::StreamExt;
+use futures_util::stream;
+use log::{debug, info, warn};
+use rand::Rng;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{UnboundedReceiver, unbounded_channel};
+
+/// Root URI of the workspace as the client sees it, the server paths are not exposed.
+pub const CLIENT_ROOT_URI: &str = "file:///workspace";
+/// Limit of a single LSP message, whole files are sent on open and change.
+pub const MAX_MESSAGE_BYTES: usize = 4 * 1024 * 1024;
+const TICKET_LENGTH: usize = 32;
+const TICKET_TTL: Duration = Duration::from_secs(30);
+
+/// Client methods forwarded to rust-analyzer. The others are refused, so the client can't
+/// reconfigure the server, e.g. to run its own commands.
+const FORWARDED_METHODS: [&str; 13] = [
+    "initialize",
+    "initialized",
+    "shutdown",
+    "exit",
+    "$/cancelRequest",
+    "textDocument/didOpen",
+    "textDocument/didChange",
+    "textDocument/didClose",
+    "textDocument/completion",
+    "completionItem/resolve",
+    "textDocument/hover",
+    "textDocument/definition",
+    "textDocument/signatureHelp",
+];
+
+#[derive(Debug)]
+pub enum LspError {
+    TooManySessions,
+    Spawn(io::Error),
+}
+
+/// rust-analyzer sessions of the workspaces, each bridged to one WebSocket connection.
+pub struct LspSessions {
+    max_sessions: usize,
+    idle_timeout: Duration,
+    /// Ticket to the workspace ID and the time it was issued.
+    tickets: Mutex<HashMap<String, (String, Instant)>>,
+    /// Running rust-analyzer of each workspace, a new session replaces the old one.
+    running: Mutex<HashMap<String, Arc<Mutex<Child>>>>,
+}
+
+impl LspSessions {
+    pub fn new(max_sessions: usize, idle_timeout: Duration) -> Self {
+        LspSessions {
+            max_sessions,
+            idle_timeout,
+            tickets: Mutex::new(HashMap::new()),
+            running: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Browsers can't set headers of WebSocket requests, the session is opened with a short
+    /// lived ticket issued to an authorized caller instead.
+    pub fn issue_ticket(&self, workspace_id: &str) -> String {
+        let ticket: String = rand::rng()
+            .sample_iter(&rand::distr::Alphanumeric)
+            .take(TICKET_LENGTH)
+            .map(char::from)
+            .collect();
+        let now = Instant::now();
+        let mut tickets = self.tickets.lock().expect("LSP tickets poisoned");
+        tickets.retain(|_, (_, issued_at)| now.duration_since(*issued_at) < TICKET_TTL);
+        tickets.insert(ticket.clone(), (workspace_id.into(), now));
+        ticket
+    }
+
+    /// Tickets are accepted only once.
+    pub fn redeem_ticket(&self, ticket: &str, workspace_id: &str) -> bool {
+        let mut tickets = self.tickets.lock().expect("LSP tickets poisoned");
+        tickets
+            .remove(ticket)
+            .is_some_and(|(ticket_workspace_id, issued_at)| {
+                ticket_workspace_id == workspace_id && issued_at.elapsed() < TICKET_TTL
+            })
+    }
+
+    /// Starts rust-analyzer in the workspace, killing the workspace's previous one.
+    pub fn start(
+        self: &Arc<Self>,
+        workspace_id: &str,
+        workspace_path: &Path,
+        settings: Value,
+    ) -> Result<LspProcess, LspError> {
+        let mut running = self.running.lock().expect("LSP sessions poisoned");
+        match running.get(workspace_id) {
+            Some(previous) => {
+                info!("replacing rust-analyzer of workspace ID {workspace_id}");
+                _ = previous.lock().expect("rust-analyzer poisoned").kill();
+            }
+            None if running.len() >= self.max_sessions => return Err(LspError::TooManySessions),
+            None => {}
+        }
+
+        let root_path = workspace_path.canonicalize().map_err(LspError::Spawn)?;
+        let mut child = Command::new("rust-analyzer")
+            .current_dir(&root_path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(LspError::Spawn)?;
+        let stdin = child.stdin.take().expect("rust-analyzer stdin is piped");
+        let stdout = child.stdout.take().expect("rust-analyzer stdout is piped");
+        let child = Arc::new(Mutex::new(child));
+        running.insert(workspace_id.into(), child.clone());
+
+        // Writes to rust-analyzer may block, they are done aside the WebSocket handling.
+        let (stdin_sender, stdin_receiver) = mpsc::channel::<String>();
+        thread::spawn(move || {
+            let mut stdin = stdin;
+            for message in stdin_receiver {
+                if write_message(&mut stdin, &message).is_err() {
+                    break;
+                }
+            }
+        });
+        let (stdout_sender, stdout_receiver) = unbounded_channel();
+        thread::spawn(move || {
+            let mut stdout = BufReader::new(stdout);
+            while let Ok(Some(message)) = read_message(&mut stdout) {
+                if stdout_sender.send(message).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(LspProcess {
+            sessions: self.clone(),
+            workspace_id: workspace_id.into(),
+            child,
+            stdin: stdin_sender,
+            stdout: Some(stdout_receiver),
+            root_uri: file_uri(&root_path),
+            settings,
+        })
+    }
+}
+
+/// rust-analyzer configuration, build scripts and proc-macros run only if the dependency policy
+/// allows them.
+pub fn settings(policy: &DependencyPolicy) -> Value {
+    json!({
+        "cargo": {
+            "buildScripts": {"enable": policy.allow_build_scripts},
+            // Kept apart from the builds' target directory, not to lock it.
+            "targetDir": true,
+        },
+        "procMacro": {"enable": policy.allow_proc_macros},
+        // Diagnostics of rust-analyzer itself, cargo check would compile the crate once more.
+        "checkOnSave": false,
+    })
+}
+
+/// Running rust-analyzer, killed once dropped.
+pub struct LspProcess {
+    sessions: Arc<LspSessions>,
+    workspace_id: String,
+    child: Arc<Mutex<Child>>,
+    stdin: mpsc::Sender<String>,
+    stdout: Option<UnboundedReceiver<String>>,
+    /// `file://` URI of the workspace directory.
+    root_uri: String,
+    settings: Value,
+}
+
+impl Drop for LspProcess {
+    fn drop(&mut self) {
+        {
+            let mut child = self.child.lock().expect("rust-analyzer poisoned");
+            _ = child.kill();
+            _ = child.wait();
+        }
+        let mut running = self.sessions.running.lock().expect("LSP sessions poisoned");
+        if running
+            .get(&self.workspace_id)
+            .is_some_and(|child| Arc::ptr_eq(child, &self.child))
+        {
+            running.remove(&self.workspace_id);
+        }
+    }
+}
+
+/// What to do with a message of the client.
+#[derive(Debug, PartialEq)]
+enum ClientAction {
+    Forward(String),
+    /// Refused request, the error response is sent back.
+    Reply(String),
+    Ignore,
+}
+
+impl LspProcess {
+    fn to_server(&self, message: &str) -> String {
+        message.replace(
+            &format!("{CLIENT_ROOT_URI}/"),
+            &format!("{}/", self.root_uri),
+        )
+    }
+
+    fn to_client(&self, message: &str) -> String {
+        message
+            .replace(
+                &format!("{}/", self.root_uri),
+                &format!("{CLIENT_ROOT_URI}/"),
+            )
+            .replace(
+                &format!("\"{}\"", self.root_uri),
+                &format!("\"{CLIENT_ROOT_URI}\""),
+            )
+    }
+
+    fn client_message(&self, text: &str) -> ClientAction {
+        let Ok(mut message) = serde_json::from_str::<Value>(text) else {
+            return ClientAction::Ignore;
+        };
+        let Some(method) = message.get("method").and_then(Value::as_str) else {
+            // Response to a request of rust-analyzer.
+            return ClientAction::Forward(self.to_server(text));
+        };
+        if method == "initialize" {
+            let Some(params) = message.get_mut("params").and_then(Value::as_object_mut) else {
+                return ClientAction::Ignore;
+            };
+            params.insert("processId".into(), Value::Null);
+            params.insert("rootPath".into(), Value::Null);
+            params.insert("rootUri".into(), self.root_uri.clone().into());
+            params.insert(
+                "workspaceFolders".into(),
+                json!([{"uri": self.root_uri, "name": "sheeet-lib"}]),
+            );
+            params.insert("initializationOptions".into(), self.settings.clone());
+            return ClientAction::Forward(message.to_string());
+        }
+        if FORWARDED_METHODS.contains(&method) {
+            return ClientAction::Forward(self.to_server(text));
+        }
+        match message.get("id") {
+            Some(id) => ClientAction::Reply(
+                json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "error": {"code": -32601, "message": format!("method '{method}' is not supported")},
+                })
+                .to_string(),
+            ),
+            None => ClientAction::Ignore,
+        }
+    }
+
+    /// Answers the configuration requests of rust-analyzer, the client's configuration is never
+    /// used. Returns `None` for the other messages.
+    fn configuration_response(&self, message: &str) -> Option<String> {
+        let message = serde_json::from_str::<Value>(message).ok()?;
+        if message.get("method")?.as_str()? != "workspace/configuration" {
+            return None;
+        }
+        let items = message
+            .pointer("/params/items")
+            .and_then(Value::as_array)
+            .map_or(&[][..], Vec::as_slice);
+        let result: Vec<Value> = items
+            .iter()
+            .map(|item| match item.get("section").and_then(Value::as_str) {
+                Some("rust-analyzer") | None => self.settings.clone(),
+                Some(_) => Value::Null,
+            })
+            .collect();
+        Some(json!({"jsonrpc": "2.0", "id": message.get("id")?, "result": result}).to_string())
+    }
+}
+
+enum BridgeEvent {
+    Client(Result<AggregatedMessage, actix_ws::ProtocolError>),
+    ClientClosed,
+    Server(String),
+    ServerExited,
+}
+
+/// Passes the LSP messages between the WebSocket and rust-analyzer, until either of them
+/// closes or the session is idle for too long.
+pub async fn bridge(
+    mut process: LspProcess,
+    mut session: actix_ws::Session,
+    messages: AggregatedMessageStream,
+) {
+    let idle_timeout = process.sessions.idle_timeout;
+    let stdout = process
+        .stdout
+        .take()
+        .expect("rust-analyzer stdout is taken once");
+    let client = messages
+        .map(BridgeEvent::Client)
+        .chain(stream::once(async { BridgeEvent::ClientClosed }));
+    let server = stream::unfold(stdout, |mut stdout| async move {
+        stdout
+            .recv()
+            .await
+            .map(|message| (BridgeEvent::Server(message), stdout))
+    })
+    .chain(stream::once(async { BridgeEvent::ServerExited }));
+    let mut events = std::pin::pin!(stream::select(client, server));
+
+    loop {
+        let event = match actix_web::rt::time::timeout(idle_timeout, events.next()).await {
+            Ok(Some(event)) => event,
+            Ok(None) => break,
+            Err(_) => {
+                info!(
+                    "closing idle rust-analyzer session of workspace ID {}",
+                    process.workspace_id
+                );
+                break;
+            }
+        };
+        match event {
+            BridgeEvent::Client(Ok(AggregatedMessage::Text(text))) => {
+                match process.client_message(&text) {
+                    ClientAction::Forward(message) => {
+                        if process.stdin.send(message).is_err() {
+                            break;
+                        }
+                    }
+                    ClientAction::Reply(response) => {
+                        if session.text(response).await.is_err() {
+                            break;
+                        }
+                    }
+                    ClientAction::Ignore => debug!("ignoring LSP message: {text}"),
+                }
+            }
+            BridgeEvent::Client(Ok(AggregatedMessage::Ping(bytes))) => {
+                if session.pong(&bytes).await.is_err() {
+                    break;
+                }
+            }
+            BridgeEvent::Client(Ok(AggregatedMessage::Binary(_) | AggregatedMessage::Pong(_))) => {}
+            BridgeEvent::Client(Ok(AggregatedMessage::Close(_))) | BridgeEvent::ClientClosed => {
+                break;
+            }
+            BridgeEvent::Client(Err(err)) => {
+                warn!("LSP WebSocket error: {err}");
+                break;
+            }
+            BridgeEvent::Server(message) => {
+                if let Some(response) = process.configuration_response(&message) {
+                    if process.stdin.send(response).is_err() {
+                        break;
+                    }
+                } else if session.text(process.to_client(&message)).await.is_err() {
+                    break;
+                }
+            }
+            BridgeEvent::ServerExited => {
+                debug!(
+                    "rust-analyzer of workspace ID {} exited",
+                    process.workspace_id
+                );
+                break;
+            }
+        }
+    }
+    _ = session.close(None).await;
+}
+
+fn file_uri(path: &Path) -> String {
+    let mut uri = String::from("file://");
+    for byte in path.to_string_lossy().bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-._~".contains(&byte) {
+            uri.push(byte as char);
+        } else {
+            uri.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    uri
+}
+
+fn write_message(writer: &mut impl Write, message: &str) -> io::Result<()> {
+    write!(writer, "Content-Length: {}\r\n\r\n{message}", message.len())?;
+    writer.flush()
+}
+
+/// Reads one message of the base protocol, `None` at the end of the stream.
+fn read_message(reader: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut content_length = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':')
+            && name.eq_ignore_ascii_case("Content-Length")
+        {
+            content_length = value.trim().parse::<usize>().ok();
+        }
+    }
+    let content_length = content_length
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length"))?;
+    let mut content = vec![0; content_length];
+    reader.read_exact(&mut content)?;
+    String::from_utf8(content)
+        .map(Some)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_message() {
+        let sessions = Arc::new(LspSessions::new(1, Duration::from_secs(60)));
+        let (stdin, _) = mpsc::channel();
+        let process = LspProcess {
+            sessions: sessions.clone(),
+            workspace_id: "abc".into(),
+            child: Arc::new(Mutex::new(Command::new("true").spawn().unwrap())),
+            stdin,
+            stdout: None,
+            root_uri: "file:///srv/workspaces/abc".into(),
+            settings: json!({"checkOnSave": false}),
+        };
+
+        let initialize = r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{"rootUri":"file:///workspace","initializationOptions":{"checkOnSave":true}}}"#;
+        let ClientAction::Forward(forwarded) = process.client_message(initialize) else {
+            panic!("initialize not forwarded");
+        };
+        let forwarded: Value = serde_json::from_str(&forwarded).unwrap();
+        assert_eq!(forwarded["params"]["rootUri"], "file:///srv/workspaces/abc");
+        assert_eq!(
+            forwarded["params"]["initializationOptions"],
+            json!({"checkOnSave": false})
+        );
+
+        let hover = r#"{"jsonrpc":"2.0","id":2,"method":"textDocument/hover","params":{"textDocument":{"uri":"file:///workspace/src/lib.rs"}}}"#;
+        assert_eq!(
+            process.client_message(hover),
+            ClientAction::Forward(
+                hover.replace("file:///workspace/", "file:///srv/workspaces/abc/")
+            )
+        );
+        let configuration =
+            r#"{"jsonrpc":"2.0","id":3,"method":"workspace/didChangeConfiguration","params":{}}"#;
+        assert!(matches!(
+            process.client_message(configuration),
+            ClientAction::Reply(_)
+        ));
+
+        let request = r#"{"jsonrpc":"2.0","id":7,"method":"workspace/configuration","params":{"items":[{"section":"rust-analyzer"}]}}"#;
+        assert_eq!(
+            process.configuration_response(request).unwrap(),
+            r#"{"id":7,"jsonrpc":"2.0","result":[{"checkOnSave":false}]}"#
+        );
+        assert_eq!(
+            process.to_client(r#"{"uri":"file:///srv/workspaces/abc/src/lib.rs"}"#),
+            r#"{"uri":"file:///workspace/src/lib.rs"}"#
+        );
+
+        let mut framed = Vec::new();
+        write_message(&mut framed, hover).unwrap();
+        let mut reader = BufReader::new(framed.as_slice());
+        assert_eq!(read_message(&mut reader).unwrap().as_deref(), Some(hover));
+        assert_eq!(read_message(&mut reader).unwrap(), None);
+
+        let ticket = sessions.issue_ticket("abc");
+        assert!(!sessions.redeem_ticket(&ticket, "xyz"));
+        let ticket = sessions.issue_ticket("abc");
+        assert!(sessions.redeem_ticket(&ticket, "abc"));
+        assert!(!sessions.redeem_ticket(&ticket, "abc"));
+    }
+}
@@ -1,22 +1,56 @@
+mod access;
+mod build;
+mod build_cache;
+mod builds;
+mod config;
+mod crate_mirror;
+mod functions;
+mod git;
+mod health;
+mod lint;
+mod lsp;
+mod metrics;
+mod policy;
+mod proof_of_work;
+mod rate_limit;
+mod share;
+mod source;
+mod testing;
+mod users;
+mod workspaces;
+
+use crate::access::Access;
+use crate::build::{DownloadInfo, StreamEvent, StreamingResponder, WorkspaceAccess};
+use crate::build_cache::BuildCache;
+use crate::config::ServerConfig;
+use crate::crate_mirror::CrateMirror;
+use crate::lsp::{LspError, LspSessions};
+use crate::metrics::Metrics;
+use crate::policy::DependencyPolicy;
+use crate::proof_of_work::ProofOfWork;
+use crate::rate_limit::RateLimiter;
+use crate::source::Source;
+use crate::testing::TestRunner;
+use crate::users::{UserError, UserStore};
+use crate::workspaces::{JanitorConfig, WORKSPACE_ID_LENGTH, is_valid_workspace_id};
 use actix_cors::Cors;
 use actix_files::Files;
 use actix_web::body::BoxBody;
 use actix_web::dev::{ServiceRequest, ServiceResponse};
-use actix_web::http::{Method, header};
+use actix_web::http::{Method, StatusCode, header};
 use actix_web::middleware::{Next, from_fn};
-use actix_web::{App, Error, HttpResponse, HttpServer, put, web};
-use bytes::Bytes;
-use futures_util::Stream;
-use futures_util::stream::{self, StreamExt};
-use log::info;
+use actix_web::{
+    App, Error, HttpMessage, HttpRequest, HttpResponse, HttpServer, delete, get, post, put, route,
+    web,
+};
+use log::{info, warn};
 use rand::Rng;
 use serde::{Deserialize, Serialize};
-use std::io::{BufRead, BufReader, Read};
+use std::io::{self, ErrorKind};
 use std::path::{Path, PathBuf};
-use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::time::Duration;
 use std::{env, fs, thread};
-use tokio::sync::mpsc;
-use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 
 #[derive(Deserialize)]
 struct CompileQuery {
@@ -24,247 +58,905 @@ struct CompileQuery {
 }
 
 #[derive(Deserialize)]
-struct CompileBody {
-    lib_rs: String,
-    cargo_toml: String,
+struct WorkspaceQuery {
+    workspace_id: String,
+}
+
+#[derive(Deserialize)]
+struct SourceQuery {
+    revision: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct CreateShareBody {
+    workspace_id: String,
+    sheet_data: serde_json::Value,
+}
+
+#[derive(Serialize)]
+struct ShareInfo {
+    token: String,
 }
 
 #[derive(Serialize)]
-struct DownloadInfo {
-    js_download_url: String,
-    wasm_download_url: String,
+struct ForkInfo {
     workspace_id: String,
+    workspace_token: String,
+}
+
+#[derive(Deserialize)]
+struct LspQuery {
+    ticket: String,
 }
 
 #[derive(Serialize)]
-#[serde(rename_all = "snake_case")]
-enum StreamEvent {
-    StdoutLine(String),
-    StderrLine(String),
-    Error(String),
-    Log(String),
-    DownloadInfo(DownloadInfo),
-}
-
-struct StreamingResponder {
-    receiver: Option<UnboundedReceiver<StreamEvent>>,
-    sender: UnboundedSender<StreamEvent>,
-}
-
-impl StreamingResponder {
-    fn new() -> Self {
-        let (sender, receiver) = mpsc::unbounded_channel();
-        StreamingResponder {
-            receiver: Some(receiver),
-            sender,
-        }
-    }
+struct LspTicket {
+    ticket: String,
+}
 
-    fn send_event(&self, stream_event: StreamEvent) {
-        _ = self.sender.send(stream_event);
-    }
+#[derive(Deserialize)]
+struct ArtifactQuery {
+    token: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CreateUserBody {
+    user_id: String,
+}
+
+#[derive(Serialize)]
+struct UserInfo {
+    user_id: String,
+    api_key: String,
+}
 
-    fn log(&self, line: String) {
-        _ = self.sender.send(StreamEvent::Log(line));
+#[put("/compile")]
+async fn compile(
+    config: web::Data<AppConfig>,
+    req: HttpRequest,
+    body: web::Json<Source>,
+    query: web::Query<CompileQuery>,
+) -> Result<HttpResponse, Error> {
+    let workspace_id = query
+        .workspace_id
+        .clone()
+        .unwrap_or_else(|| random_id(WORKSPACE_ID_LENGTH));
+    info!("compile for workspace ID: {workspace_id}");
+    if !is_valid_workspace_id(&workspace_id) {
+        return Ok(HttpResponse::BadRequest().body("Invalid workspace ID"));
+    }
+    if let Err(err) = body.validate() {
+        return Ok(HttpResponse::BadRequest().body(format!("Invalid source: {err}")));
+    }
+    let workspace_path = config.workspaces_path.join(&workspace_id);
+    if !fs::exists(&workspace_path)? && query.workspace_id.is_some() {
+        return Ok(HttpResponse::NotFound().body("Invalid workspace ID"));
     }
 
-    fn terminate_error(&self, err: impl ToString) {
-        _ = self.sender.send(StreamEvent::Error(err.to_string()));
+    let access = match query.workspace_id {
+        // Only the new workspace is claimed by anyone, the existing ones by the admin.
+        None => Access::Unclaimed,
+        Some(_) => workspace_access(&req, &workspace_path)?,
+    };
+    // Checked before the budgets, the callers without access can't use up the workspace's.
+    if access == Access::Denied {
+        return Ok(access_denied(has_workspace_token(&req)));
+    }
+    let caller = caller(&req);
+    if let Some(response) = abuse_check(&config, &req, &caller, query.workspace_id.as_deref()) {
+        return Ok(response);
     }
 
-    fn stream_command(&self, command: &mut Command) -> Result<std::process::Child, Error> {
-        let mut child = command
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .spawn()
-            .map_err(|err| {
-                actix_web::error::ErrorInternalServerError(format!(
-                    "failed to spawn command: {err}",
-                ))
-            })?;
+    let mut responder = StreamingResponder::new();
+    if access == Access::Unclaimed {
+        let workspace_token = random_id(WORKSPACE_TOKEN_LENGTH);
+        match access::issue(&workspace_path, &workspace_token, caller.user_id.as_deref()) {
+            Ok(()) => responder.send_event(StreamEvent::WorkspaceAccess(WorkspaceAccess {
+                workspace_id: workspace_id.clone(),
+                workspace_token,
+            })),
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => {
+                return Ok(access_denied(has_workspace_token(&req)));
+            }
+            Err(err) => return Err(actix_web::error::ErrorInternalServerError(err)),
+        }
+    }
 
-        let stdout = child.stdout.take().ok_or_else(|| {
-            actix_web::error::ErrorInternalServerError("failed to capture stdout")
-        })?;
-        let stderr = child.stderr.take().ok_or_else(|| {
-            actix_web::error::ErrorInternalServerError("failed to capture stderr")
-        })?;
+    let stream = responder.produce_stream();
+    thread::spawn(move || {
+        build::build_workspace(&config, workspace_id, &workspace_path, &body, &responder)
+    });
 
-        self.spawn_buff_line_reading(stdout, StreamEvent::StdoutLine);
-        self.spawn_buff_line_reading(stderr, StreamEvent::StderrLine);
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .streaming(stream))
+}
 
-        Ok(child)
+#[get(
+    "/workspaces/{workspace_id}/builds",
+    wrap = "from_fn(authorization_middleware)"
+)]
+async fn get_builds(
+    config: web::Data<AppConfig>,
+    req: HttpRequest,
+    path: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+    let workspace_id = path.into_inner();
+    if !is_valid_workspace_id(&workspace_id) {
+        return Ok(HttpResponse::BadRequest().body("Invalid workspace ID"));
     }
+    let workspace_path = config.workspaces_path.join(&workspace_id);
+    if workspace_access(&req, &workspace_path)? == Access::Denied {
+        return Ok(access_denied(has_workspace_token(&req)));
+    }
+    Ok(HttpResponse::Ok().json(builds::load_manifest(&workspace_path)?))
+}
 
-    fn spawn_buff_line_reading(
-        &self,
-        pipe: impl Read + Send + 'static,
-        line_constructor: fn(String) -> StreamEvent,
-    ) {
-        let sender = self.sender.clone();
-        // TODO: Shouldn't this be async?
-        thread::spawn(move || {
-            let reader = BufReader::new(pipe);
-            for line in reader.lines() {
-                if let Ok(line) = line {
-                    let _ = sender.send(line_constructor(line.trim().into()));
-                }
-            }
-        });
+/// Makes the recent build current again and restores its source as the latest revision,
+/// so the next compile continues from it.
+#[post(
+    "/workspaces/{workspace_id}/builds/{build_id}/rollback",
+    wrap = "from_fn(authorization_middleware)"
+)]
+async fn rollback_build(
+    config: web::Data<AppConfig>,
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+) -> Result<HttpResponse, Error> {
+    let (workspace_id, build_id) = path.into_inner();
+    if !is_valid_workspace_id(&workspace_id) {
+        return Ok(HttpResponse::BadRequest().body("Invalid workspace ID"));
+    }
+    if !builds::is_valid_build_id(&build_id) {
+        return Ok(HttpResponse::BadRequest().body("Invalid build ID"));
+    }
+    let workspace_path = config.workspaces_path.join(&workspace_id);
+    if workspace_access(&req, &workspace_path)? == Access::Denied {
+        return Ok(access_denied(has_workspace_token(&req)));
+    }
+    info!("roll back workspace ID {workspace_id} to build {build_id}");
+    let Some(mut build) = builds::rollback(&workspace_path, &build_id)? else {
+        return Ok(HttpResponse::NotFound().body("Build not found"));
+    };
+    let source = source::load_revision(&workspace_path, build.revision)?.source;
+    source::write_source(&workspace_path, &source)?;
+    build.revision = source::save_revision(&workspace_path, &source)?;
+    if let Err(err) = git::commit_all(&workspace_path, &format!("Roll back to build {build_id}")) {
+        warn!("commit rollback of workspace ID {workspace_id}: {err}");
     }
+    workspaces::touch(&workspace_path)?;
+    let functions = builds::load_functions(&builds::build_path(&workspace_path, &build.build_id))?;
+    Ok(HttpResponse::Ok().json(DownloadInfo::new(workspace_id, build, functions)))
+}
 
-    // TODO: Update docs.
-    /// Consumes the responder and produces stream of all messages that receiver will receive.
-    fn produce_stream(&mut self) -> impl Stream<Item = Result<Bytes, Error>> + 'static {
-        stream::unfold(self.receiver.take().unwrap(), |mut receiver| async move {
-            match receiver.recv().await {
-                Some(event) => Some((event, receiver)),
-                None => None,
-            }
-        })
-        .map(|event: StreamEvent| {
-            Ok(Bytes::from(format!(
-                "data: {}\n",
-                serde_json::to_string(&event)?
-            )))
-        })
+/// Issues the ticket opening the workspace's language server session, browsers can't set
+/// the authorization headers of WebSocket requests.
+#[post(
+    "/workspaces/{workspace_id}/lsp/tickets",
+    wrap = "from_fn(authorization_middleware)"
+)]
+async fn create_lsp_ticket(
+    config: web::Data<AppConfig>,
+    req: HttpRequest,
+    path: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+    let workspace_id = path.into_inner();
+    let Some(lsp_sessions) = &config.lsp_sessions else {
+        return Ok(HttpResponse::NotFound().body("Language server is disabled"));
+    };
+    if !is_valid_workspace_id(&workspace_id) {
+        return Ok(HttpResponse::BadRequest().body("Invalid workspace ID"));
     }
+    let workspace_path = config.workspaces_path.join(&workspace_id);
+    if !fs::exists(&workspace_path)? {
+        return Ok(HttpResponse::NotFound().body("Invalid workspace ID"));
+    }
+    match workspace_access(&req, &workspace_path)? {
+        Access::Granted => {}
+        Access::Denied => return Ok(access_denied(has_workspace_token(&req))),
+        Access::Unclaimed => {
+            return Ok(HttpResponse::Conflict().body("Compile the workspace first"));
+        }
+    }
+    let ticket = lsp_sessions.issue_ticket(&workspace_id);
+    Ok(HttpResponse::Ok().json(LspTicket { ticket }))
 }
 
-#[put("/compile")]
-async fn compile(
+/// Bridges the workspace's rust-analyzer over WebSocket, authorized by the ticket.
+#[get("/workspaces/{workspace_id}/lsp")]
+async fn lsp_session(
     config: web::Data<AppConfig>,
-    body: web::Json<CompileBody>,
-    query: web::Query<CompileQuery>,
+    req: HttpRequest,
+    path: web::Path<String>,
+    query: web::Query<LspQuery>,
+    body: web::Payload,
 ) -> Result<HttpResponse, Error> {
-    let workspace_id = query.workspace_id.clone().unwrap_or_else(|| {
-        rand::rng()
-            .sample_iter(&rand::distr::Alphabetic)
-            .take(12)
-            .map(char::from)
-            .map(|c| c.to_ascii_lowercase())
-            .collect()
-    });
-    info!("compile for workspace ID: {workspace_id}");
+    let workspace_id = path.into_inner();
+    let Some(lsp_sessions) = &config.lsp_sessions else {
+        return Ok(HttpResponse::NotFound().body("Language server is disabled"));
+    };
+    if !lsp_sessions.redeem_ticket(&query.ticket, &workspace_id) {
+        return Ok(HttpResponse::Forbidden().body("Invalid ticket"));
+    }
+    info!("language server session for workspace ID: {workspace_id}");
     let workspace_path = config.workspaces_path.join(&workspace_id);
+    workspaces::touch(&workspace_path)?;
+    let process = match lsp_sessions.start(
+        &workspace_id,
+        &workspace_path,
+        lsp::settings(&config.dependency_policy),
+    ) {
+        Ok(process) => process,
+        Err(LspError::TooManySessions) => {
+            return Ok(HttpResponse::ServiceUnavailable().body("Too many language server sessions"));
+        }
+        Err(LspError::Spawn(err)) => {
+            return Err(actix_web::error::ErrorInternalServerError(format!(
+                "failed to spawn rust-analyzer: {err}"
+            )));
+        }
+    };
+    let (response, session, messages) = actix_ws::handle(&req, body)?;
+    let messages = messages
+        .max_frame_size(lsp::MAX_MESSAGE_BYTES)
+        .aggregate_continuations()
+        .max_continuation_size(lsp::MAX_MESSAGE_BYTES);
+    actix_web::rt::spawn(lsp::bridge(process, session, messages));
+    Ok(response)
+}
+
+/// Checks the caller may run the submitted source in the existing workspace. Returns
+/// the response rejecting the request.
+fn check_workspace_run(
+    config: &AppConfig,
+    req: &HttpRequest,
+    body: &Source,
+    workspace_id: &str,
+) -> Result<Option<HttpResponse>, Error> {
+    if !is_valid_workspace_id(workspace_id) {
+        return Ok(Some(
+            HttpResponse::BadRequest().body("Invalid workspace ID"),
+        ));
+    }
+    if let Err(err) = body.validate() {
+        return Ok(Some(
+            HttpResponse::BadRequest().body(format!("Invalid source: {err}")),
+        ));
+    }
+    let workspace_path = config.workspaces_path.join(workspace_id);
     if !fs::exists(&workspace_path)? {
-        if query.workspace_id.is_some() {
-            return Ok(HttpResponse::NotFound().body("Invalid workspace ID"));
+        return Ok(Some(HttpResponse::NotFound().body("Invalid workspace ID")));
+    }
+    match workspace_access(req, &workspace_path)? {
+        Access::Granted => {}
+        Access::Denied => return Ok(Some(access_denied(has_workspace_token(req)))),
+        Access::Unclaimed => {
+            return Ok(Some(
+                HttpResponse::Conflict().body("Compile the workspace first"),
+            ));
         }
     }
+    Ok(abuse_check(config, req, &caller(req), Some(workspace_id)))
+}
+
+/// Runs the tests of the workspace's crate with the submitted source. Counts against
+/// the compile budgets, it builds the crate too.
+#[put("/test")]
+async fn test_workspace(
+    config: web::Data<AppConfig>,
+    req: HttpRequest,
+    body: web::Json<Source>,
+    query: web::Query<WorkspaceQuery>,
+) -> Result<HttpResponse, Error> {
+    let workspace_id = query.workspace_id.clone();
+    info!("test for workspace ID: {workspace_id}");
+    if let Some(response) = check_workspace_run(&config, &req, &body, &workspace_id)? {
+        return Ok(response);
+    }
 
+    let workspace_path = config.workspaces_path.join(&workspace_id);
     let mut responder = StreamingResponder::new();
     let stream = responder.produce_stream();
     thread::spawn(move || {
-        if !fs::exists(&workspace_path).unwrap_or(false) {
-            if let Err(err) = fs::create_dir_all(&workspace_path) {
-                responder.terminate_error(format!("create dir all '{workspace_path:?}': {err}"));
-                return;
-            };
+        testing::run_tests(&config, &workspace_path, &body, &responder);
+    });
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .streaming(stream))
+}
 
-            let mut child = match responder.stream_command(
-                Command::new("cargo")
-                    .current_dir(&workspace_path)
-                    .arg("init")
-                    .arg("--lib")
-                    .arg("--name")
-                    .arg("sheeet-lib")
-                    .env("RUST_LOG", "info")
-                    .env("RUST_LOG_STYLE", "never"),
-            ) {
-                Ok(child) => child,
-                Err(err) => {
-                    responder.terminate_error(err);
-                    return;
-                }
-            };
+/// Runs clippy on the workspace's crate with the submitted source, streams its diagnostics.
+/// Counts against the compile budgets.
+#[put("/lint")]
+async fn lint_workspace(
+    config: web::Data<AppConfig>,
+    req: HttpRequest,
+    body: web::Json<Source>,
+    query: web::Query<WorkspaceQuery>,
+) -> Result<HttpResponse, Error> {
+    let workspace_id = query.workspace_id.clone();
+    info!("lint for workspace ID: {workspace_id}");
+    if let Some(response) = check_workspace_run(&config, &req, &body, &workspace_id)? {
+        return Ok(response);
+    }
 
-            match child.wait() {
-                Ok(status) if status.success() => {
-                    responder.log("Workspace initialized.".into());
-                }
-                Ok(status) => {
-                    responder.terminate_error(format!("build failed: {status}"));
-                    return;
-                }
-                Err(err) => {
-                    responder.terminate_error(format!("build failed with err: {err}"));
-                    return;
-                }
-            };
+    let workspace_path = config.workspaces_path.join(&workspace_id);
+    let mut responder = StreamingResponder::new();
+    let stream = responder.produce_stream();
+    thread::spawn(move || {
+        lint::run_lint(&config, &workspace_path, &body, &responder);
+    });
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .streaming(stream))
+}
+
+/// Formats the Rust files of the submitted source with rustfmt, responds with the whole source.
+#[put("/format")]
+async fn format_source(body: web::Json<Source>) -> Result<HttpResponse, Error> {
+    if let Err(err) = body.validate() {
+        return Ok(HttpResponse::BadRequest().body(format!("Invalid source: {err}")));
+    }
+    match web::block(move || lint::format_source(&body)).await? {
+        Ok(source) => Ok(HttpResponse::Ok().json(source)),
+        Err(err) => Ok(HttpResponse::UnprocessableEntity().body(err)),
+    }
+}
+
+/// Issues a proof of work challenge, anonymous compiles need its solution.
+#[get("/compile/challenge")]
+async fn get_compile_challenge(config: web::Data<AppConfig>) -> HttpResponse {
+    match &config.proof_of_work {
+        Some(proof_of_work) => HttpResponse::Ok().json(proof_of_work.issue()),
+        None => HttpResponse::NotFound().body("Proof of work not required"),
+    }
+}
+
+#[get(
+    "/workspaces/{workspace_id}/source",
+    wrap = "from_fn(authorization_middleware)"
+)]
+async fn get_source(
+    config: web::Data<AppConfig>,
+    req: HttpRequest,
+    path: web::Path<String>,
+    query: web::Query<SourceQuery>,
+) -> Result<HttpResponse, Error> {
+    let workspace_id = path.into_inner();
+    if !is_valid_workspace_id(&workspace_id) {
+        return Ok(HttpResponse::BadRequest().body("Invalid workspace ID"));
+    }
+    let workspace_path = config.workspaces_path.join(&workspace_id);
+    if workspace_access(&req, &workspace_path)? == Access::Denied {
+        return Ok(access_denied(has_workspace_token(&req)));
+    }
+    match source::load_source(&workspace_path, query.revision) {
+        Ok(source_revision) => {
+            workspaces::touch(&workspace_path)?;
+            Ok(HttpResponse::Ok().json(source_revision))
         }
+        Err(err) if err.kind() == ErrorKind::NotFound => {
+            Ok(HttpResponse::NotFound().body("Source revision not found"))
+        }
+        Err(err) => Err(actix_web::error::ErrorInternalServerError(err)),
+    }
+}
 
-        if let Err(err) = fs::write(
-            Path::new(&workspace_path).join("index.html"),
-            include_str!("user.html"),
-        ) {
-            responder.terminate_error(err);
-            return;
-        };
-        if let Err(err) = fs::write(Path::new(&workspace_path).join("src/lib.rs"), &body.lib_rs) {
-            responder.terminate_error(err);
-            return;
+#[get(
+    "/workspaces/{workspace_id}/revisions",
+    wrap = "from_fn(authorization_middleware)"
+)]
+async fn get_revisions(
+    config: web::Data<AppConfig>,
+    req: HttpRequest,
+    path: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+    let workspace_id = path.into_inner();
+    if !is_valid_workspace_id(&workspace_id) {
+        return Ok(HttpResponse::BadRequest().body("Invalid workspace ID"));
+    }
+    let workspace_path = config.workspaces_path.join(&workspace_id);
+    if !fs::exists(&workspace_path)? {
+        return Ok(HttpResponse::NotFound().body("Invalid workspace ID"));
+    }
+    if workspace_access(&req, &workspace_path)? == Access::Denied {
+        return Ok(access_denied(has_workspace_token(&req)));
+    }
+    Ok(HttpResponse::Ok().json(source::list_revisions(&workspace_path)?))
+}
+
+#[post("/shares")]
+async fn create_share(
+    config: web::Data<AppConfig>,
+    req: HttpRequest,
+    body: web::Json<CreateShareBody>,
+) -> Result<HttpResponse, Error> {
+    let body = body.into_inner();
+    if !is_valid_workspace_id(&body.workspace_id) {
+        return Ok(HttpResponse::BadRequest().body("Invalid workspace ID"));
+    }
+    let workspace_path = config.workspaces_path.join(&body.workspace_id);
+    if workspace_access(&req, &workspace_path)? == Access::Denied {
+        return Ok(access_denied(has_workspace_token(&req)));
+    }
+    if !fs::exists(&workspace_path)? {
+        return Ok(HttpResponse::NotFound().body("Invalid workspace ID"));
+    }
+    // The source the shared artifacts were built from, not the latest one, which may
+    // have failed to compile.
+    let Some(build) = builds::current(&workspace_path)? else {
+        return Ok(HttpResponse::Conflict().body("Workspace is not compiled yet"));
+    };
+    let source = match source::load_revision(&workspace_path, build.revision) {
+        Ok(source_revision) => source_revision.source,
+        Err(err) if err.kind() == ErrorKind::NotFound => {
+            return Ok(HttpResponse::Conflict().body("Source of the current build is missing"));
+        }
+        Err(err) => return Err(actix_web::error::ErrorInternalServerError(err)),
+    };
+
+    let token = random_id(SHARE_TOKEN_LENGTH);
+    info!("share workspace ID {} as {token}", body.workspace_id);
+    share::create(
+        &config.workspaces_path,
+        &workspace_path,
+        &token,
+        body.sheet_data,
+        &build,
+        source,
+    )?;
+    Ok(HttpResponse::Ok().json(ShareInfo { token }))
+}
+
+#[get("/shares/{token}")]
+async fn get_share(
+    config: web::Data<AppConfig>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+    let token = path.into_inner();
+    if !is_valid_share_token(&token) {
+        return Ok(HttpResponse::BadRequest().body("Invalid share token"));
+    }
+    match share::load(&config.workspaces_path, &token) {
+        Ok(snapshot) => Ok(HttpResponse::Ok().json(snapshot)),
+        Err(err) if err.kind() == ErrorKind::NotFound => {
+            Ok(HttpResponse::NotFound().body("Invalid share token"))
+        }
+        Err(err) => Err(actix_web::error::ErrorInternalServerError(err)),
+    }
+}
+
+/// Copies the shared snapshot's code into a new workspace. Counts against the compile budgets
+/// as the compile creating a workspace.
+#[post("/shares/{token}/fork")]
+async fn fork_share(
+    config: web::Data<AppConfig>,
+    req: HttpRequest,
+    path: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+    let token = path.into_inner();
+    if !is_valid_share_token(&token) {
+        return Ok(HttpResponse::BadRequest().body("Invalid share token"));
+    }
+    let snapshot = match share::load(&config.workspaces_path, &token) {
+        Ok(snapshot) => snapshot,
+        Err(err) if err.kind() == ErrorKind::NotFound => {
+            return Ok(HttpResponse::NotFound().body("Invalid share token"));
+        }
+        Err(err) => return Err(actix_web::error::ErrorInternalServerError(err)),
+    };
+
+    let caller = caller(&req);
+    if let Some(response) = abuse_check(&config, &req, &caller, None) {
+        return Ok(response);
+    }
+    // Shared before the policy changed, or by a more permissive instance.
+    if let Err(err) = check_pushed_source(&config, &snapshot.source) {
+        return Ok(HttpResponse::Forbidden().body(format!("Can't fork the share: {err}")));
+    }
+
+    let workspace_id = random_id(WORKSPACE_ID_LENGTH);
+    info!("fork share {token} to workspace ID {workspace_id}");
+    let workspace_path = config.workspaces_path.join(&workspace_id);
+    let workspace_token = random_id(WORKSPACE_TOKEN_LENGTH);
+    access::issue(&workspace_path, &workspace_token, caller.user_id.as_deref())?;
+    config.build_cache.init_workspace(&workspace_path)?;
+    source::write_source(&workspace_path, &snapshot.source)?;
+    let revision = source::save_revision(&workspace_path, &snapshot.source)?;
+    let share_path = share::share_path(&config.workspaces_path, &token);
+    builds::publish(&workspace_path, &share_path.join("dist"), revision)?;
+    git::commit_all(&workspace_path, "Fork shared workspace")?;
+
+    Ok(HttpResponse::Ok().json(ForkInfo {
+        workspace_id,
+        workspace_token,
+    }))
+}
+
+/// Serves the workspace git repository over smart HTTP, so it can be cloned and pushed to.
+/// Pushed commits are checked as the submitted sources, successful push to the main branch
+/// triggers compile of the pushed code. Pushes count against the compile budgets.
+#[route("/git/{workspace_id}/{path:.*}", method = "GET", method = "POST")]
+async fn git_http_backend(
+    config: web::Data<AppConfig>,
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    body: web::Bytes,
+) -> Result<HttpResponse, Error> {
+    let (workspace_id, path) = path.into_inner();
+    if !is_valid_workspace_id(&workspace_id) {
+        return Ok(HttpResponse::BadRequest().body("Invalid workspace ID"));
+    }
+    let workspace_path = config.workspaces_path.join(&workspace_id);
+    if !fs::exists(workspace_path.join(".git"))? {
+        return Ok(HttpResponse::NotFound().body("Invalid workspace ID"));
+    }
+    if workspace_access(&req, &workspace_path)? == Access::Denied {
+        return Ok(access_denied(has_workspace_token(&req)));
+    }
+    let is_push = path == "git-receive-pack";
+    if is_push
+        && let Some(response) = abuse_check(&config, &req, &caller(&req), Some(&workspace_id))
+    {
+        return Ok(response);
+    }
+    workspaces::touch(&workspace_path)?;
+
+    let header = |name: &str| {
+        req.headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(String::from)
+    };
+    let method = req.method().to_string();
+    let query_string = req.query_string().to_string();
+    let content_type = header("Content-Type");
+    let content_encoding = header("Content-Encoding");
+    let git_protocol = header("Git-Protocol");
+    let block_config = config.clone();
+    let block_workspace_path = workspace_path.clone();
+    let response = web::block(move || {
+        let request = git::HttpBackendRequest {
+            method: &method,
+            path_info: &format!("/{workspace_id}/{path}"),
+            query_string: &query_string,
+            content_type: content_type.as_deref(),
+            content_encoding: content_encoding.as_deref(),
+            git_protocol: git_protocol.as_deref(),
+            body: &body,
         };
-        if let Err(err) = fs::write(
-            Path::new(&workspace_path).join("Cargo.toml"),
-            &body.cargo_toml,
-        ) {
-            responder.terminate_error(err);
-            return;
+        let workspaces_path = &block_config.workspaces_path;
+        let response = match is_push {
+            true => git::receive_pack(workspaces_path, &block_workspace_path, request, |source| {
+                check_pushed_source(&block_config, source)
+            })?,
+            false => (git::http_backend(workspaces_path, request)?, None),
         };
+        Ok::<_, io::Error>((response, workspace_id))
+    })
+    .await?
+    .map_err(actix_web::error::ErrorInternalServerError)?;
+    let ((response, pushed_source), workspace_id) = response;
 
-        let mut child = match responder.stream_command(
-            Command::new("trunk")
-                .arg("build")
-                .current_dir(&workspace_path)
-                .env("RUST_LOG", "info")
-                .env("RUST_LOG_STYLE", "never"),
-        ) {
-            Ok(child) => child,
-            Err(err) => {
-                responder.terminate_error(err);
-                return;
-            }
-        };
+    if let Some(source) = pushed_source {
+        info!("push to workspace ID {workspace_id}, compiling");
+        thread::spawn(move || {
+            let responder = StreamingResponder::new();
+            build::build_workspace(&config, workspace_id, &workspace_path, &source, &responder);
+            responder.log_events();
+        });
+    }
+
+    let mut builder = HttpResponse::build(
+        StatusCode::from_u16(response.status)
+            .map_err(actix_web::error::ErrorInternalServerError)?,
+    );
+    for (name, value) in response.headers {
+        builder.insert_header((name, value));
+    }
+    Ok(builder.body(response.body))
+}
+
+/// Checks the pushed (or forked) source as the compile checks the submitted one, before any
+/// of it gets into the workspace.
+fn check_pushed_source(config: &AppConfig, source: &Source) -> Result<(), String> {
+    source.validate()?;
+    if !config.dependency_policy.is_permissive() {
+        config
+            .dependency_policy
+            .check_manifest(source.cargo_toml())
+            .map_err(|err| format!("dependency policy: {err}"))?;
+    }
+    Ok(())
+}
+
+/// Creates user account, only with the instance secret API key.
+#[post("/users")]
+async fn create_user(
+    config: web::Data<AppConfig>,
+    req: HttpRequest,
+    body: web::Json<CreateUserBody>,
+) -> Result<HttpResponse, Error> {
+    if !caller(&req).admin {
+        return Ok(HttpResponse::Forbidden().body("Users are managed with the secret API key"));
+    }
+    let user_id = body.into_inner().user_id;
+    info!("create user {user_id}");
+    let users = config.users.clone();
+    let secret = random_id(USER_SECRET_LENGTH);
+    match web::block(move || {
+        users
+            .create(&user_id, &secret)
+            .map(|api_key| (user_id, api_key))
+    })
+    .await?
+    {
+        Ok((user_id, api_key)) => Ok(HttpResponse::Ok().json(UserInfo { user_id, api_key })),
+        Err(err) => user_error_response(err),
+    }
+}
+
+#[delete("/users/{user_id}")]
+async fn delete_user(
+    config: web::Data<AppConfig>,
+    req: HttpRequest,
+    path: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+    if !caller(&req).admin {
+        return Ok(HttpResponse::Forbidden().body("Users are managed with the secret API key"));
+    }
+    let user_id = path.into_inner();
+    info!("delete user {user_id}");
+    match config.users.delete(&user_id) {
+        Ok(()) => Ok(HttpResponse::NoContent().finish()),
+        Err(err) => user_error_response(err),
+    }
+}
+
+fn user_error_response(err: UserError) -> Result<HttpResponse, Error> {
+    match err {
+        UserError::InvalidUserId => Ok(HttpResponse::BadRequest().body("Invalid user ID")),
+        UserError::AlreadyExists => Ok(HttpResponse::Conflict().body("User already exists")),
+        UserError::NotFound => Ok(HttpResponse::NotFound().body("User not found")),
+        UserError::Io(err) => Err(actix_web::error::ErrorInternalServerError(err)),
+    }
+}
 
-        match child.wait() {
-            Ok(status) if status.success() => {
-                responder.send_event(StreamEvent::DownloadInfo(DownloadInfo {
-                    js_download_url: Path::new("/workspaces")
-                        .join(&workspace_id)
-                        .join("dist/sheeet-lib.js")
-                        .to_str()
-                        .unwrap() // I build the complete path myself with UTF-8 chars only.
-                        .into(),
-                    wasm_download_url: Path::new("/workspaces")
-                        .join(&workspace_id)
-                        .join("dist/sheeet-lib_bg.wasm")
-                        .to_str()
-                        .unwrap() // I build the complete path myself with UTF-8 chars only.
-                        .into(),
-                    workspace_id,
-                }));
+fn admin_only(req: &HttpRequest) -> Option<HttpResponse> {
+    (!caller(req).admin).then(|| HttpResponse::Forbidden().body("Only for the secret API key"))
+}
+
+/// Lists all workspaces with their disk usage, least recently used first.
+#[get("/admin/workspaces")]
+async fn list_workspaces(
+    config: web::Data<AppConfig>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    if let Some(denied) = admin_only(&req) {
+        return Ok(denied);
+    }
+    let workspaces_path = config.workspaces_path.clone();
+    let workspaces = web::block(move || workspaces::list(&workspaces_path)).await??;
+    Ok(HttpResponse::Ok().json(workspaces))
+}
+
+#[delete("/admin/workspaces/{workspace_id}")]
+async fn delete_workspace(
+    config: web::Data<AppConfig>,
+    req: HttpRequest,
+    path: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+    if let Some(denied) = admin_only(&req) {
+        return Ok(denied);
+    }
+    let workspace_id = path.into_inner();
+    if !is_valid_workspace_id(&workspace_id) {
+        return Ok(HttpResponse::BadRequest().body("Invalid workspace ID"));
+    }
+    let workspace_path = config.workspaces_path.join(&workspace_id);
+    if !fs::exists(&workspace_path)? {
+        return Ok(HttpResponse::NotFound().body("Invalid workspace ID"));
+    }
+    info!("delete workspace ID {workspace_id}");
+    web::block(move || workspaces::delete(&workspace_path)).await??;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Removes the workspace's build artifacts, the next compile rebuilds them.
+#[post("/admin/workspaces/{workspace_id}/prune")]
+async fn prune_workspace(
+    config: web::Data<AppConfig>,
+    req: HttpRequest,
+    path: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+    if let Some(denied) = admin_only(&req) {
+        return Ok(denied);
+    }
+    let workspace_id = path.into_inner();
+    if !is_valid_workspace_id(&workspace_id) {
+        return Ok(HttpResponse::BadRequest().body("Invalid workspace ID"));
+    }
+    let workspaces_path = config.workspaces_path.clone();
+    if !fs::exists(workspaces_path.join(&workspace_id))? {
+        return Ok(HttpResponse::NotFound().body("Invalid workspace ID"));
+    }
+    info!("prune workspace ID {workspace_id}");
+    let info = web::block(move || {
+        workspaces::prune(&workspaces_path.join(&workspace_id))?;
+        workspaces::info(&workspaces_path, &workspace_id)
+    })
+    .await??;
+    Ok(HttpResponse::Ok().json(info))
+}
+
+/// Liveness, the server responds.
+#[get("/healthz")]
+async fn healthz() -> HttpResponse {
+    HttpResponse::Ok().body("ok")
+}
+
+/// Readiness, compiles can succeed.
+#[get("/readyz")]
+async fn readyz(config: web::Data<AppConfig>) -> Result<HttpResponse, Error> {
+    let workspaces_path = config.workspaces_path.clone();
+    let checks = web::block(move || health::readiness_checks(&workspaces_path)).await?;
+    if checks.iter().all(|check| check.error.is_none()) {
+        Ok(HttpResponse::Ok().json(checks))
+    } else {
+        Ok(HttpResponse::ServiceUnavailable().json(checks))
+    }
+}
+
+#[get("/metrics")]
+async fn get_metrics(config: web::Data<AppConfig>) -> Result<HttpResponse, Error> {
+    let config = config.into_inner();
+    let rendered = web::block(move || {
+        let disk_usage = config
+            .metrics
+            .disk_usage(|| workspaces::dir_size(&config.workspaces_path).ok());
+        config.metrics.render(disk_usage)
+    })
+    .await?;
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(rendered))
+}
+
+const SHARE_TOKEN_LENGTH: usize = 24;
+const WORKSPACE_TOKEN_LENGTH: usize = 32;
+const USER_SECRET_LENGTH: usize = 32;
+const WORKSPACE_TOKEN_HEADER: &str = "X-Workspace-Token";
+const PROOF_OF_WORK_HEADER: &str = "X-Proof-Of-Work";
+
+fn random_id(length: usize) -> String {
+    rand::rng()
+        .sample_iter(&rand::distr::Alphabetic)
+        .take(length)
+        .map(char::from)
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+fn is_valid_share_token(token: &str) -> bool {
+    token.len() == SHARE_TOKEN_LENGTH && token.chars().all(|c| c.is_ascii_lowercase())
+}
+
+/// Identity of the API caller, set by the authorization middleware.
+#[derive(Clone, Default)]
+struct Caller {
+    /// Caller sent the instance secret API key.
+    admin: bool,
+    /// Caller sent the API key of this user account.
+    user_id: Option<String>,
+}
+
+fn caller(req: &HttpRequest) -> Caller {
+    req.extensions()
+        .get::<Caller>()
+        .cloned()
+        .unwrap_or_default()
+}
+
+fn has_workspace_token(req: &HttpRequest) -> bool {
+    req.headers().contains_key(WORKSPACE_TOKEN_HEADER)
+}
+
+/// Checks the workspace token header, or whether the caller's account owns the workspace.
+/// Workspaces created before the access tokens are accessible only to the admin, until
+/// the admin's compile claims them.
+fn workspace_access(req: &HttpRequest, workspace_path: &Path) -> io::Result<Access> {
+    let token = req
+        .headers()
+        .get(WORKSPACE_TOKEN_HEADER)
+        .and_then(|value| value.to_str().ok());
+    let caller = caller(req);
+    Ok(
+        match access::check(workspace_path, token, caller.user_id.as_deref())? {
+            Access::Unclaimed if !caller.admin => Access::Denied,
+            access => access,
+        },
+    )
+}
+
+/// Anonymous callers need a proof of work, all but the admin are rate limited. Returns
+/// the response rejecting the request.
+fn abuse_check(
+    config: &AppConfig,
+    req: &HttpRequest,
+    caller: &Caller,
+    workspace_id: Option<&str>,
+) -> Option<HttpResponse> {
+    if let Some(proof_of_work) = &config.proof_of_work
+        && !caller.admin
+        && caller.user_id.is_none()
+    {
+        match req
+            .headers()
+            .get(PROOF_OF_WORK_HEADER)
+            .and_then(|value| value.to_str().ok())
+        {
+            None => {
+                return Some(
+                    HttpResponse::build(StatusCode::PRECONDITION_REQUIRED)
+                        .body("Missing proof of work"),
+                );
             }
-            Ok(status) => {
-                responder.terminate_error(format!("build failed: {status}"));
-                return;
+            Some(solution) if !proof_of_work.verify(solution) => {
+                return Some(HttpResponse::Forbidden().body("Invalid proof of work"));
             }
-            Err(err) => {
-                responder.terminate_error(format!("build failed with err: {err}"));
-                return;
+            Some(_) => {}
+        }
+    }
+    if !caller.admin {
+        let client_ip = rate_limit::client_ip(req, config.trust_proxy_headers);
+        let limits = [
+            (config.compile_ip_limiter.as_ref(), Some(client_ip.as_str())),
+            (config.compile_workspace_limiter.as_ref(), workspace_id),
+        ];
+        for (limiter, key) in limits {
+            if let (Some(limiter), Some(key)) = (limiter, key)
+                && let Err(retry_after) = limiter.acquire(key)
+            {
+                let retry_after = retry_after.as_secs_f64().ceil() as u64;
+                return Some(
+                    HttpResponse::TooManyRequests()
+                        .insert_header((header::RETRY_AFTER, retry_after))
+                        .body(format!("Too many compiles, retry in {retry_after} s")),
+                );
             }
-        };
-    });
+        }
+    }
+    None
+}
 
-    Ok(HttpResponse::Ok()
-        .content_type("text/event-stream")
-        .streaming(stream))
+fn access_denied(has_token: bool) -> HttpResponse {
+    if has_token {
+        HttpResponse::Forbidden().body("Invalid workspace token")
+    } else {
+        HttpResponse::Unauthorized().body("Missing workspace token")
+    }
 }
 
 #[derive(Clone)]
 struct AppConfig {
     workspaces_path: PathBuf,
     secret_api_key: Option<String>,
+    build_cache: Arc<BuildCache>,
+    users: Arc<UserStore>,
+    metrics: Arc<Metrics>,
+    dependency_policy: DependencyPolicy,
+    /// Offline builds resolve crates only from the mirror.
+    crate_mirror: Option<CrateMirror>,
+    url_prefix: String,
+    compile_ip_limiter: Option<Arc<RateLimiter>>,
+    compile_workspace_limiter: Option<Arc<RateLimiter>>,
+    trust_proxy_headers: bool,
+    proof_of_work: Option<Arc<ProofOfWork>>,
+    test_runner: TestRunner,
+    /// Whole test run is killed after it.
+    test_timeout: Duration,
+    /// Language server sessions, `None` if disabled.
+    lsp_sessions: Option<Arc<LspSessions>>,
 }
 
 async fn authorization_middleware(
@@ -274,40 +966,151 @@ async fn authorization_middleware(
     if req.method() == Method::OPTIONS {
         return next.call(req).await;
     }
-    if let Some(app_config) = req.app_data::<web::Data<AppConfig>>() {
-        if let Some(secret_api_key) = &app_config.secret_api_key {
-            match req.headers().get(header::AUTHORIZATION) {
-                None => {
-                    let response =
-                        HttpResponse::Unauthorized().body("Missing authorization header");
-                    return Ok(req.into_response(response));
-                }
-                Some(key) => {
-                    if secret_api_key != key {
-                        let response = HttpResponse::Forbidden().body("Invalid API key");
-                        return Ok(req.into_response(response));
-                    }
-                }
+    let mut caller = Caller::default();
+    if let Some(app_config) = req.app_data::<web::Data<AppConfig>>().cloned() {
+        let key = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|key| key.to_str().ok())
+            .map(String::from);
+        if let Some(key) = key.clone() {
+            if app_config.secret_api_key.as_ref() == Some(&key) {
+                caller.admin = true;
+            } else {
+                let users = app_config.users.clone();
+                caller.user_id = web::block(move || users.authenticate(&key)).await?;
             }
         }
+        if app_config.secret_api_key.is_some() && !caller.admin && caller.user_id.is_none() {
+            let response = match key {
+                None => HttpResponse::Unauthorized().body("Missing authorization header"),
+                Some(_) => HttpResponse::Forbidden().body("Invalid API key"),
+            };
+            return Ok(req.into_response(response));
+        }
     };
+    req.extensions_mut().insert(caller);
     next.call(req).await
 }
 
+/// Serves the workspace artifacts only with the workspace token, sent either as the header or
+/// the `token` query param, browsers can't set headers of module imports. Shares are public.
+///
+/// Published builds and shares never change, browsers can cache them for good.
+async fn artifacts_middleware(
+    req: ServiceRequest,
+    next: Next<BoxBody>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let Some(app_config) = req.app_data::<web::Data<AppConfig>>().cloned() else {
+        return next.call(req).await;
+    };
+    let path = req
+        .path()
+        .strip_prefix(&format!("{}/workspaces/", app_config.url_prefix))
+        .unwrap_or_default()
+        .to_string();
+    let mut segments = path.split('/');
+    let (root, dir) = (
+        segments.next().unwrap_or_default(),
+        segments.next().unwrap_or_default(),
+    );
+    if is_valid_workspace_id(root) {
+        let query = web::Query::<ArtifactQuery>::from_query(req.query_string()).ok();
+        let token = req
+            .headers()
+            .get(WORKSPACE_TOKEN_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .or(query.as_ref().and_then(|query| query.token.as_deref()));
+        let workspace_path = app_config.workspaces_path.join(root);
+        if access::check(&workspace_path, token, None)? != Access::Granted {
+            let response = access_denied(token.is_some());
+            return Ok(req.into_response(response));
+        }
+    }
+    let cache_control = match (root, dir) {
+        (share::SHARES_DIR, _) => "public, max-age=31536000, immutable",
+        (_, builds::BUILDS_DIR) => "private, max-age=31536000, immutable",
+        _ => "no-cache",
+    };
+
+    let mut response = next.call(req).await?;
+    if response.status().is_success() {
+        response.headers_mut().insert(
+            header::CACHE_CONTROL,
+            header::HeaderValue::from_static(cache_control),
+        );
+    }
+    Ok(response)
+}
+
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     env_logger::init();
 
+    let server_config = ServerConfig::load().unwrap_or_else(|err| {
+        eprintln!("invalid configuration: {err}");
+        std::process::exit(1);
+    });
+    let tls_config = server_config.tls_config().unwrap_or_else(|err| {
+        eprintln!("invalid configuration: {err}");
+        std::process::exit(1);
+    });
+
+    let workspaces_path = server_config.workspaces_path.clone().unwrap_or_else(|| {
+        Path::new(&env::var("HOME").expect("expected 'HOME' environment variable to be set"))
+            .join("workspaces")
+    });
+    // Hidden directory, so it is not served by the artifacts file service.
+    let build_cache_path = server_config
+        .build_cache_path
+        .clone()
+        .unwrap_or(workspaces_path.join(".cache"));
+    let dependency_policy = server_config.dependency_policy().unwrap_or_else(|err| {
+        eprintln!("invalid configuration: {err}");
+        std::process::exit(1);
+    });
+    info!("dependency policy: {dependency_policy:?}");
+    let test_runner = server_config.test_runner();
+    info!("test runner: {test_runner:?}");
+    let users_path = server_config
+        .users_path
+        .clone()
+        .unwrap_or(workspaces_path.join(".users.json"));
+    let users = UserStore::load(users_path).unwrap_or_else(|err| {
+        eprintln!("invalid users file: {err}");
+        std::process::exit(1);
+    });
     let app_config = AppConfig {
-        workspaces_path: env::var("SHEEET_WORKSPACES_PATH")
-            .map(|val| Path::new(&val).to_path_buf())
-            .unwrap_or(
-                Path::new(
-                    &env::var("HOME").expect("expected 'HOME' environment variable to be set"),
-                )
-                .join("workspaces"),
-            ),
-        secret_api_key: env::var("SHEEET_SECRET_API_KEY").ok(),
+        workspaces_path,
+        secret_api_key: server_config.secret_api_key.clone(),
+        build_cache: Arc::new(BuildCache::new(build_cache_path)),
+        users: Arc::new(users),
+        metrics: Arc::new(Metrics::default()),
+        dependency_policy,
+        crate_mirror: server_config
+            .crate_mirror_path
+            .clone()
+            .map(CrateMirror::new),
+        url_prefix: server_config.url_prefix.clone(),
+        compile_ip_limiter: server_config
+            .compile_rate_limit_per_ip
+            .map(|limit| Arc::new(RateLimiter::new(limit))),
+        compile_workspace_limiter: server_config
+            .compile_rate_limit_per_workspace
+            .map(|limit| Arc::new(RateLimiter::new(limit))),
+        trust_proxy_headers: server_config.trust_proxy_headers,
+        proof_of_work: server_config
+            .proof_of_work_difficulty
+            .map(|difficulty| Arc::new(ProofOfWork::new(difficulty))),
+        test_runner,
+        test_timeout: Duration::from_secs(server_config.test_timeout_secs),
+        lsp_sessions: match server_config.lsp_max_sessions {
+            0 => None,
+            max_sessions => Some(Arc::new(LspSessions::new(
+                max_sessions,
+                Duration::from_secs(server_config.lsp_idle_timeout_secs),
+            ))),
+        },
     };
 
     info!(
@@ -315,25 +1118,130 @@ async fn main() -> std::io::Result<()> {
         app_config.workspaces_path
     );
 
-    HttpServer::new(move || {
-        // TODO: CORS.
-        let cors = Cors::default()
-            .allow_any_origin()
+    let janitor_config = JanitorConfig {
+        interval: Duration::from_secs(server_config.janitor_interval_secs),
+        prune_after: server_config
+            .workspace_prune_after_secs
+            .map(Duration::from_secs),
+        evict_after: server_config
+            .workspace_evict_after_secs
+            .map(Duration::from_secs),
+        disk_budget: server_config.workspaces_disk_budget_bytes,
+    };
+    if janitor_config.is_enabled() {
+        info!("workspaces janitor: {janitor_config:?}");
+        let workspaces_path = app_config.workspaces_path.clone();
+        let build_cache = app_config.build_cache.clone();
+        thread::spawn(move || {
+            workspaces::janitor_loop(&workspaces_path, &janitor_config, &build_cache)
+        });
+    }
+
+    let unclaimed = fs::read_dir(&app_config.workspaces_path)
+        .into_iter()
+        .flatten()
+        .filter_map(Result::ok)
+        .filter(|entry| {
+            entry
+                .file_name()
+                .to_str()
+                .is_some_and(is_valid_workspace_id)
+        })
+        .filter(|entry| {
+            matches!(
+                access::check(&entry.path(), None, None),
+                Ok(Access::Unclaimed)
+            )
+        })
+        .count();
+    if unclaimed > 0 {
+        warn!(
+            "{unclaimed} workspaces were created before the access tokens, only the admin can open them, compile them with the secret API key to claim them"
+        );
+    }
+
+    let build_cache = app_config.build_cache.clone();
+    let crate_mirror = app_config.crate_mirror.clone();
+    thread::spawn(move || build_cache.warm_template(crate_mirror.as_ref()));
+
+    let ServerConfig {
+        bind_address,
+        port,
+        workers,
+        allowed_origins,
+        json_limit_bytes,
+        git_payload_limit_bytes,
+        url_prefix,
+        ..
+    } = server_config;
+    let server = HttpServer::new(move || {
+        let mut cors = Cors::default()
             .allow_any_method()
-            .allow_any_header();
+            .allow_any_header()
+            .expose_headers([header::RETRY_AFTER]);
+        if allowed_origins.is_empty() {
+            cors = cors.allow_any_origin();
+        }
+        for origin in &allowed_origins {
+            cors = cors.allowed_origin(origin);
+        }
 
         App::new()
             .app_data(web::Data::new(app_config.clone()))
-            .service(Files::new("/api/workspaces", &app_config.workspaces_path))
+            .app_data(web::JsonConfig::default().limit(json_limit_bytes))
+            .service(healthz)
+            .service(readyz)
+            .service(get_metrics)
             .service(
-                web::scope("/api")
-                    .service(compile)
-                    .wrap(from_fn(authorization_middleware)),
+                web::scope(&url_prefix)
+                    // Registered before the artifacts file service, which would swallow these paths.
+                    .service(get_source)
+                    .service(get_revisions)
+                    .service(get_builds)
+                    .service(rollback_build)
+                    .service(create_lsp_ticket)
+                    .service(lsp_session)
+                    .service(
+                        web::scope("/workspaces")
+                            .service(Files::new("", &app_config.workspaces_path))
+                            .wrap(from_fn(artifacts_middleware)),
+                    )
+                    .service(
+                        web::scope("")
+                            .service(compile)
+                            .service(test_workspace)
+                            .service(lint_workspace)
+                            .service(format_source)
+                            .service(get_compile_challenge)
+                            .service(create_share)
+                            .service(get_share)
+                            .service(fork_share)
+                            .service(git_http_backend)
+                            .service(create_user)
+                            .service(delete_user)
+                            .service(list_workspaces)
+                            .service(delete_workspace)
+                            .service(prune_workspace)
+                            .app_data(web::PayloadConfig::new(git_payload_limit_bytes))
+                            .wrap(from_fn(authorization_middleware)),
+                    ),
             )
             .wrap(cors)
     })
-    .bind(("0.0.0.0", 8080))?
-    .workers(4)
+    .workers(workers);
+
+    info!(
+        "listening on {bind_address}:{port}{}",
+        if tls_config.is_some() {
+            " with TLS"
+        } else {
+            ""
+        }
+    );
+    match tls_config {
+        Some(tls_config) => server.bind_rustls_0_23((bind_address, port), tls_config)?,
+        None => server.bind((bind_address, port))?,
+    }
     .run()
     .await
 }
@@ -0,0 +1,225 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Upper bounds of the compile duration histogram buckets in seconds.
+const DURATION_BUCKETS: [f64; 9] = [1.0, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1200.0];
+/// Walking the workspaces directory is expensive, scrapes reuse the value for a while.
+const DISK_USAGE_MAX_AGE: Duration = Duration::from_secs(60);
+
+/// Why the build failed, the `reason` label of the failures counter.
+#[derive(Clone, Copy, Debug)]
+pub enum FailureReason {
+    /// Workspace files couldn't be prepared.
+    Workspace,
+    CrateMirror,
+    DependencyPolicy,
+    BuildCache,
+    /// The user crate didn't compile.
+    Build,
+    Internal,
+}
+
+impl FailureReason {
+    fn label(self) -> &'static str {
+        match self {
+            FailureReason::Workspace => "workspace",
+            FailureReason::CrateMirror => "crate_mirror",
+            FailureReason::DependencyPolicy => "dependency_policy",
+            FailureReason::BuildCache => "build_cache",
+            FailureReason::Build => "build",
+            FailureReason::Internal => "internal",
+        }
+    }
+}
+
+/// Compile metrics in the Prometheus text exposition format.
+#[derive(Default)]
+pub struct Metrics {
+    succeeded: AtomicU64,
+    failed: Mutex<BTreeMap<&'static str, u64>>,
+    duration_buckets: [AtomicU64; DURATION_BUCKETS.len()],
+    duration_sum_micros: AtomicU64,
+    queued: AtomicI64,
+    active: AtomicI64,
+    disk_usage: Mutex<Option<(Instant, u64)>>,
+}
+
+/// Decrements the gauge when dropped.
+pub struct GaugeGuard<'a>(&'a AtomicI64);
+
+impl Drop for GaugeGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Metrics {
+    /// Counts the build as waiting for the build cache lock, until the guard is dropped.
+    pub fn queued(&self) -> GaugeGuard<'_> {
+        self.queued.fetch_add(1, Ordering::Relaxed);
+        GaugeGuard(&self.queued)
+    }
+
+    /// Counts the build as running, until the guard is dropped.
+    pub fn active(&self) -> GaugeGuard<'_> {
+        self.active.fetch_add(1, Ordering::Relaxed);
+        GaugeGuard(&self.active)
+    }
+
+    pub fn record_build(&self, duration: Duration, failure: Option<FailureReason>) {
+        match failure {
+            None => {
+                self.succeeded.fetch_add(1, Ordering::Relaxed);
+            }
+            Some(reason) => {
+                *self
+                    .failed
+                    .lock()
+                    .expect("metrics poisoned")
+                    .entry(reason.label())
+                    .or_default() += 1;
+            }
+        }
+        let seconds = duration.as_secs_f64();
+        for (bucket, bound) in self.duration_buckets.iter().zip(DURATION_BUCKETS) {
+            if seconds <= bound {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.duration_sum_micros
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// Returns the cached disk usage, or computes and caches a fresh one.
+    pub fn disk_usage(&self, compute: impl FnOnce() -> Option<u64>) -> Option<u64> {
+        let mut cached = self.disk_usage.lock().expect("metrics poisoned");
+        if let Some((measured_at, bytes)) = *cached
+            && measured_at.elapsed() < DISK_USAGE_MAX_AGE
+        {
+            return Some(bytes);
+        }
+        let bytes = compute()?;
+        *cached = Some((Instant::now(), bytes));
+        Some(bytes)
+    }
+
+    pub fn render(&self, disk_usage: Option<u64>) -> String {
+        let mut out = String::new();
+        let succeeded = self.succeeded.load(Ordering::Relaxed);
+        let failed = self.failed.lock().expect("metrics poisoned").clone();
+        let total = succeeded + failed.values().sum::<u64>();
+
+        _ = writeln!(
+            out,
+            "# HELP sheeet_compiles_total Finished compiles by result."
+        );
+        _ = writeln!(out, "# TYPE sheeet_compiles_total counter");
+        _ = writeln!(
+            out,
+            "sheeet_compiles_total{{result=\"success\"}} {succeeded}"
+        );
+        _ = writeln!(
+            out,
+            "sheeet_compiles_total{{result=\"failure\"}} {}",
+            total - succeeded
+        );
+
+        _ = writeln!(
+            out,
+            "# HELP sheeet_compile_failures_total Failed compiles by reason."
+        );
+        _ = writeln!(out, "# TYPE sheeet_compile_failures_total counter");
+        for (reason, count) in &failed {
+            _ = writeln!(
+                out,
+                "sheeet_compile_failures_total{{reason=\"{reason}\"}} {count}"
+            );
+        }
+
+        _ = writeln!(
+            out,
+            "# HELP sheeet_compile_duration_seconds Compile durations."
+        );
+        _ = writeln!(out, "# TYPE sheeet_compile_duration_seconds histogram");
+        for (bucket, bound) in self.duration_buckets.iter().zip(DURATION_BUCKETS) {
+            _ = writeln!(
+                out,
+                "sheeet_compile_duration_seconds_bucket{{le=\"{bound}\"}} {}",
+                bucket.load(Ordering::Relaxed)
+            );
+        }
+        _ = writeln!(
+            out,
+            "sheeet_compile_duration_seconds_bucket{{le=\"+Inf\"}} {total}"
+        );
+        _ = writeln!(
+            out,
+            "sheeet_compile_duration_seconds_sum {}",
+            self.duration_sum_micros.load(Ordering::Relaxed) as f64 / 1e6
+        );
+        _ = writeln!(out, "sheeet_compile_duration_seconds_count {total}");
+
+        _ = writeln!(
+            out,
+            "# HELP sheeet_builds_queued Builds waiting for another build with the same dependencies."
+        );
+        _ = writeln!(out, "# TYPE sheeet_builds_queued gauge");
+        _ = writeln!(
+            out,
+            "sheeet_builds_queued {}",
+            self.queued.load(Ordering::Relaxed)
+        );
+        _ = writeln!(out, "# HELP sheeet_builds_active Running builds.");
+        _ = writeln!(out, "# TYPE sheeet_builds_active gauge");
+        _ = writeln!(
+            out,
+            "sheeet_builds_active {}",
+            self.active.load(Ordering::Relaxed)
+        );
+
+        if let Some(disk_usage) = disk_usage {
+            _ = writeln!(
+                out,
+                "# HELP sheeet_workspaces_disk_usage_bytes Size of the workspaces directory."
+            );
+            _ = writeln!(out, "# TYPE sheeet_workspaces_disk_usage_bytes gauge");
+            _ = writeln!(out, "sheeet_workspaces_disk_usage_bytes {disk_usage}");
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::default();
+        metrics.record_build(Duration::from_secs(3), None);
+        metrics.record_build(Duration::from_secs(40), Some(FailureReason::Build));
+        let _active = metrics.active();
+        {
+            let _queued = metrics.queued();
+        }
+
+        let rendered = metrics.render(Some(1024));
+        for line in [
+            "sheeet_compiles_total{result=\"success\"} 1",
+            "sheeet_compiles_total{result=\"failure\"} 1",
+            "sheeet_compile_failures_total{reason=\"build\"} 1",
+            "sheeet_compile_duration_seconds_bucket{le=\"5\"} 1",
+            "sheeet_compile_duration_seconds_bucket{le=\"60\"} 2",
+            "sheeet_compile_duration_seconds_sum 43",
+            "sheeet_compile_duration_seconds_count 2",
+            "sheeet_builds_queued 0",
+            "sheeet_builds_active 1",
+            "sheeet_workspaces_disk_usage_bytes 1024",
+        ] {
+            assert!(rendered.lines().any(|rendered| rendered == line), "{line}");
+        }
+    }
+}
@@ -0,0 +1,437 @@
+use semver::{Version, VersionReq};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::process::Command;
+
+/// Crates of the default user crate. Their dependency trees are trusted, so `wasm-bindgen`'s
+/// proc-macros and build scripts pass even on restrictive instances.
+const TRUSTED_CRATES: [&str; 2] = ["wasm-bindgen", "sheeet-funcs"];
+/// Manifest tables, whose entries are resolved as dependencies.
+const DEPENDENCY_TABLES: [&str; 3] = ["dependencies", "build-dependencies", "dev-dependencies"];
+
+/// Rules the user crate and its dependencies must pass before the build runs any of their code.
+///
+/// Public instances are restrictive by default, private instances (with `SHEEET_SECRET_API_KEY`)
+/// allow everything unless configured otherwise.
+#[derive(Clone, Debug)]
+pub struct DependencyPolicy {
+    /// Allowed direct dependencies with their version requirements, `None` allows any crate.
+    pub allowlist: Option<HashMap<String, VersionReq>>,
+    pub allow_build_scripts: bool,
+    pub allow_proc_macros: bool,
+    pub allow_git_dependencies: bool,
+    pub allow_path_dependencies: bool,
+}
+
+impl DependencyPolicy {
+    /// Nothing to check, the dependency resolution can be skipped.
+    pub fn is_permissive(&self) -> bool {
+        self.allowlist.is_none()
+            && self.allow_build_scripts
+            && self.allow_proc_macros
+            && self.allow_git_dependencies
+            && self.allow_path_dependencies
+    }
+
+    /// Checks the submitted Cargo.toml. Runs before the dependencies are resolved, so git
+    /// and path dependencies are never fetched or read.
+    pub fn check_manifest(&self, cargo_toml: &str) -> Result<(), String> {
+        let manifest: toml::Table =
+            toml::from_str(cargo_toml).map_err(|err| format!("invalid Cargo.toml: {err}"))?;
+
+        let mut tables = dependency_tables(&manifest);
+        // Patched crates replace the dependencies, so they go through the same rules.
+        if let Some(patches) = manifest.get("patch").and_then(toml::Value::as_table) {
+            tables.extend(patches.values().filter_map(toml::Value::as_table));
+        }
+        if let Some(replace) = manifest.get("replace").and_then(toml::Value::as_table) {
+            tables.push(replace);
+        }
+
+        for (key, value) in tables.into_iter().flatten() {
+            let name = value
+                .get("package")
+                .and_then(toml::Value::as_str)
+                .unwrap_or(key);
+            if value.get("git").is_some() && !self.allow_git_dependencies {
+                return Err(format!(
+                    "git dependency '{name}' is not allowed (SHEEET_ALLOW_GIT_DEPENDENCIES)"
+                ));
+            }
+            if value.get("path").is_some() && !self.allow_path_dependencies {
+                return Err(format!(
+                    "path dependency '{name}' is not allowed (SHEEET_ALLOW_PATH_DEPENDENCIES)"
+                ));
+            }
+            if let Some(allowlist) = &self.allowlist
+                && !allowlist.contains_key(name)
+            {
+                return Err(format!(
+                    "dependency '{name}' is not in the allowed dependencies (SHEEET_ALLOWED_DEPENDENCIES)"
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the resolved dependency graph, the same one cargo writes to Cargo.lock.
+    pub fn check_metadata(&self, metadata: &Metadata) -> Result<(), String> {
+        let packages: HashMap<&str, &Package> = metadata
+            .packages
+            .iter()
+            .map(|package| (package.id.as_str(), package))
+            .collect();
+        let nodes: HashMap<&str, &Node> = metadata
+            .resolve
+            .nodes
+            .iter()
+            .map(|node| (node.id.as_str(), node))
+            .collect();
+        let root = metadata
+            .resolve
+            .root
+            .as_deref()
+            .ok_or("missing the user crate in the resolved dependencies")?;
+        let direct: &[String] = nodes
+            .get(root)
+            .map_or(&[], |node| node.dependencies.as_slice());
+
+        let mut trusted = HashSet::new();
+        let mut pending: Vec<&str> = Vec::new();
+        for id in direct {
+            let package = packages
+                .get(id.as_str())
+                .ok_or_else(|| format!("missing resolved package '{id}'"))?;
+            if let Some(allowlist) = &self.allowlist {
+                let requirement = allowlist.get(&package.name).ok_or_else(|| {
+                    format!(
+                        "dependency '{}' is not in the allowed dependencies (SHEEET_ALLOWED_DEPENDENCIES)",
+                        package.name
+                    )
+                })?;
+                let version = Version::parse(&package.version).map_err(|err| {
+                    format!("invalid version of dependency '{}': {err}", package.name)
+                })?;
+                if !requirement.matches(&version) {
+                    return Err(format!(
+                        "dependency '{}' resolved to version {version}, allowed is '{requirement}' (SHEEET_ALLOWED_DEPENDENCIES)",
+                        package.name
+                    ));
+                }
+                pending.push(id);
+            } else if TRUSTED_CRATES.contains(&package.name.as_str()) {
+                pending.push(id);
+            }
+        }
+        while let Some(id) = pending.pop() {
+            if trusted.insert(id)
+                && let Some(node) = nodes.get(id)
+            {
+                pending.extend(node.dependencies.iter().map(String::as_str));
+            }
+        }
+
+        for package in &metadata.packages {
+            let is_root = package.id == root;
+            if !is_root {
+                match &package.source {
+                    None if !self.allow_path_dependencies => {
+                        return Err(format!(
+                            "path dependency '{}' is not allowed (SHEEET_ALLOW_PATH_DEPENDENCIES)",
+                            package.name
+                        ));
+                    }
+                    Some(source) if source.starts_with("git+") && !self.allow_git_dependencies => {
+                        return Err(format!(
+                            "git dependency '{}' is not allowed (SHEEET_ALLOW_GIT_DEPENDENCIES)",
+                            package.name
+                        ));
+                    }
+                    _ => {}
+                }
+            }
+            if trusted.contains(package.id.as_str()) {
+                continue;
+            }
+            let described = if is_root {
+                "the user crate".to_string()
+            } else {
+                format!("dependency '{}'", package.name)
+            };
+            if package.has_target("custom-build") && !self.allow_build_scripts {
+                return Err(format!(
+                    "{described} has a build script, build scripts are not allowed (SHEEET_ALLOW_BUILD_SCRIPTS)"
+                ));
+            }
+            if package.has_target("proc-macro") && !self.allow_proc_macros {
+                return Err(format!(
+                    "{described} is a proc-macro crate, proc-macros are not allowed (SHEEET_ALLOW_PROC_MACROS)"
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Dependency tables of the manifest, including the target specific ones.
+pub fn dependency_tables(manifest: &toml::Table) -> Vec<&toml::Table> {
+    let mut tables: Vec<&toml::Table> = DEPENDENCY_TABLES
+        .iter()
+        .filter_map(|table| manifest.get(*table)?.as_table())
+        .collect();
+    if let Some(targets) = manifest.get("target").and_then(toml::Value::as_table) {
+        for target in targets.values().filter_map(toml::Value::as_table) {
+            tables.extend(
+                DEPENDENCY_TABLES
+                    .iter()
+                    .filter_map(|table| target.get(*table)?.as_table()),
+            );
+        }
+    }
+    tables
+}
+
+/// Parses comma separated `name` or `name@requirement` entries, e.g. `wasm-bindgen@0.2, sheeet-funcs`.
+pub fn parse_allowlist(value: &str) -> Result<HashMap<String, VersionReq>, String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| match entry.split_once('@') {
+            Some((name, requirement)) => VersionReq::parse(requirement.trim())
+                .map(|requirement| (name.trim().to_string(), requirement))
+                .map_err(|err| format!("invalid version requirement in '{entry}': {err}")),
+            None => Ok((entry.to_string(), VersionReq::STAR)),
+        })
+        .collect()
+}
+
+/// Subset of the `cargo metadata` output.
+#[derive(Deserialize)]
+pub struct Metadata {
+    packages: Vec<Package>,
+    resolve: Resolve,
+}
+
+#[derive(Deserialize)]
+struct Package {
+    id: String,
+    name: String,
+    version: String,
+    /// `None` for path dependencies and the user crate itself.
+    source: Option<String>,
+    targets: Vec<Target>,
+}
+
+impl Package {
+    fn has_target(&self, kind: &str) -> bool {
+        self.targets
+            .iter()
+            .any(|target| target.kind.iter().any(|target_kind| target_kind == kind))
+    }
+}
+
+#[derive(Deserialize)]
+struct Target {
+    kind: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct Resolve {
+    root: Option<String>,
+    nodes: Vec<Node>,
+}
+
+#[derive(Deserialize)]
+struct Node {
+    id: String,
+    dependencies: Vec<String>,
+}
+
+/// Resolves the workspace dependencies for the wasm target, without building anything.
+pub fn resolve(workspace_path: &Path) -> Result<Metadata, String> {
+    let output = Command::new("cargo")
+        .args(["metadata", "--format-version", "1"])
+        .args(["--filter-platform", "wasm32-unknown-unknown"])
+        .current_dir(workspace_path)
+        .output()
+        .map_err(|err| format!("failed to run cargo metadata: {err}"))?;
+    if !output.status.success() {
+        return Err(format!(
+            "failed to resolve dependencies: {}\n{}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    serde_json::from_slice(&output.stdout)
+        .map_err(|err| format!("invalid cargo metadata output: {err}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn restrictive(allowlist: Option<&str>) -> DependencyPolicy {
+        DependencyPolicy {
+            allowlist: allowlist.map(|allowlist| parse_allowlist(allowlist).unwrap()),
+            allow_build_scripts: false,
+            allow_proc_macros: false,
+            allow_git_dependencies: false,
+            allow_path_dependencies: false,
+        }
+    }
+
+    #[test]
+    fn test_parse_allowlist() {
+        let allowlist = parse_allowlist("wasm-bindgen@0.2, sheeet-funcs ,,").unwrap();
+        assert_eq!(allowlist.len(), 2);
+        assert_eq!(
+            allowlist["wasm-bindgen"],
+            VersionReq::parse("^0.2").unwrap()
+        );
+        assert_eq!(allowlist["sheeet-funcs"], VersionReq::STAR);
+        parse_allowlist("serde@latest").expect_err("expected invalid requirement");
+    }
+
+    #[test]
+    fn test_check_manifest() {
+        let policy = restrictive(None);
+        let cargo_toml = include_str!("user_cargo.toml");
+        assert_eq!(policy.check_manifest(cargo_toml), Ok(()));
+
+        for (dependency, rule) in [
+            (
+                r#"evil = { git = "https://example.com/evil" }"#,
+                "SHEEET_ALLOW_GIT",
+            ),
+            (r#"evil = { path = "/etc" }"#, "SHEEET_ALLOW_PATH"),
+        ] {
+            let err = policy
+                .check_manifest(&format!("{cargo_toml}{dependency}\n"))
+                .expect_err(dependency);
+            assert!(err.contains(rule), "{err}");
+        }
+        let patched =
+            format!("{cargo_toml}\n[patch.crates-io]\nwasm-bindgen = {{ path = \"/tmp\" }}\n");
+        policy.check_manifest(&patched).expect_err("expected patch");
+
+        let allowlisted = restrictive(Some("wasm-bindgen, sheeet-funcs"));
+        assert_eq!(allowlisted.check_manifest(cargo_toml), Ok(()));
+        let renamed =
+            format!("{cargo_toml}funcs = {{ package = \"sheeet-funcs\", version = \"0.1\" }}\n");
+        assert_eq!(allowlisted.check_manifest(&renamed), Ok(()));
+        let err = allowlisted
+            .check_manifest(&format!("{cargo_toml}serde = \"1\"\n"))
+            .expect_err("expected not allowlisted");
+        assert!(err.contains("'serde'"), "{err}");
+    }
+
+    /// Name, version, source, target kinds and dependency names.
+    type TestPackage<'a> = (
+        &'a str,
+        &'a str,
+        Option<&'a str>,
+        &'a [&'a str],
+        &'a [&'a str],
+    );
+
+    fn metadata(packages: &[TestPackage]) -> Metadata {
+        let id = |name: &str| format!("{name}-id");
+        Metadata {
+            packages: packages
+                .iter()
+                .map(|(name, version, source, kinds, _)| Package {
+                    id: id(name),
+                    name: name.to_string(),
+                    version: version.to_string(),
+                    source: source.map(String::from),
+                    targets: kinds
+                        .iter()
+                        .map(|kind| Target {
+                            kind: vec![kind.to_string()],
+                        })
+                        .collect(),
+                })
+                .collect(),
+            resolve: Resolve {
+                root: Some(id(packages[0].0)),
+                nodes: packages
+                    .iter()
+                    .map(|(name, _, _, _, dependencies)| Node {
+                        id: id(name),
+                        dependencies: dependencies.iter().map(|name| id(name)).collect(),
+                    })
+                    .collect(),
+            },
+        }
+    }
+
+    const REGISTRY: Option<&str> = Some("registry+https://github.com/rust-lang/crates.io-index");
+
+    #[test]
+    fn test_check_metadata() {
+        let default = metadata(&[
+            ("sheeet-lib", "0.0.0", None, &["cdylib"], &["wasm-bindgen"]),
+            (
+                "wasm-bindgen",
+                "0.2.100",
+                REGISTRY,
+                &["lib", "custom-build"],
+                &["wasm-bindgen-macro"],
+            ),
+            (
+                "wasm-bindgen-macro",
+                "0.2.100",
+                REGISTRY,
+                &["proc-macro"],
+                &[],
+            ),
+        ]);
+        assert_eq!(restrictive(None).check_metadata(&default), Ok(()));
+        assert_eq!(
+            restrictive(Some("wasm-bindgen@0.2")).check_metadata(&default),
+            Ok(())
+        );
+        let err = restrictive(Some("wasm-bindgen@0.3"))
+            .check_metadata(&default)
+            .expect_err("expected version mismatch");
+        assert!(err.contains("0.2.100"), "{err}");
+
+        let with_serde = metadata(&[
+            ("sheeet-lib", "0.0.0", None, &["cdylib"], &["serde"]),
+            ("serde", "1.0.0", REGISTRY, &["lib"], &["serde_derive"]),
+            ("serde_derive", "1.0.0", REGISTRY, &["proc-macro"], &[]),
+        ]);
+        let err = restrictive(None)
+            .check_metadata(&with_serde)
+            .expect_err("expected proc-macro");
+        assert!(err.contains("SHEEET_ALLOW_PROC_MACROS"), "{err}");
+        // Allowlisted crates are trusted with their whole dependency tree.
+        assert_eq!(
+            restrictive(Some("serde")).check_metadata(&with_serde),
+            Ok(())
+        );
+
+        let with_build_script = metadata(&[(
+            "sheeet-lib",
+            "0.0.0",
+            None,
+            &["cdylib", "custom-build"],
+            &[],
+        )]);
+        let err = restrictive(None)
+            .check_metadata(&with_build_script)
+            .expect_err("expected build script");
+        assert!(err.contains("the user crate"), "{err}");
+
+        let with_path = metadata(&[
+            ("sheeet-lib", "0.0.0", None, &["cdylib"], &["local"]),
+            ("local", "0.1.0", None, &["lib"], &[]),
+        ]);
+        let err = restrictive(None)
+            .check_metadata(&with_path)
+            .expect_err("expected path dependency");
+        assert!(err.contains("SHEEET_ALLOW_PATH_DEPENDENCIES"), "{err}");
+    }
+}
@@ -0,0 +1,114 @@
+use rand::Rng;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const CHALLENGE_LENGTH: usize = 24;
+const CHALLENGE_TTL: Duration = Duration::from_secs(5 * 60);
+/// Unsolved challenges are swept once there are more of them.
+const SWEEP_THRESHOLD: usize = 1024;
+
+#[derive(Serialize)]
+pub struct Challenge {
+    pub challenge: String,
+    /// Required leading zero bits of the solution hash.
+    pub difficulty: u32,
+}
+
+/// Hashcash-like proof of work, anonymous callers of public instances pay for each compile
+/// with the CPU time of their browser.
+///
+/// The solution is `{challenge}:{nonce}`, whose sha256 starts with `difficulty` zero bits.
+/// Challenges are issued by the server and accepted only once.
+pub struct ProofOfWork {
+    difficulty: u32,
+    issued: Mutex<HashMap<String, Instant>>,
+}
+
+fn leading_zero_bits(hash: &[u8]) -> u32 {
+    let mut bits = 0;
+    for byte in hash {
+        bits += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    bits
+}
+
+impl ProofOfWork {
+    pub fn new(difficulty: u32) -> Self {
+        ProofOfWork {
+            difficulty,
+            issued: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn issue(&self) -> Challenge {
+        let challenge: String = rand::rng()
+            .sample_iter(&rand::distr::Alphabetic)
+            .take(CHALLENGE_LENGTH)
+            .map(char::from)
+            .collect();
+        let now = Instant::now();
+        let mut issued = self.issued.lock().expect("proof of work poisoned");
+        if issued.len() > SWEEP_THRESHOLD {
+            issued.retain(|_, issued_at| now.duration_since(*issued_at) < CHALLENGE_TTL);
+        }
+        issued.insert(challenge.clone(), now);
+        Challenge {
+            challenge,
+            difficulty: self.difficulty,
+        }
+    }
+
+    pub fn verify(&self, solution: &str) -> bool {
+        let Some((challenge, _)) = solution.split_once(':') else {
+            return false;
+        };
+        if challenge.len() != CHALLENGE_LENGTH {
+            return false;
+        }
+        if leading_zero_bits(&Sha256::digest(solution)) < self.difficulty {
+            return false;
+        }
+        let mut issued = self.issued.lock().expect("proof of work poisoned");
+        issued
+            .remove(challenge)
+            .is_some_and(|issued_at| issued_at.elapsed() < CHALLENGE_TTL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solve(challenge: &Challenge, valid: bool) -> String {
+        (0..)
+            .map(|nonce| format!("{}:{nonce}", challenge.challenge))
+            .find(|solution| {
+                (leading_zero_bits(&Sha256::digest(solution)) >= challenge.difficulty) == valid
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn test_verify() {
+        let proof_of_work = ProofOfWork::new(8);
+        let challenge = proof_of_work.issue();
+        assert!(!proof_of_work.verify(&solve(&challenge, false)));
+        let solution = solve(&challenge, true);
+        assert!(proof_of_work.verify(&solution));
+        // Accepted only once.
+        assert!(!proof_of_work.verify(&solution));
+
+        let unissued = Challenge {
+            challenge: "a".repeat(CHALLENGE_LENGTH),
+            difficulty: 8,
+        };
+        assert!(!proof_of_work.verify(&solve(&unissued, true)));
+        assert_eq!(leading_zero_bits(&[0, 0b0001_0000, 0xff]), 11);
+    }
+}
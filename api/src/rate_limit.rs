@@ -0,0 +1,169 @@
+use actix_web::HttpRequest;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Buckets are swept of the refilled ones once there are more of them.
+const SWEEP_THRESHOLD: usize = 1024;
+
+/// Budget of `requests` per `period`, written as `{requests}/{period_secs}`, e.g. `10/60`.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(try_from = "String")]
+pub struct RateLimit {
+    pub requests: u32,
+    pub period: Duration,
+}
+
+impl FromStr for RateLimit {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (requests, period_secs) = value
+            .split_once('/')
+            .ok_or_else(|| format!("invalid rate limit '{value}', expected e.g. '10/60'"))?;
+        let requests: u32 = requests
+            .trim()
+            .parse()
+            .map_err(|err| format!("invalid rate limit '{value}' requests: {err}"))?;
+        let period_secs: u64 = period_secs
+            .trim()
+            .parse()
+            .map_err(|err| format!("invalid rate limit '{value}' period: {err}"))?;
+        if requests == 0 || period_secs == 0 {
+            return Err(format!(
+                "invalid rate limit '{value}', requests and period must be positive"
+            ));
+        }
+        Ok(RateLimit {
+            requests,
+            period: Duration::from_secs(period_secs),
+        })
+    }
+}
+
+impl TryFrom<String> for RateLimit {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+/// Token bucket per key, full buckets allow bursts of the whole budget.
+pub struct RateLimiter {
+    limit: RateLimit,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: RateLimit) -> Self {
+        RateLimiter {
+            limit,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Takes one request from the key's budget, or returns how long until there is one.
+    pub fn acquire(&self, key: &str) -> Result<(), Duration> {
+        self.acquire_at(key, Instant::now())
+    }
+
+    fn acquire_at(&self, key: &str, now: Instant) -> Result<(), Duration> {
+        let capacity = f64::from(self.limit.requests);
+        let refill_per_sec = capacity / self.limit.period.as_secs_f64();
+        let mut buckets = self.buckets.lock().expect("rate limiter poisoned");
+        if buckets.len() > SWEEP_THRESHOLD {
+            let period = self.limit.period;
+            buckets.retain(|_, bucket| now.duration_since(bucket.updated_at) < period);
+        }
+
+        let bucket = buckets.entry(key.into()).or_insert(Bucket {
+            tokens: capacity,
+            updated_at: now,
+        });
+        let elapsed = now.duration_since(bucket.updated_at).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * refill_per_sec).min(capacity);
+        bucket.updated_at = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(self.limit.period.mul_f64((1.0 - bucket.tokens) / capacity))
+        }
+    }
+}
+
+/// Rate limits key on the client IP address. Behind a proxy it is the last forwarded one,
+/// appended by the trusted proxy, the client can send any entries before it.
+pub fn client_ip(req: &HttpRequest, trust_proxy_headers: bool) -> String {
+    let forwarded = req
+        .headers()
+        .get_all("X-Forwarded-For")
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|ip| !ip.is_empty())
+        .last()
+        .filter(|_| trust_proxy_headers);
+    match forwarded {
+        Some(ip) => ip.into(),
+        None => req
+            .peer_addr()
+            .map(|addr| addr.ip().to_string())
+            .unwrap_or_default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_acquire() {
+        let limiter = RateLimiter::new("2/60".parse().unwrap());
+        let start = Instant::now();
+        let acquire = |key, at| {
+            limiter
+                .acquire_at(key, at)
+                .map_err(|retry_after| retry_after.as_secs_f64().round())
+        };
+        assert_eq!(acquire("a", start), Ok(()));
+        assert_eq!(acquire("a", start), Ok(()));
+        assert_eq!(acquire("a", start), Err(30.0));
+        // Other keys have their own budget.
+        assert_eq!(acquire("b", start), Ok(()));
+
+        let later = start + Duration::from_secs(20);
+        assert_eq!(acquire("a", later), Err(10.0));
+        assert_eq!(acquire("a", later + Duration::from_secs(10)), Ok(()));
+
+        for invalid in ["10", "0/60", "10/0", "ten/60"] {
+            invalid.parse::<RateLimit>().expect_err(invalid);
+        }
+    }
+
+    #[test]
+    fn test_client_ip() {
+        let req = actix_web::test::TestRequest::default()
+            .peer_addr("10.0.0.2:4000".parse().unwrap())
+            // The client spoofs the leading entries, the proxy appends the client's address.
+            .append_header(("X-Forwarded-For", "1.1.1.1, 2.2.2.2"))
+            .append_header(("X-Forwarded-For", "203.0.113.7"))
+            .to_http_request();
+        assert_eq!(client_ip(&req, true), "203.0.113.7");
+        assert_eq!(client_ip(&req, false), "10.0.0.2");
+
+        let req = actix_web::test::TestRequest::default()
+            .peer_addr("10.0.0.2:4000".parse().unwrap())
+            .insert_header(("X-Forwarded-For", "1.1.1.1, 203.0.113.7"))
+            .to_http_request();
+        assert_eq!(client_ip(&req, true), "203.0.113.7");
+    }
+}
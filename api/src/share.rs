@@ -0,0 +1,71 @@
+use crate::builds::{self, Build};
+use crate::functions::Function;
+use crate::source::Source;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+/// Directory inside the workspaces path holding the share snapshots, served by the artifacts
+/// file service. It can't collide with workspace IDs, those are always 12 letters long.
+pub const SHARES_DIR: &str = "shares";
+/// Hidden, so the snapshot is served only through the API.
+const SNAPSHOT_FILE: &str = ".snapshot.json";
+
+/// Read-only snapshot of the workspace's sheet data and code.
+#[derive(Serialize, Deserialize)]
+pub struct Snapshot {
+    /// Serialized sheet state, as the GUI keeps it in the local storage.
+    pub sheet_data: serde_json::Value,
+    #[serde(flatten)]
+    pub source: Source,
+    pub js_download_url: String,
+    pub wasm_download_url: String,
+    /// Functions exported by the shared build, unknown for the snapshots shared before
+    /// the manifests.
+    #[serde(default)]
+    pub functions: Option<Vec<Function>>,
+}
+
+pub fn share_path(workspaces_path: &Path, token: &str) -> PathBuf {
+    workspaces_path.join(SHARES_DIR).join(token)
+}
+
+/// Stores the snapshot together with the build's artifacts under the token, the source must
+/// be the build's revision.
+pub fn create(
+    workspaces_path: &Path,
+    workspace_path: &Path,
+    token: &str,
+    sheet_data: serde_json::Value,
+    build: &Build,
+    source: Source,
+) -> io::Result<Snapshot> {
+    let build_path = builds::build_path(workspace_path, &build.build_id);
+    let share_path = share_path(workspaces_path, token);
+    fs::create_dir_all(share_path.join("dist"))?;
+    for artifact in builds::ARTIFACTS {
+        fs::copy(
+            build_path.join(artifact),
+            share_path.join("dist").join(artifact),
+        )?;
+    }
+
+    let download_url = |artifact: &str| format!("/workspaces/{SHARES_DIR}/{token}/dist/{artifact}");
+    let snapshot = Snapshot {
+        sheet_data,
+        source,
+        js_download_url: download_url(builds::ARTIFACTS[0]),
+        wasm_download_url: download_url(builds::ARTIFACTS[1]),
+        functions: builds::load_functions(&build_path)?,
+    };
+    fs::write(
+        share_path.join(SNAPSHOT_FILE),
+        serde_json::to_string(&snapshot)?,
+    )?;
+    Ok(snapshot)
+}
+
+pub fn load(workspaces_path: &Path, token: &str) -> io::Result<Snapshot> {
+    let content = fs::read_to_string(share_path(workspaces_path, token).join(SNAPSHOT_FILE))?;
+    serde_json::from_str(&content).map_err(io::Error::other)
+}
@@ -0,0 +1,427 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{fs, io};
+
+/// Hidden directory inside the workspace, so it is not served by the artifacts file service.
+const REVISIONS_DIR: &str = ".revisions";
+
+pub const CARGO_TOML: &str = "Cargo.toml";
+pub const LIB_RS: &str = "src/lib.rs";
+/// Top level files, that can be part of the user crate next to the `src` and `tests` directories.
+const ALLOWED_ROOT_FILES: [&str; 3] = [CARGO_TOML, "README.md", "README"];
+/// Directories, whose files are owned completely by the user crate.
+const ALLOWED_ROOT_DIRS: [&str; 2] = ["src", "tests"];
+const MAX_FILES: usize = 256;
+
+/// User crate sources submitted by the GUI, file paths relative to the workspace root mapped
+/// to the file contents.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(from = "SourceRepr")]
+pub struct Source {
+    pub files: BTreeMap<String, String>,
+}
+
+/// Accepts also the single `src/lib.rs` source, stored in older revisions and sent by older GUIs.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SourceRepr {
+    Files { files: BTreeMap<String, String> },
+    LibRs { lib_rs: String, cargo_toml: String },
+}
+
+impl From<SourceRepr> for Source {
+    fn from(repr: SourceRepr) -> Self {
+        match repr {
+            SourceRepr::Files { files } => Source { files },
+            SourceRepr::LibRs { lib_rs, cargo_toml } => Source {
+                files: BTreeMap::from([(CARGO_TOML.into(), cargo_toml), (LIB_RS.into(), lib_rs)]),
+            },
+        }
+    }
+}
+
+impl Source {
+    pub fn cargo_toml(&self) -> &str {
+        self.files.get(CARGO_TOML).map_or("", String::as_str)
+    }
+
+    /// Checks the required files are present and all paths stay inside the user crate.
+    pub fn validate(&self) -> Result<(), String> {
+        for required in [CARGO_TOML, LIB_RS] {
+            if !self.files.contains_key(required) {
+                return Err(format!("missing required file '{required}'"));
+            }
+        }
+        if self.files.len() > MAX_FILES {
+            return Err(format!("too many files, at most {MAX_FILES} allowed"));
+        }
+        for path in self.files.keys() {
+            validate_path(path)?;
+        }
+        Ok(())
+    }
+}
+
+fn validate_path(path: &str) -> Result<(), String> {
+    let components: Vec<&str> = path.split('/').collect();
+    for component in &components {
+        if component.is_empty()
+            || component.starts_with('.')
+            || component.contains(['\\', ':', '\0'])
+        {
+            return Err(format!("invalid file path '{path}'"));
+        }
+    }
+    match components.as_slice() {
+        [file] if ALLOWED_ROOT_FILES.contains(file) => Ok(()),
+        [dir, _, ..] if ALLOWED_ROOT_DIRS.contains(dir) => Ok(()),
+        _ => Err(format!(
+            "file path '{path}' not allowed, only {ALLOWED_ROOT_FILES:?} and files in {ALLOWED_ROOT_DIRS:?} directories"
+        )),
+    }
+}
+
+/// Type of the file, `None` if it doesn't exist. Symlinks aren't followed.
+fn file_type(path: &Path) -> io::Result<Option<fs::FileType>> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) => Ok(Some(metadata.file_type())),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Collects files in the directory recursively, keyed by the path relative to the `root`.
+/// Symlinks are skipped, they are never part of the source.
+fn collect_files(root: &Path, dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    if !file_type(dir)?.is_some_and(|file_type| file_type.is_dir()) {
+        return Ok(());
+    }
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let path = entry.path();
+        if file_type.is_dir() {
+            collect_files(root, &path, files)?;
+        } else if file_type.is_file()
+            && let Ok(relative) = path.strip_prefix(root)
+        {
+            files.push(relative.to_path_buf());
+        }
+    }
+    Ok(())
+}
+
+fn workspace_source_files(workspace_path: &Path) -> io::Result<Vec<String>> {
+    let mut files = Vec::new();
+    for dir in ALLOWED_ROOT_DIRS {
+        collect_files(workspace_path, &workspace_path.join(dir), &mut files)?;
+    }
+    for file in ALLOWED_ROOT_FILES {
+        if file_type(&workspace_path.join(file))?.is_some_and(|file_type| file_type.is_file()) {
+            files.push(PathBuf::from(file));
+        }
+    }
+    Ok(files
+        .into_iter()
+        .filter_map(|path| path.to_str().map(|path| path.replace('\\', "/")))
+        .collect())
+}
+
+/// Path of the source file in the workspace. Symlinks on the way are removed (not their
+/// targets), they are never part of the source and could point the writes outside
+/// of the workspace.
+fn workspace_file(workspace_path: &Path, path: &str) -> io::Result<PathBuf> {
+    let path = Path::new(path);
+    let mut ancestors: Vec<&Path> = path.ancestors().collect();
+    ancestors.reverse();
+    for ancestor in ancestors
+        .into_iter()
+        .filter(|ancestor| !ancestor.as_os_str().is_empty())
+    {
+        let ancestor = workspace_path.join(ancestor);
+        match file_type(&ancestor)? {
+            Some(file_type) if file_type.is_symlink() => fs::remove_file(&ancestor)?,
+            Some(_) => {}
+            None => break,
+        }
+    }
+    Ok(workspace_path.join(path))
+}
+
+/// Writes the source files to the workspace and removes the user crate files, that are
+/// no longer part of the source.
+pub fn write_source(workspace_path: &Path, source: &Source) -> io::Result<()> {
+    for path in workspace_source_files(workspace_path)? {
+        if !source.files.contains_key(&path) {
+            fs::remove_file(workspace_file(workspace_path, &path)?)?;
+        }
+    }
+    fs::create_dir_all(workspace_path)?;
+    let root = fs::canonicalize(workspace_path)?;
+    for (path, content) in &source.files {
+        let file = workspace_file(workspace_path, path)?;
+        if let Some(parent) = file.parent() {
+            fs::create_dir_all(parent)?;
+            if !fs::canonicalize(parent)?.starts_with(&root) {
+                return Err(io::Error::other(format!(
+                    "file '{path}' is outside of the workspace"
+                )));
+            }
+        }
+        fs::write(file, content)?;
+    }
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SourceRevision {
+    pub revision: u32,
+    /// Unix timestamp in seconds.
+    pub created_at: u64,
+    #[serde(flatten)]
+    pub source: Source,
+}
+
+#[derive(Serialize, Debug)]
+pub struct RevisionInfo {
+    pub revision: u32,
+    pub created_at: u64,
+}
+
+fn revisions_path(workspace_path: &Path) -> PathBuf {
+    workspace_path.join(REVISIONS_DIR)
+}
+
+fn revision_path(workspace_path: &Path, revision: u32) -> PathBuf {
+    revisions_path(workspace_path).join(format!("{revision:06}.json"))
+}
+
+/// Returns sorted revision numbers stored in the workspace.
+pub fn list_revision_numbers(workspace_path: &Path) -> io::Result<Vec<u32>> {
+    let mut revisions = Vec::new();
+    let entries = match fs::read_dir(revisions_path(workspace_path)) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(revisions),
+        Err(err) => return Err(err),
+    };
+    for entry in entries {
+        let file_name = entry?.file_name();
+        if let Some(revision) = file_name
+            .to_str()
+            .and_then(|name| name.strip_suffix(".json"))
+            .and_then(|name| name.parse().ok())
+        {
+            revisions.push(revision);
+        }
+    }
+    revisions.sort();
+    Ok(revisions)
+}
+
+pub fn list_revisions(workspace_path: &Path) -> io::Result<Vec<RevisionInfo>> {
+    list_revision_numbers(workspace_path)?
+        .into_iter()
+        .map(|revision| {
+            load_revision(workspace_path, revision).map(|loaded| RevisionInfo {
+                revision,
+                created_at: loaded.created_at,
+            })
+        })
+        .collect()
+}
+
+pub fn load_revision(workspace_path: &Path, revision: u32) -> io::Result<SourceRevision> {
+    let content = fs::read_to_string(revision_path(workspace_path, revision))?;
+    serde_json::from_str(&content).map_err(io::Error::other)
+}
+
+/// Loads the given revision, or the latest one if `revision` is `None`.
+pub fn load_source(workspace_path: &Path, revision: Option<u32>) -> io::Result<SourceRevision> {
+    let revision = match revision {
+        Some(revision) => revision,
+        None => *list_revision_numbers(workspace_path)?
+            .last()
+            .ok_or_else(|| io::Error::new(ErrorKind::NotFound, "no source revisions"))?,
+    };
+    load_revision(workspace_path, revision)
+}
+
+/// Stores the source as a new revision, unless it is the same as the latest one.
+/// Returns the revision number the source is stored under.
+pub fn save_revision(workspace_path: &Path, source: &Source) -> io::Result<u32> {
+    fs::create_dir_all(revisions_path(workspace_path))?;
+    loop {
+        let latest = list_revision_numbers(workspace_path)?.last().copied();
+        if let Some(latest) = latest
+            && load_revision(workspace_path, latest)?.source == *source
+        {
+            return Ok(latest);
+        }
+
+        let revision = latest.map_or(1, |latest| latest + 1);
+        let source_revision = SourceRevision {
+            revision,
+            created_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or_default(),
+            source: source.clone(),
+        };
+        // Concurrent compile of the same workspace could take the same number, retry then.
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(revision_path(workspace_path, revision))
+        {
+            Ok(mut file) => {
+                file.write_all(serde_json::to_string(&source_revision)?.as_bytes())?;
+                return Ok(revision);
+            }
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(files: &[(&str, &str)]) -> Source {
+        Source {
+            files: files
+                .iter()
+                .map(|(path, content)| (path.to_string(), content.to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let valid = source(&[
+            (CARGO_TOML, ""),
+            (LIB_RS, ""),
+            ("src/stats/mod.rs", ""),
+            ("tests/integration.rs", ""),
+            ("README.md", ""),
+        ]);
+        assert_eq!(valid.validate(), Ok(()));
+
+        source(&[(LIB_RS, "")])
+            .validate()
+            .expect_err("expected missing Cargo.toml");
+        for path in [
+            "../Cargo.toml",
+            "src/../../etc/passwd",
+            "/etc/passwd",
+            "src//lib.rs",
+            "src/.hidden.rs",
+            "build.rs",
+            "index.html",
+            "dist/sheeet-lib.js",
+            "src",
+            "src\\lib.rs",
+        ] {
+            source(&[(CARGO_TOML, ""), (LIB_RS, ""), (path, "")])
+                .validate()
+                .expect_err(path);
+        }
+    }
+
+    #[test]
+    fn test_deserialize_lib_rs_source() {
+        let source: Source =
+            serde_json::from_str(r#"{"lib_rs": "// lib", "cargo_toml": "[package]"}"#).unwrap();
+        assert_eq!(source.cargo_toml(), "[package]");
+        assert_eq!(source.files.get(LIB_RS).unwrap(), "// lib");
+    }
+
+    /// Reads the source currently checked out in the workspace.
+    fn read_workspace_source(workspace_path: &Path) -> io::Result<Source> {
+        let mut files = BTreeMap::new();
+        for path in workspace_source_files(workspace_path)? {
+            let content = fs::read_to_string(workspace_path.join(&path))?;
+            files.insert(path, content);
+        }
+        Ok(Source { files })
+    }
+
+    #[test]
+    fn test_write_and_read_workspace_source() {
+        let workspace_path =
+            std::env::temp_dir().join(format!("sheeet-source-test-{}", rand::random::<u32>()));
+        let first = source(&[
+            (CARGO_TOML, "[package]"),
+            (LIB_RS, "mod stats;"),
+            ("src/stats.rs", "// stats"),
+        ]);
+        write_source(&workspace_path, &first).unwrap();
+        assert_eq!(read_workspace_source(&workspace_path).unwrap(), first);
+
+        // Removed module is removed from the workspace as well.
+        let second = source(&[(CARGO_TOML, "[package]"), (LIB_RS, "// lib")]);
+        write_source(&workspace_path, &second).unwrap();
+        assert_eq!(read_workspace_source(&workspace_path).unwrap(), second);
+
+        fs::remove_dir_all(&workspace_path).unwrap();
+    }
+
+    #[test]
+    fn test_write_source_through_symlinks() {
+        let path =
+            std::env::temp_dir().join(format!("sheeet-source-test-{}", rand::random::<u32>()));
+        let (workspace_path, outside_path) = (path.join("workspace"), path.join("outside"));
+        fs::create_dir_all(&workspace_path).unwrap();
+        fs::create_dir_all(&outside_path).unwrap();
+        fs::write(outside_path.join("lib.rs"), "// outside").unwrap();
+        fs::write(outside_path.join("passwd"), "root").unwrap();
+        std::os::unix::fs::symlink(&outside_path, workspace_path.join("src")).unwrap();
+        std::os::unix::fs::symlink(outside_path.join("passwd"), workspace_path.join(CARGO_TOML))
+            .unwrap();
+
+        // The symlinked files aren't the workspace source.
+        assert_eq!(read_workspace_source(&workspace_path).unwrap(), source(&[]));
+
+        let written = source(&[(CARGO_TOML, "[package]"), (LIB_RS, "// lib")]);
+        write_source(&workspace_path, &written).unwrap();
+        assert_eq!(read_workspace_source(&workspace_path).unwrap(), written);
+        assert!(
+            !fs::symlink_metadata(workspace_path.join("src"))
+                .unwrap()
+                .is_symlink()
+        );
+        assert_eq!(
+            fs::read_to_string(outside_path.join("lib.rs")).unwrap(),
+            "// outside"
+        );
+        assert_eq!(
+            fs::read_to_string(outside_path.join("passwd")).unwrap(),
+            "root"
+        );
+
+        fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn test_save_and_load_revisions() {
+        let workspace_path =
+            std::env::temp_dir().join(format!("sheeet-source-test-{}", rand::random::<u32>()));
+        let first = source(&[(CARGO_TOML, "[package]"), (LIB_RS, "// first")]);
+        let second = source(&[(CARGO_TOML, "[package]"), (LIB_RS, "// second")]);
+
+        load_source(&workspace_path, None).expect_err("expected no revisions");
+        assert_eq!(save_revision(&workspace_path, &first).unwrap(), 1);
+        assert_eq!(save_revision(&workspace_path, &first).unwrap(), 1); // Unchanged.
+        assert_eq!(save_revision(&workspace_path, &second).unwrap(), 2);
+
+        assert_eq!(load_source(&workspace_path, None).unwrap().source, second);
+        assert_eq!(load_source(&workspace_path, Some(1)).unwrap().source, first);
+        assert_eq!(list_revisions(&workspace_path).unwrap().len(), 2);
+
+        fs::remove_dir_all(&workspace_path).unwrap();
+    }
+}
@@ -0,0 +1,303 @@
+use crate::AppConfig;
+use crate::build::{self, StreamEvent, StreamingResponder};
+use crate::metrics::FailureReason;
+use crate::source::Source;
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How the user crate tests are run, unlike the builds they execute the user code.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TestRunner {
+    /// Natively on the server, for instances trusting their users.
+    Native,
+    /// Compiled to `wasm32-wasip1` and sandboxed by `wasmtime`.
+    Wasmtime,
+}
+
+impl FromStr for TestRunner {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "native" => Ok(TestRunner::Native),
+            "wasmtime" => Ok(TestRunner::Wasmtime),
+            _ => Err("expected 'native' or 'wasmtime'".into()),
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TestOutcome {
+    Passed,
+    Failed,
+    Ignored,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct TestResult {
+    pub name: String,
+    pub outcome: TestOutcome,
+    /// Captured stdout and stderr of the test.
+    pub output: String,
+}
+
+/// Sent last, once all the test binaries finished.
+#[derive(Serialize, Default, Debug, PartialEq)]
+pub struct TestSummary {
+    pub passed: u32,
+    pub failed: u32,
+    pub ignored: u32,
+}
+
+/// Parses the libtest human readable output, JSON output is unstable.
+#[derive(Default)]
+struct TestOutputParser {
+    /// Results of the running test binary, its captured outputs follow them.
+    results: Vec<TestResult>,
+    /// Test name and lines of the output section being read.
+    section: Option<(String, Vec<String>)>,
+    summary: TestSummary,
+}
+
+impl TestOutputParser {
+    /// Returns the test binary's results once it finishes.
+    fn line(&mut self, line: &str) -> Vec<TestResult> {
+        if let Some((name, outcome)) = line
+            .strip_prefix("test ")
+            .and_then(|line| line.rsplit_once(" ... "))
+        {
+            let outcome = match outcome {
+                "ok" => TestOutcome::Passed,
+                "FAILED" => TestOutcome::Failed,
+                outcome if outcome.starts_with("ignored") => TestOutcome::Ignored,
+                _ => return Vec::new(),
+            };
+            self.results.push(TestResult {
+                name: name.into(),
+                outcome,
+                output: String::new(),
+            });
+            return Vec::new();
+        }
+
+        if let Some(name) = line
+            .strip_prefix("---- ")
+            .and_then(|line| line.strip_suffix(" stdout ----"))
+        {
+            self.finish_section();
+            self.section = Some((name.into(), Vec::new()));
+        } else if line == "failures:" || line == "successes:" {
+            self.finish_section();
+        } else if line.starts_with("test result: ") {
+            self.finish_section();
+            for result in &self.results {
+                match result.outcome {
+                    TestOutcome::Passed => self.summary.passed += 1,
+                    TestOutcome::Failed => self.summary.failed += 1,
+                    TestOutcome::Ignored => self.summary.ignored += 1,
+                }
+            }
+            return std::mem::take(&mut self.results);
+        } else if let Some((_, lines)) = &mut self.section {
+            lines.push(line.into());
+        }
+        Vec::new()
+    }
+
+    fn finish_section(&mut self) {
+        let Some((name, lines)) = self.section.take() else {
+            return;
+        };
+        if let Some(result) = self.results.iter_mut().find(|result| result.name == name) {
+            result.output = lines.join("\n").trim().into();
+        }
+    }
+}
+
+/// Writes the source to the workspace and runs its tests, streaming the results
+/// to the responder.
+pub fn run_tests(
+    config: &AppConfig,
+    workspace_path: &Path,
+    body: &Source,
+    responder: &StreamingResponder,
+) {
+    if build::prepare_workspace(config, workspace_path, body, responder).is_err() {
+        return;
+    }
+    _ = build::with_build_cache(config, workspace_path, responder, |target_dir| {
+        run_cargo_test(config, workspace_path, target_dir, responder)
+    });
+}
+
+fn run_cargo_test(
+    config: &AppConfig,
+    workspace_path: &Path,
+    target_dir: PathBuf,
+    responder: &StreamingResponder,
+) -> Result<(), FailureReason> {
+    let mut cargo_test = Command::new("cargo");
+    cargo_test
+        .args(["test", "--color", "never"])
+        .current_dir(workspace_path)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        // Own process group, so the test binaries spawned by cargo can be killed with it.
+        .process_group(0);
+    if config.test_runner == TestRunner::Wasmtime {
+        cargo_test
+            .args(["--target", "wasm32-wasip1"])
+            .env("CARGO_TARGET_WASM32_WASIP1_RUNNER", "wasmtime");
+    }
+    cargo_test.env("CARGO_TARGET_DIR", target_dir);
+    cargo_test.args(["--", "--show-output"]);
+
+    let _active = config.metrics.active();
+    let mut child = match cargo_test.spawn() {
+        Ok(child) => child,
+        Err(err) => {
+            responder.terminate_error(format!("failed to spawn cargo test: {err}"));
+            return Err(FailureReason::Internal);
+        }
+    };
+    if let Some(stderr) = child.stderr.take() {
+        responder.spawn_buff_line_reading(stderr, StreamEvent::StderrLine);
+    }
+    let stdout = child.stdout.take().map(|stdout| {
+        let sender = responder.sender();
+        thread::spawn(move || {
+            let mut parser = TestOutputParser::default();
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                for result in parser.line(&line) {
+                    _ = sender.send(StreamEvent::TestResult(result));
+                }
+            }
+            parser.summary
+        })
+    });
+
+    // Tests can loop forever, the whole run is limited.
+    let started = Instant::now();
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if started.elapsed() > config.test_timeout => {
+                // Negative PID is the process group, after `--` so it isn't read as an option.
+                match Command::new("kill")
+                    .args(["-KILL", "--", &format!("-{}", child.id())])
+                    .status()
+                {
+                    Ok(status) if status.success() => {}
+                    _ => _ = child.kill(),
+                }
+                _ = child.wait();
+                responder.terminate_error(format!(
+                    "tests timed out after {} s",
+                    config.test_timeout.as_secs()
+                ));
+                return Err(FailureReason::Build);
+            }
+            Ok(None) => thread::sleep(POLL_INTERVAL),
+            Err(err) => {
+                responder.terminate_error(format!("cargo test failed with err: {err}"));
+                return Err(FailureReason::Internal);
+            }
+        }
+    };
+    let summary = stdout
+        .and_then(|stdout| stdout.join().ok())
+        .unwrap_or_default();
+
+    if !status.success() && summary.failed == 0 {
+        responder.terminate_error(format!("tests failed to build: {status}"));
+        return Err(FailureReason::Build);
+    }
+    responder.send_event(StreamEvent::TestSummary(summary));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_output() {
+        let output = "
+running 3 tests
+test tests::adds ... ok
+test tests::divides ... FAILED
+test tests::slow ... ignored, takes ages
+
+successes:
+
+---- tests::adds stdout ----
+sum is 3
+
+successes:
+    tests::adds
+
+failures:
+
+---- tests::divides stdout ----
+
+thread 'tests::divides' panicked at src/lib.rs:10:9:
+attempt to divide by zero
+
+
+failures:
+    tests::divides
+
+test result: FAILED. 1 passed; 1 failed; 1 ignored; 0 measured; 0 filtered out; finished in 0.00s
+
+running 1 test
+test src/lib.rs - add (line 3) ... ok
+
+test result: ok. 1 passed; 0 failed; 0 ignored; 0 measured; 0 filtered out; finished in 0.00s
+";
+        let mut parser = TestOutputParser::default();
+        let results: Vec<TestResult> = output.lines().flat_map(|line| parser.line(line)).collect();
+        assert_eq!(
+            results,
+            [
+                TestResult {
+                    name: "tests::adds".into(),
+                    outcome: TestOutcome::Passed,
+                    output: "sum is 3".into(),
+                },
+                TestResult {
+                    name: "tests::divides".into(),
+                    outcome: TestOutcome::Failed,
+                    output: "thread 'tests::divides' panicked at src/lib.rs:10:9:\nattempt to divide by zero".into(),
+                },
+                TestResult {
+                    name: "tests::slow".into(),
+                    outcome: TestOutcome::Ignored,
+                    output: String::new(),
+                },
+                TestResult {
+                    name: "src/lib.rs - add (line 3)".into(),
+                    outcome: TestOutcome::Passed,
+                    output: String::new(),
+                },
+            ]
+        );
+        assert_eq!(
+            parser.summary,
+            TestSummary {
+                passed: 2,
+                failed: 1,
+                ignored: 1,
+            }
+        );
+    }
+}
@@ -0,0 +1,6 @@
+#[allow(unused)]
+use sheeet_funcs::prelude::*;
+use wasm_bindgen::prelude::*;
+
+// #[wasm_bindgen]
+// ...
@@ -0,0 +1,143 @@
+use argon2::Argon2;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::{fs, io};
+
+/// Optional user accounts, identified by their API keys in the `Authorization` header.
+///
+/// API keys have the `{user_id}.{secret}` format, only the argon2 hash of the whole key is stored.
+pub struct UserStore {
+    path: PathBuf,
+    users: Mutex<BTreeMap<String, User>>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct User {
+    api_key_hash: String,
+}
+
+#[derive(Debug)]
+pub enum UserError {
+    InvalidUserId,
+    AlreadyExists,
+    NotFound,
+    Io(io::Error),
+}
+
+impl From<io::Error> for UserError {
+    fn from(err: io::Error) -> Self {
+        UserError::Io(err)
+    }
+}
+
+pub fn is_valid_user_id(user_id: &str) -> bool {
+    (1..=32).contains(&user_id.len())
+        && user_id
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+}
+
+impl UserStore {
+    pub fn load(path: PathBuf) -> io::Result<Self> {
+        let users = match fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content).map_err(io::Error::other)?,
+            Err(err) if err.kind() == ErrorKind::NotFound => BTreeMap::new(),
+            Err(err) => return Err(err),
+        };
+        Ok(UserStore {
+            path,
+            users: Mutex::new(users),
+        })
+    }
+
+    fn save(&self, users: &BTreeMap<String, User>) -> io::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&self.path, serde_json::to_string_pretty(users)?)
+    }
+
+    /// Creates the user and returns its API key, the only time it is available in plain text.
+    pub fn create(&self, user_id: &str, secret: &str) -> Result<String, UserError> {
+        if !is_valid_user_id(user_id) {
+            return Err(UserError::InvalidUserId);
+        }
+        let api_key = format!("{user_id}.{secret}");
+        // Password hash errors don't implement `std::error::Error`.
+        let hash_error = |err: argon2::password_hash::Error| io::Error::other(err.to_string());
+        let salt = SaltString::encode_b64(&rand::random::<[u8; 16]>()).map_err(hash_error)?;
+        let api_key_hash = Argon2::default()
+            .hash_password(api_key.as_bytes(), &salt)
+            .map_err(hash_error)?
+            .to_string();
+
+        let mut users = self.users.lock().expect("users poisoned");
+        if users.contains_key(user_id) {
+            return Err(UserError::AlreadyExists);
+        }
+        users.insert(user_id.into(), User { api_key_hash });
+        self.save(&users)?;
+        Ok(api_key)
+    }
+
+    pub fn delete(&self, user_id: &str) -> Result<(), UserError> {
+        let mut users = self.users.lock().expect("users poisoned");
+        if users.remove(user_id).is_none() {
+            return Err(UserError::NotFound);
+        }
+        self.save(&users)?;
+        Ok(())
+    }
+
+    /// Returns the user ID of the API key owner. Verifying the hash is deliberately slow,
+    /// call it from a blocking context.
+    pub fn authenticate(&self, api_key: &str) -> Option<String> {
+        let (user_id, _) = api_key.split_once('.')?;
+        let user = self
+            .users
+            .lock()
+            .expect("users poisoned")
+            .get(user_id)?
+            .clone();
+        let hash = PasswordHash::new(&user.api_key_hash).ok()?;
+        Argon2::default()
+            .verify_password(api_key.as_bytes(), &hash)
+            .ok()
+            .map(|_| user_id.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_create_and_authenticate() {
+        let path =
+            std::env::temp_dir().join(format!("sheeet-users-test-{}.json", rand::random::<u32>()));
+        let store = UserStore::load(path.clone()).unwrap();
+        let api_key = store.create("alice", "secret").unwrap();
+        assert!(matches!(
+            store.create("alice", "other"),
+            Err(UserError::AlreadyExists)
+        ));
+        assert!(matches!(
+            store.create("Alice!", "secret"),
+            Err(UserError::InvalidUserId)
+        ));
+
+        // Reloaded from the file.
+        let store = UserStore::load(path.clone()).unwrap();
+        assert_eq!(store.authenticate(&api_key), Some("alice".into()));
+        assert_eq!(store.authenticate("alice.wrong"), None);
+        assert_eq!(store.authenticate("bob.secret"), None);
+
+        store.delete("alice").unwrap();
+        assert_eq!(store.authenticate(&api_key), None);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,271 @@
+use crate::build_cache::BuildCache;
+use log::{info, warn};
+use serde::Serialize;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::{fs, io, thread};
+
+pub const WORKSPACE_ID_LENGTH: usize = 12;
+/// Hidden, its modification time is the workspace's last use.
+pub const LAST_USED_FILE: &str = ".last-used";
+/// Build outputs, the next compile recreates them.
+const ARTIFACT_DIRS: [&str; 2] = ["target", "dist"];
+/// Recently used workspaces are never evicted for the disk budget, they could be compiling.
+const MIN_IDLE: Duration = Duration::from_secs(10 * 60);
+
+/// Workspace IDs are generated as 12 lowercase ASCII letters, anything else could escape
+/// the workspaces directory or collide with the build cache and shares.
+pub fn is_valid_workspace_id(workspace_id: &str) -> bool {
+    workspace_id.len() == WORKSPACE_ID_LENGTH
+        && workspace_id.chars().all(|c| c.is_ascii_lowercase())
+}
+
+#[derive(Serialize, Debug)]
+pub struct WorkspaceInfo {
+    pub workspace_id: String,
+    /// Total size in bytes.
+    pub size: u64,
+    /// Size of the build artifacts in bytes, pruning frees it.
+    pub artifacts_size: u64,
+    /// Unix timestamp in seconds.
+    pub last_used_at: u64,
+}
+
+/// Marks the workspace (or the shared target directory) as used now.
+pub fn touch(workspace_path: &Path) -> io::Result<()> {
+    fs::write(workspace_path.join(LAST_USED_FILE), "")
+}
+
+pub fn last_used(workspace_path: &Path) -> io::Result<SystemTime> {
+    match fs::metadata(workspace_path.join(LAST_USED_FILE)) {
+        Ok(metadata) => metadata.modified(),
+        // Workspaces used before the tracking.
+        Err(err) if err.kind() == ErrorKind::NotFound => fs::metadata(workspace_path)?.modified(),
+        Err(err) => Err(err),
+    }
+}
+
+pub fn dir_size(path: &Path) -> io::Result<u64> {
+    let entries = match fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(err),
+    };
+    let mut size = 0;
+    for entry in entries {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            size += dir_size(&entry.path())?;
+        } else if file_type.is_file() {
+            size += entry.metadata()?.len();
+        }
+    }
+    Ok(size)
+}
+
+pub fn info(workspaces_path: &Path, workspace_id: &str) -> io::Result<WorkspaceInfo> {
+    let workspace_path = workspaces_path.join(workspace_id);
+    let mut artifacts_size = 0;
+    for dir in ARTIFACT_DIRS {
+        artifacts_size += dir_size(&workspace_path.join(dir))?;
+    }
+    Ok(WorkspaceInfo {
+        workspace_id: workspace_id.into(),
+        size: dir_size(&workspace_path)?,
+        artifacts_size,
+        last_used_at: last_used(&workspace_path)?
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default(),
+    })
+}
+
+/// Lists the workspaces, least recently used first.
+pub fn list(workspaces_path: &Path) -> io::Result<Vec<WorkspaceInfo>> {
+    let mut workspaces = Vec::new();
+    for entry in fs::read_dir(workspaces_path)? {
+        let entry = entry?;
+        let Some(workspace_id) = entry.file_name().to_str().map(String::from) else {
+            continue;
+        };
+        if is_valid_workspace_id(&workspace_id) && entry.file_type()?.is_dir() {
+            workspaces.push(info(workspaces_path, &workspace_id)?);
+        }
+    }
+    workspaces.sort_by_key(|workspace| workspace.last_used_at);
+    Ok(workspaces)
+}
+
+/// Removes the workspace's build artifacts, returns the freed bytes.
+pub fn prune(workspace_path: &Path) -> io::Result<u64> {
+    let mut freed = 0;
+    for dir in ARTIFACT_DIRS {
+        let path = workspace_path.join(dir);
+        let size = dir_size(&path)?;
+        match fs::remove_dir_all(&path) {
+            Ok(()) => freed += size,
+            Err(err) if err.kind() == ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(freed)
+}
+
+pub fn delete(workspace_path: &Path) -> io::Result<()> {
+    fs::remove_dir_all(workspace_path)
+}
+
+/// Keeps the workspaces directory in check, idle workspaces are pruned and evicted.
+#[derive(Clone, Debug)]
+pub struct JanitorConfig {
+    pub interval: Duration,
+    /// Build artifacts of the workspaces idle for longer are removed.
+    pub prune_after: Option<Duration>,
+    /// Workspaces idle for longer are deleted.
+    pub evict_after: Option<Duration>,
+    /// Total size of the workspaces in bytes. Over the budget, the least recently used
+    /// workspaces are pruned first, then evicted.
+    pub disk_budget: Option<u64>,
+}
+
+impl JanitorConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.prune_after.is_some() || self.evict_after.is_some() || self.disk_budget.is_some()
+    }
+}
+
+pub fn run_janitor(workspaces_path: &Path, config: &JanitorConfig) -> io::Result<()> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+    let idle =
+        |workspace: &WorkspaceInfo| Duration::from_secs(now.saturating_sub(workspace.last_used_at));
+
+    let mut workspaces = Vec::new();
+    for mut workspace in list(workspaces_path)? {
+        let workspace_path = workspaces_path.join(&workspace.workspace_id);
+        if config.evict_after.is_some_and(|ttl| idle(&workspace) > ttl) {
+            info!("evicting idle workspace ID {}", workspace.workspace_id);
+            delete(&workspace_path)?;
+            continue;
+        }
+        if workspace.artifacts_size > 0
+            && config.prune_after.is_some_and(|ttl| idle(&workspace) > ttl)
+        {
+            info!("pruning idle workspace ID {}", workspace.workspace_id);
+            let freed = prune(&workspace_path)?;
+            workspace.size -= freed.min(workspace.size);
+            workspace.artifacts_size = 0;
+        }
+        workspaces.push(workspace);
+    }
+
+    let Some(disk_budget) = config.disk_budget else {
+        return Ok(());
+    };
+    let mut total: u64 = workspaces.iter().map(|workspace| workspace.size).sum();
+    let candidates = || {
+        workspaces
+            .iter()
+            .filter(|workspace| idle(workspace) > MIN_IDLE)
+    };
+    for workspace in candidates().filter(|workspace| workspace.artifacts_size > 0) {
+        if total <= disk_budget {
+            return Ok(());
+        }
+        info!(
+            "pruning workspace ID {} over the disk budget",
+            workspace.workspace_id
+        );
+        total -= prune(&workspaces_path.join(&workspace.workspace_id))?.min(total);
+    }
+    for workspace in candidates() {
+        if total <= disk_budget {
+            return Ok(());
+        }
+        info!(
+            "evicting workspace ID {} over the disk budget",
+            workspace.workspace_id
+        );
+        let size = dir_size(&workspaces_path.join(&workspace.workspace_id))?;
+        delete(&workspaces_path.join(&workspace.workspace_id))?;
+        total -= size.min(total);
+    }
+    if total > disk_budget {
+        warn!("workspaces use {total} bytes over the disk budget {disk_budget} bytes");
+    }
+    Ok(())
+}
+
+/// Runs the janitor periodically, forever. The idle shared target directories are pruned
+/// as the workspaces' artifacts.
+pub fn janitor_loop(workspaces_path: &Path, config: &JanitorConfig, build_cache: &BuildCache) {
+    loop {
+        thread::sleep(config.interval);
+        if let Err(err) = run_janitor(workspaces_path, config) {
+            warn!("workspaces janitor failed: {err}");
+        }
+        if let Some(prune_after) = config.prune_after
+            && let Err(err) = build_cache.prune_targets(prune_after)
+        {
+            warn!("build cache janitor failed: {err}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+
+    fn workspace(workspaces_path: &Path, workspace_id: &str, idle: Duration, artifact: &[u8]) {
+        let workspace_path = workspaces_path.join(workspace_id);
+        fs::create_dir_all(workspace_path.join("target")).unwrap();
+        fs::write(workspace_path.join("Cargo.toml"), "[package]").unwrap();
+        fs::write(workspace_path.join("target/lib.wasm"), artifact).unwrap();
+        touch(&workspace_path).unwrap();
+        File::options()
+            .write(true)
+            .open(workspace_path.join(LAST_USED_FILE))
+            .unwrap()
+            .set_modified(SystemTime::now() - idle)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_run_janitor() {
+        let workspaces_path =
+            std::env::temp_dir().join(format!("sheeet-janitor-test-{}", rand::random::<u32>()));
+        let day = Duration::from_secs(24 * 60 * 60);
+        workspace(&workspaces_path, "evictedaaaaa", 10 * day, b"wasm");
+        workspace(&workspaces_path, "prunedaaaaaa", 2 * day, b"wasm");
+        workspace(&workspaces_path, "budgetaaaaaa", day / 2, &[0; 1000]);
+        workspace(&workspaces_path, "recentaaaaaa", Duration::ZERO, &[0; 1000]);
+        fs::create_dir_all(workspaces_path.join("shares")).unwrap();
+
+        let config = JanitorConfig {
+            interval: Duration::from_secs(60),
+            prune_after: Some(day),
+            evict_after: Some(7 * day),
+            disk_budget: Some(1500),
+        };
+        run_janitor(&workspaces_path, &config).unwrap();
+
+        let workspaces = list(&workspaces_path).unwrap();
+        let ids: Vec<&str> = workspaces
+            .iter()
+            .map(|workspace| workspace.workspace_id.as_str())
+            .collect();
+        assert_eq!(ids, ["prunedaaaaaa", "budgetaaaaaa", "recentaaaaaa"]);
+        // Pruned by the TTL, then by the budget. Recently used one is kept as is.
+        assert_eq!(workspaces[0].artifacts_size, 0);
+        assert_eq!(workspaces[1].artifacts_size, 0);
+        assert_eq!(workspaces[2].artifacts_size, 1000);
+        assert!(fs::exists(workspaces_path.join("shares")).unwrap());
+
+        fs::remove_dir_all(&workspaces_path).unwrap();
+    }
+}
@@ -0,0 +1,287 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::spanned::Spanned;
+use syn::{
+    Attribute, Expr, ExprLit, FnArg, GenericArgument, ItemFn, Lit, LitStr, Meta, Pat,
+    PathArguments, ReturnType, Type, parse_macro_input,
+};
+
+/// Declares a sheet function, see `sheeet_funcs::function` for the documentation.
+#[proc_macro_attribute]
+pub fn function(args: TokenStream, item: TokenStream) -> TokenStream {
+    let mut options = Options::default();
+    let parser = syn::meta::parser(|meta| {
+        if meta.path.is_ident("volatile") {
+            options.volatile = true;
+        } else if meta.path.is_ident("variadic") {
+            options.variadic = true;
+        } else if meta.path.is_ident("doc") {
+            options.doc = Some(meta.value()?.parse::<LitStr>()?.value());
+        } else if meta.path.is_ident("name") {
+            options.name = Some(meta.value()?.parse::<LitStr>()?.value());
+        } else {
+            return Err(meta.error(
+                "unsupported sheet function option, expected `volatile`, `variadic`, `doc` or `name`",
+            ));
+        }
+        Ok(())
+    });
+    parse_macro_input!(args with parser);
+    let function = parse_macro_input!(item as ItemFn);
+    expand(options, function)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+#[derive(Default)]
+struct Options {
+    /// Recalculated on every sheet change, e.g. reads the current time.
+    volatile: bool,
+    /// The last parameter collects all the remaining arguments.
+    variadic: bool,
+    /// Help text, the function's doc comment if not set.
+    doc: Option<String>,
+    /// Name the sheet calls the function by, the Rust name if not set.
+    name: Option<String>,
+}
+
+fn expand(options: Options, function: ItemFn) -> syn::Result<TokenStream2> {
+    let signature = &function.sig;
+    if let Some(asyncness) = signature.asyncness {
+        return Err(syn::Error::new(
+            asyncness.span(),
+            "async sheet functions are not supported, use `#[wasm_bindgen]` instead",
+        ));
+    }
+    if !signature.generics.params.is_empty() {
+        return Err(syn::Error::new(
+            signature.generics.span(),
+            "sheet functions can't be generic",
+        ));
+    }
+
+    let mut params = Vec::new();
+    for input in &signature.inputs {
+        let FnArg::Typed(input) = input else {
+            return Err(syn::Error::new(
+                input.span(),
+                "sheet functions can't take `self`",
+            ));
+        };
+        let Pat::Ident(pat) = &*input.pat else {
+            return Err(syn::Error::new(
+                input.pat.span(),
+                "sheet function parameters must be plain identifiers",
+            ));
+        };
+        params.push((pat.ident.clone(), (*input.ty).clone()));
+    }
+    if options.variadic
+        && params
+            .last()
+            .is_none_or(|(_, ty)| collection_element(ty).is_none())
+    {
+        return Err(syn::Error::new(
+            signature.inputs.span(),
+            "the last parameter of the variadic sheet function must be a `Vec` or a slice",
+        ));
+    }
+
+    let ident = &signature.ident;
+    let name = options.name.unwrap_or_else(|| ident.to_string());
+    let mut docs: Vec<String> = match options.doc {
+        Some(doc) => doc.lines().map(String::from).collect(),
+        None => doc_lines(&function.attrs),
+    };
+    // Read by the function manifest from the generated JS glue.
+    if options.volatile {
+        docs.push("@volatile".into());
+    }
+    if options.variadic {
+        docs.push("@variadic".into());
+    }
+    let return_type = match &signature.output {
+        ReturnType::Default => "undefined".into(),
+        ReturnType::Type(_, ty) => js_type(result_ok(ty).unwrap_or(ty)),
+    };
+
+    let wasm_bindgen = quote!(::sheeet_funcs::__private::wasm_bindgen);
+    let wrapper = format_ident!("__sheeet_function_{}", ident);
+    let vis = &function.vis;
+    let param_idents: Vec<_> = params.iter().map(|(ident, _)| ident).collect();
+    let param_js_types: Vec<_> = params
+        .iter()
+        .map(|(_, ty)| match option_inner(ty) {
+            Some(inner) => format!("{} | undefined", js_type(inner)),
+            None => js_type(ty),
+        })
+        .collect();
+    let mut coercions = Vec::new();
+    let mut passed = Vec::new();
+    for (index, (ident, ty)) in params.iter().enumerate() {
+        let name = ident.to_string();
+        let owned_type = match ty {
+            // Coerced to the owned value, the function borrows it.
+            Type::Reference(reference) => {
+                passed.push(quote!(&#ident));
+                owned_type(&reference.elem)
+            }
+            ty => {
+                passed.push(quote!(#ident));
+                quote!(#ty)
+            }
+        };
+        coercions.push(match collection_element(ty) {
+            // Each of the collected arguments is coerced, not their array as a range.
+            Some(element) if options.variadic && index == params.len() - 1 => quote!(
+                ::sheeet_funcs::value::variadic_argument::<#element>(#ident, #name)?
+            ),
+            _ => quote!(::sheeet_funcs::value::argument::<#owned_type>(#ident, #name)?),
+        });
+    }
+
+    Ok(quote! {
+        #function
+
+        #[#wasm_bindgen::prelude::wasm_bindgen(
+            js_name = #name,
+            wasm_bindgen = #wasm_bindgen,
+            unchecked_return_type = #return_type
+        )]
+        #(#[doc = #docs])*
+        #[allow(non_snake_case)]
+        #vis fn #wrapper(
+            #(#[wasm_bindgen(unchecked_param_type = #param_js_types)] #param_idents: #wasm_bindgen::JsValue),*
+        ) -> #wasm_bindgen::JsValue {
+            ::sheeet_funcs::value::call(|| {
+                #(let #param_idents = #coercions;)*
+                ::core::result::Result::Ok(::sheeet_funcs::value::IntoSheetValue::into_sheet_value(
+                    #ident(#(#passed),*),
+                ))
+            })
+        }
+    })
+}
+
+fn doc_lines(attrs: &[Attribute]) -> Vec<String> {
+    attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("doc"))
+        .filter_map(|attr| match &attr.meta {
+            Meta::NameValue(meta) => match &meta.value {
+                Expr::Lit(ExprLit {
+                    lit: Lit::Str(doc), ..
+                }) => Some(doc.value().trim().to_string()),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect()
+}
+
+/// Type of the owned value borrowed by the reference parameter, e.g. `String` for `&str`.
+fn owned_type(ty: &Type) -> TokenStream2 {
+    match ty {
+        Type::Path(path) if path.path.is_ident("str") => quote!(::std::string::String),
+        Type::Slice(slice) => {
+            let elem = &slice.elem;
+            quote!(::std::vec::Vec<#elem>)
+        }
+        ty => quote!(#ty),
+    }
+}
+
+/// The only generic argument of the last path segment named `name`, e.g. `T` of `Vec<T>`.
+fn generic_argument<'a>(ty: &'a Type, name: &str) -> Option<&'a Type> {
+    let Type::Path(path) = ty else {
+        return None;
+    };
+    let segment = path.path.segments.last()?;
+    if segment.ident != name {
+        return None;
+    }
+    let PathArguments::AngleBracketed(arguments) = &segment.arguments else {
+        return None;
+    };
+    match arguments.args.first()? {
+        GenericArgument::Type(ty) => Some(ty),
+        _ => None,
+    }
+}
+
+fn option_inner(ty: &Type) -> Option<&Type> {
+    generic_argument(ty, "Option")
+}
+
+fn result_ok(ty: &Type) -> Option<&Type> {
+    generic_argument(ty, "Result")
+}
+
+/// Element type of the `Vec` or the slice, the ranges are coerced to them.
+fn collection_element(ty: &Type) -> Option<&Type> {
+    match ty {
+        Type::Reference(reference) => collection_element(&reference.elem),
+        Type::Slice(slice) => Some(&slice.elem),
+        ty => generic_argument(ty, "Vec"),
+    }
+}
+
+/// JS type of the coerced Rust type, as listed in the function manifest.
+fn js_type(ty: &Type) -> String {
+    if let Some(inner) = option_inner(ty) {
+        return format!("{} | null", js_type(inner));
+    }
+    if let Some(element) = collection_element(ty) {
+        return format!("{}[]", js_type(element));
+    }
+    let ty = match ty {
+        Type::Reference(reference) => &*reference.elem,
+        ty => ty,
+    };
+    let Type::Path(path) = ty else {
+        return "any".into();
+    };
+    let Some(segment) = path.path.segments.last() else {
+        return "any".into();
+    };
+    match segment.ident.to_string().as_str() {
+        "f32" | "f64" | "i8" | "i16" | "i32" | "i64" | "isize" | "u8" | "u16" | "u32" | "u64"
+        | "usize"
+        // `sheeet_funcs::number::Number`, also read from the numbers with the `decimal` feature.
+        | "Number" => "number",
+        "String" | "str" | "char" => "string",
+        "bool" => "boolean",
+        "NaiveDate" | "NaiveDateTime" => "Date",
+        // `sheeet_funcs::range::Range`, the rows of the cells.
+        "Range" => "any[][]",
+        _ => "any",
+    }
+    .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_js_type() {
+        for (ty, expected) in [
+            ("f64", "number"),
+            ("Number", "number"),
+            ("&str", "string"),
+            ("Vec<f64>", "number[]"),
+            ("&[String]", "string[]"),
+            ("Option<bool>", "boolean | null"),
+            ("Vec<NaiveDate>", "Date[]"),
+            ("Range", "any[][]"),
+            ("Result<f64, String>", "any"),
+            ("JsValue", "any"),
+        ] {
+            let ty: Type = syn::parse_str(ty).unwrap();
+            assert_eq!(js_type(&ty), expected);
+        }
+        let ty: Type = syn::parse_str("Result<Vec<u32>, String>").unwrap();
+        assert_eq!(js_type(result_ok(&ty).unwrap()), "number[]");
+    }
+}
@@ -0,0 +1,539 @@
+//! Date and time functions. The sheet's dates are the JS `Date`s holding the wall-clock time
+//! in their UTC fields, the functions take and return them as the [`NaiveDate`]
+//! and the [`NaiveDateTime`]. The numbers are the serial dates, the days since 1899-12-30
+//! (as in the other spreadsheets) with the time of day as the fraction. The time zones are
+//! `UTC`, the fixed offsets like `+02:00` or, with the default `timezones` feature, the IANA
+//! names like `Europe/Prague`.
+
+use crate as sheeet;
+use chrono::{
+    DateTime, Datelike, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime, TimeDelta, Timelike,
+};
+use std::collections::HashSet;
+
+/// Day zero of the serial dates.
+const SERIAL_EPOCH: NaiveDate = NaiveDate::from_ymd_opt(1899, 12, 30).unwrap();
+const MILLIS_PER_DAY: f64 = 24.0 * 60.0 * 60.0 * 1000.0;
+
+const DATE_FORMATS: [&str; 8] = [
+    "%Y-%m-%d",
+    "%Y/%m/%d",
+    "%d.%m.%Y",
+    "%d. %m. %Y",
+    "%m/%d/%Y",
+    "%d %B %Y",
+    "%B %d, %Y",
+    "%B %d %Y",
+];
+const TIME_FORMATS: [&str; 2] = ["%H:%M", "%H:%M:%S%.f"];
+
+fn out_of_range() -> String {
+    "date out of range".into()
+}
+
+/// Parses the date, optionally with the time after a space or `T`: ISO (`2025-03-01`),
+/// `2025/03/01`, `1.3.2025`, `1. 3. 2025`, US `3/1/2025`, `1 March 2025` and `March 1, 2025`.
+/// The RFC 3339 time with the offset (e.g. `2025-03-01T14:30:00+01:00`) is converted to UTC.
+pub fn parse_date_time(text: &str) -> Result<NaiveDateTime, String> {
+    let trimmed = text.trim();
+    if let Ok(date_time) = DateTime::parse_from_rfc3339(trimmed) {
+        return Ok(date_time.naive_utc());
+    }
+    for date_format in DATE_FORMATS {
+        if let Ok(date) = NaiveDate::parse_from_str(trimmed, date_format) {
+            return Ok(date.and_time(NaiveTime::MIN));
+        }
+        for separator in [" ", "T"] {
+            for time_format in TIME_FORMATS {
+                let format = format!("{date_format}{separator}{time_format}");
+                if let Ok(date_time) = NaiveDateTime::parse_from_str(trimmed, &format) {
+                    return Ok(date_time);
+                }
+            }
+        }
+    }
+    Err(format!(r#"expected a date, got "{text}""#))
+}
+
+/// Formats as the sheet shows the dates, the ISO date with the time of day if it isn't midnight.
+pub fn format_date_time(date_time: NaiveDateTime) -> String {
+    let format = match date_time.time() {
+        NaiveTime::MIN => "%Y-%m-%d",
+        time if time.second() == 0 && time.nanosecond() == 0 => "%Y-%m-%d %H:%M",
+        _ => "%Y-%m-%d %H:%M:%S",
+    };
+    date_time.format(format).to_string()
+}
+
+/// Date of the serial number, the days since 1899-12-30.
+pub fn from_serial(serial: f64) -> Result<NaiveDateTime, String> {
+    let epoch = SERIAL_EPOCH.and_time(NaiveTime::MIN);
+    let millis = (serial * MILLIS_PER_DAY).round();
+    if !millis.is_finite() || millis.abs() > i64::MAX as f64 {
+        return Err(out_of_range());
+    }
+    TimeDelta::try_milliseconds(millis as i64)
+        .and_then(|delta| epoch.checked_add_signed(delta))
+        .ok_or_else(out_of_range)
+}
+
+/// Serial number of the date, the days since 1899-12-30.
+pub fn to_serial(date_time: NaiveDateTime) -> f64 {
+    let epoch = SERIAL_EPOCH.and_time(NaiveTime::MIN);
+    (date_time - epoch).num_milliseconds() as f64 / MILLIS_PER_DAY
+}
+
+/// Date of the JS `Date`'s time, the milliseconds since the Unix epoch.
+pub fn from_timestamp(timestamp: f64) -> Result<NaiveDateTime, String> {
+    if !timestamp.is_finite() {
+        return Err("invalid date".into());
+    }
+    DateTime::from_timestamp_millis(timestamp as i64)
+        .map(|date_time| date_time.naive_utc())
+        .ok_or_else(out_of_range)
+}
+
+/// The JS `Date`'s time of the date, the milliseconds since the Unix epoch.
+pub fn to_timestamp(date_time: NaiveDateTime) -> f64 {
+    date_time.and_utc().timestamp_millis() as f64
+}
+
+enum TimeZone {
+    Fixed(FixedOffset),
+    #[cfg(feature = "timezones")]
+    Named(chrono_tz::Tz),
+}
+
+impl TimeZone {
+    fn parse(name: &str) -> Result<Self, String> {
+        if name.eq_ignore_ascii_case("utc") || name == "Z" {
+            return Ok(TimeZone::Fixed(FixedOffset::east_opt(0).unwrap()));
+        }
+        if let Ok(offset) = name.parse() {
+            return Ok(TimeZone::Fixed(offset));
+        }
+        #[cfg(feature = "timezones")]
+        if let Ok(time_zone) = name.parse() {
+            return Ok(TimeZone::Named(time_zone));
+        }
+        Err(match cfg!(feature = "timezones") {
+            true => format!(r#"unknown time zone "{name}""#),
+            false => format!(
+                r#"unknown time zone "{name}", the IANA names need the `timezones` feature"#
+            ),
+        })
+    }
+
+    /// Wall-clock time of the UTC time in the time zone.
+    fn local_time(&self, utc: NaiveDateTime) -> NaiveDateTime {
+        use chrono::TimeZone as _;
+        match self {
+            TimeZone::Fixed(offset) => offset.from_utc_datetime(&utc).naive_local(),
+            #[cfg(feature = "timezones")]
+            TimeZone::Named(time_zone) => time_zone.from_utc_datetime(&utc).naive_local(),
+        }
+    }
+
+    /// UTC time of the wall-clock time in the time zone, the earlier one of the times repeated
+    /// when the clocks go back.
+    fn utc_time(&self, local: NaiveDateTime) -> Option<NaiveDateTime> {
+        use chrono::TimeZone as _;
+        match self {
+            TimeZone::Fixed(offset) => offset.from_local_datetime(&local).earliest(),
+            #[cfg(feature = "timezones")]
+            TimeZone::Named(time_zone) => time_zone
+                .from_local_datetime(&local)
+                .earliest()
+                .map(|date_time| date_time.fixed_offset()),
+        }
+        .map(|date_time| date_time.naive_utc())
+    }
+}
+
+/// Current UTC time and the local time's offset from it in minutes, outside the WASM (e.g. in
+/// the user crate's tests) the local time is UTC.
+#[cfg(target_arch = "wasm32")]
+fn current_time() -> (NaiveDateTime, i64) {
+    let now = js_sys::Date::new_0();
+    let utc = from_timestamp(now.get_time()).unwrap_or_default();
+    (utc, -(now.get_timezone_offset() as i64))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn current_time() -> (NaiveDateTime, i64) {
+    let utc = DateTime::<chrono::Utc>::from(std::time::SystemTime::now()).naive_utc();
+    (utc, 0)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Unit {
+    Years,
+    Months,
+    Weeks,
+    Days,
+    Hours,
+    Minutes,
+    Seconds,
+}
+
+impl Unit {
+    /// Days by default, the names are singular or plural, e.g. `month` or `months`.
+    fn parse(unit: Option<String>) -> Result<Self, String> {
+        let Some(unit) = unit else {
+            return Ok(Unit::Days);
+        };
+        let lowercase = unit.trim().to_lowercase();
+        Ok(match lowercase.strip_suffix('s').unwrap_or(&lowercase) {
+            "year" => Unit::Years,
+            "month" => Unit::Months,
+            "week" => Unit::Weeks,
+            "day" => Unit::Days,
+            "hour" => Unit::Hours,
+            "minute" => Unit::Minutes,
+            "second" => Unit::Seconds,
+            _ => {
+                return Err(format!(
+                    r#"unknown unit "{unit}", expected years, months, weeks, days, hours, minutes or seconds"#
+                ));
+            }
+        })
+    }
+}
+
+/// Shifts the date by the months, the day past the end of the month is the month's last day.
+fn add_months(date_time: NaiveDateTime, months: i64) -> Option<NaiveDateTime> {
+    let shift = chrono::Months::new(u32::try_from(months.unsigned_abs()).ok()?);
+    match months < 0 {
+        true => date_time.checked_sub_months(shift),
+        false => date_time.checked_add_months(shift),
+    }
+}
+
+/// The date, errors on the month or the day out of the range, e.g. `date(2025, 2, 30)`.
+#[sheeet::function]
+pub fn date(year: i32, month: u32, day: u32) -> Result<NaiveDate, String> {
+    NaiveDate::from_ymd_opt(year, month, day)
+        .ok_or_else(|| format!("no date {year:04}-{month:02}-{day:02}"))
+}
+
+/// Parses the date text, in the `format` (e.g. `%d/%m/%Y`, see the chrono's `strftime`) if
+/// it's given, else in the ISO or the common formats.
+#[sheeet::function]
+pub fn date_value(text: &str, format: Option<String>) -> Result<NaiveDateTime, String> {
+    let Some(format) = format else {
+        return parse_date_time(text);
+    };
+    NaiveDateTime::parse_from_str(text.trim(), &format)
+        .or_else(|_| {
+            NaiveDate::parse_from_str(text.trim(), &format)
+                .map(|date| date.and_time(NaiveTime::MIN))
+        })
+        .map_err(|err| format!(r#""{text}" doesn't match the format "{format}": {err}"#))
+}
+
+/// Serial number of the date, the days since 1899-12-30 with the time of day as the fraction.
+#[sheeet::function]
+pub fn serial(date: NaiveDateTime) -> f64 {
+    to_serial(date)
+}
+
+/// Current date and time, in the time zone if it's given, else the local one.
+#[sheeet::function(volatile)]
+pub fn now(time_zone: Option<String>) -> Result<NaiveDateTime, String> {
+    let (utc, local_offset) = current_time();
+    match time_zone {
+        Some(time_zone) => Ok(TimeZone::parse(&time_zone)?.local_time(utc)),
+        None => Ok(utc + TimeDelta::minutes(local_offset)),
+    }
+}
+
+/// Current date, in the time zone if it's given, else the local one.
+#[sheeet::function(volatile)]
+pub fn today(time_zone: Option<String>) -> Result<NaiveDate, String> {
+    now(time_zone).map(|now| now.date())
+}
+
+/// Adds the `amount` (negative subtracts) of the `unit`, days by default. Adding the months
+/// or the years past the end of the month gives the month's last day, e.g. a month after
+/// 2025-01-31 is 2025-02-28.
+#[sheeet::function]
+pub fn date_add(
+    date: NaiveDateTime,
+    amount: i64,
+    unit: Option<String>,
+) -> Result<NaiveDateTime, String> {
+    let delta = match Unit::parse(unit)? {
+        Unit::Years => {
+            return amount
+                .checked_mul(12)
+                .and_then(|months| add_months(date, months))
+                .ok_or_else(out_of_range);
+        }
+        Unit::Months => return add_months(date, amount).ok_or_else(out_of_range),
+        Unit::Weeks => TimeDelta::try_weeks(amount),
+        Unit::Days => TimeDelta::try_days(amount),
+        Unit::Hours => TimeDelta::try_hours(amount),
+        Unit::Minutes => TimeDelta::try_minutes(amount),
+        Unit::Seconds => TimeDelta::try_seconds(amount),
+    };
+    delta
+        .and_then(|delta| date.checked_add_signed(delta))
+        .ok_or_else(out_of_range)
+}
+
+/// Number of the whole `unit`s (days by default) from the `start` to the `end`, negative
+/// if the `end` is earlier.
+#[sheeet::function]
+pub fn date_diff(
+    start: NaiveDateTime,
+    end: NaiveDateTime,
+    unit: Option<String>,
+) -> Result<i64, String> {
+    let unit = Unit::parse(unit)?;
+    if let Unit::Years | Unit::Months = unit {
+        let mut months = i64::from(end.year() - start.year()) * 12 + i64::from(end.month())
+            - i64::from(start.month());
+        // The last month isn't whole if its day (and time) isn't reached yet.
+        let shifted = add_months(start, months).ok_or_else(out_of_range)?;
+        if months > 0 && shifted > end {
+            months -= 1;
+        } else if months < 0 && shifted < end {
+            months += 1;
+        }
+        return Ok(match unit {
+            Unit::Years => months / 12,
+            _ => months,
+        });
+    }
+    let delta = end - start;
+    Ok(match unit {
+        Unit::Weeks => delta.num_weeks(),
+        Unit::Hours => delta.num_hours(),
+        Unit::Minutes => delta.num_minutes(),
+        Unit::Seconds => delta.num_seconds(),
+        _ => delta.num_days(),
+    })
+}
+
+/// Day of the week, 1 is Monday and 7 is Sunday.
+#[sheeet::function]
+pub fn weekday(date: NaiveDate) -> u32 {
+    date.weekday().number_from_monday()
+}
+
+/// Last day of the month, `months` (0 by default) after the date's month.
+#[sheeet::function]
+pub fn eomonth(date: NaiveDate, months: Option<i64>) -> Result<NaiveDate, String> {
+    let first = date.with_day(1).ok_or_else(out_of_range)?;
+    months
+        .unwrap_or(0)
+        .checked_add(1)
+        .and_then(|months| add_months(first.and_time(NaiveTime::MIN), months))
+        .and_then(|next| next.date().pred_opt())
+        .ok_or_else(out_of_range)
+}
+
+/// Number of the working days (Monday to Friday) from the `start` to the `end`, both included,
+/// without the `holidays` (the dates or the ranges of them). Negative if the `end` is earlier.
+#[sheeet::function(variadic)]
+pub fn networkdays(start: NaiveDate, end: NaiveDate, holidays: Vec<NaiveDate>) -> i64 {
+    let holidays: HashSet<NaiveDate> = holidays.into_iter().collect();
+    let (first, last, sign) = match start <= end {
+        true => (start, end, 1),
+        false => (end, start, -1),
+    };
+    let days = first
+        .iter_days()
+        .take_while(|day| *day <= last)
+        .filter(|day| day.weekday().number_from_monday() <= 5 && !holidays.contains(day))
+        .count();
+    sign * days as i64
+}
+
+/// Converts the wall-clock time from the time zone `from` to the time zone `to`.
+#[sheeet::function]
+pub fn convert_tz(date: NaiveDateTime, from: &str, to: &str) -> Result<NaiveDateTime, String> {
+    let utc = TimeZone::parse(from)?
+        .utc_time(date)
+        .ok_or_else(|| format!("{} doesn't exist in {from}", format_date_time(date)))?;
+    Ok(TimeZone::parse(to)?.local_time(utc))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(text: &str) -> NaiveDateTime {
+        parse_date_time(text).unwrap()
+    }
+
+    fn day(text: &str) -> NaiveDate {
+        at(text).date()
+    }
+
+    #[test]
+    fn test_parse_and_format() {
+        for text in [
+            "2025-03-01",
+            " 2025/03/01 ",
+            "1.3.2025",
+            "01. 03. 2025",
+            "3/1/2025",
+            "1 March 2025",
+            "1 Mar 2025",
+            "March 1, 2025",
+        ] {
+            assert_eq!(format_date_time(at(text)), "2025-03-01", "{text}");
+        }
+        assert_eq!(format_date_time(at("2025-03-01T14:30")), "2025-03-01 14:30");
+        assert_eq!(
+            format_date_time(at("1.3.2025 14:30:15")),
+            "2025-03-01 14:30:15"
+        );
+        assert_eq!(
+            format_date_time(at("2025-03-01T14:30:00+01:00")),
+            "2025-03-01 13:30"
+        );
+        assert_eq!(
+            parse_date_time("2025-02-30"),
+            Err(r#"expected a date, got "2025-02-30""#.into())
+        );
+        assert_eq!(
+            date_value("01/03/2025", Some("%d/%m/%Y".into())),
+            Ok(at("2025-03-01"))
+        );
+        assert!(date_value("2025", Some("%d/%m/%Y".into())).is_err());
+        assert_eq!(date(2024, 2, 29), Ok(day("2024-02-29")));
+        assert_eq!(date(2025, 2, 29), Err("no date 2025-02-29".into()));
+    }
+
+    #[test]
+    fn test_serial() {
+        assert_eq!(serial(at("1899-12-30")), 0.0);
+        assert_eq!(serial(at("2025-03-01")), 45717.0);
+        assert_eq!(serial(at("2025-03-01 18:00")), 45717.75);
+        assert_eq!(from_serial(45717.75), Ok(at("2025-03-01 18:00")));
+        assert_eq!(from_serial(f64::NAN), Err("date out of range".into()));
+        assert_eq!(
+            from_timestamp(to_timestamp(at("1969-07-20 20:17"))),
+            Ok(at("1969-07-20 20:17"))
+        );
+    }
+
+    #[test]
+    fn test_add_and_diff() {
+        let start = at("2025-01-31 10:00");
+        assert_eq!(
+            date_add(start, 1, Some("month".into())),
+            Ok(at("2025-02-28 10:00"))
+        );
+        assert_eq!(
+            date_add(start, -1, Some("Years".into())),
+            Ok(at("2024-01-31 10:00"))
+        );
+        assert_eq!(date_add(start, 1, None), Ok(at("2025-02-01 10:00")));
+        assert_eq!(
+            date_add(start, 90, Some("minutes".into())),
+            Ok(at("2025-01-31 11:30"))
+        );
+        assert_eq!(
+            date_add(start, 1, Some("fortnight".into())),
+            Err(r#"unknown unit "fortnight", expected years, months, weeks, days, hours, minutes or seconds"#.into())
+        );
+        assert_eq!(
+            date_add(start, i64::MAX, None),
+            Err("date out of range".into())
+        );
+
+        assert_eq!(
+            date_diff(at("2025-01-31"), at("2025-02-28"), Some("months".into())),
+            Ok(1)
+        );
+        assert_eq!(
+            date_diff(at("2025-02-28"), at("2025-03-27"), Some("months".into())),
+            Ok(0)
+        );
+        assert_eq!(
+            date_diff(at("2025-03-27"), at("2025-02-28"), Some("months".into())),
+            Ok(0)
+        );
+        // As the years added to the leap day, its anniversary is the end of February.
+        assert_eq!(
+            date_diff(at("2020-02-29"), at("2025-02-28"), Some("years".into())),
+            Ok(5)
+        );
+        assert_eq!(
+            date_diff(at("2020-03-01"), at("2025-02-28"), Some("years".into())),
+            Ok(4)
+        );
+        assert_eq!(date_diff(at("2025-03-01"), at("2025-02-01"), None), Ok(-28));
+        assert_eq!(
+            date_diff(
+                at("2025-03-01"),
+                at("2025-03-01 23:59"),
+                Some("hours".into())
+            ),
+            Ok(23)
+        );
+    }
+
+    #[test]
+    fn test_calendar() {
+        assert_eq!(weekday(day("2025-03-01")), 6);
+        assert_eq!(weekday(day("2025-03-03")), 1);
+        assert_eq!(eomonth(day("2024-01-15"), Some(1)), Ok(day("2024-02-29")));
+        assert_eq!(eomonth(day("2025-03-31"), None), Ok(day("2025-03-31")));
+        assert_eq!(eomonth(day("2025-03-01"), Some(-3)), Ok(day("2024-12-31")));
+        // Monday 2025-03-03 to Sunday 2025-03-16, two working weeks.
+        assert_eq!(
+            networkdays(day("2025-03-03"), day("2025-03-16"), vec![]),
+            10
+        );
+        assert_eq!(
+            networkdays(
+                day("2025-03-03"),
+                day("2025-03-16"),
+                vec![day("2025-03-05"), day("2025-03-08"), day("2025-04-01")]
+            ),
+            9
+        );
+        assert_eq!(
+            networkdays(day("2025-03-16"), day("2025-03-03"), vec![]),
+            -10
+        );
+    }
+
+    #[test]
+    fn test_time_zones() {
+        let summer = at("2025-07-01 12:00");
+        assert_eq!(
+            convert_tz(summer, "UTC", "+02:00"),
+            Ok(at("2025-07-01 14:00"))
+        );
+        assert_eq!(
+            convert_tz(summer, "-05:30", "utc"),
+            Ok(at("2025-07-01 17:30"))
+        );
+        assert!(now(Some("+01:00".into())).is_ok());
+        #[cfg(feature = "timezones")]
+        {
+            assert_eq!(
+                convert_tz(summer, "Europe/Prague", "America/New_York"),
+                Ok(at("2025-07-01 06:00"))
+            );
+            assert_eq!(
+                convert_tz(at("2025-01-01 12:00"), "Europe/Prague", "UTC"),
+                Ok(at("2025-01-01 11:00"))
+            );
+            // The clocks go forward from 02:00 to 03:00.
+            assert_eq!(
+                convert_tz(at("2025-03-30 02:30"), "Europe/Prague", "UTC"),
+                Err("2025-03-30 02:30 doesn't exist in Europe/Prague".into())
+            );
+            assert_eq!(
+                convert_tz(summer, "Mars/Olympus", "UTC"),
+                Err(r#"unknown time zone "Mars/Olympus""#.into())
+            );
+        }
+    }
+}
@@ -1,14 +1,84 @@
+use crate as sheeet;
+use crate::number::{CheckedArithmetic, Number};
 use wasm_bindgen::prelude::*;
 
+// The macro generated code refers to the crate by its name, also inside the crate.
+extern crate self as sheeet_funcs;
+
+/// Declares a sheet function. Generates the `#[wasm_bindgen]` wrapper, which coerces
+/// the arguments (e.g. numeric texts to numbers, ranges to `Vec`s, the numeric ones skip
+/// the ranges' texts), turns the returned `Err`
+/// and the panics into the error values and registers the function in the build's function
+/// manifest, which the sheet checks the calls against and the GUI lists.
+///
+/// Options:
+/// - `volatile`, recalculated on every sheet change, e.g. reads the current time,
+/// - `variadic`, the last parameter (a `Vec` or a slice) collects all the remaining arguments,
+/// - `doc = "..."`, help text, the doc comment of the function by default,
+/// - `name = "..."`, name the sheet calls the function by, the Rust name by default.
+///
+/// A panic aborts the WASM call half way through, which can leave the instance's memory
+/// inconsistent. The panicking call's cell gets the panic message as its error value, all
+/// the following calls are refused with an error value, the GUI instantiates the module
+/// again and retries them.
+///
+/// ```
+/// use sheeet_funcs::prelude::*;
+///
+/// /// Joins the texts with the separator.
+/// #[sheeet::function(variadic)]
+/// pub fn join(sep: &str, texts: Vec<String>) -> String {
+///     texts.join(sep)
+/// }
+///
+/// #[sheeet::function(doc = "Divides, errors on zero.")]
+/// pub fn checked_div(a: f64, b: f64) -> Result<f64, String> {
+///     match b {
+///         0.0 => Err("division by zero".into()),
+///         b => Ok(a / b),
+///     }
+/// }
+/// ```
+pub use sheeet_funcs_macros::function;
+
+/// The date types the date functions take and return.
+pub use chrono;
+
+pub mod date;
+pub mod logging;
+pub mod lookup;
+pub mod number;
+pub mod range;
+pub mod statistics;
+pub mod text;
+pub mod value;
+
+#[doc(hidden)]
+pub mod __private {
+    pub use wasm_bindgen;
+}
+
 pub mod prelude {
+    /// Lets the user crate write `#[sheeet::function]`.
+    pub use crate as sheeet;
+    pub use crate::{log, trace};
+
     pub use crate::add;
-    pub use crate::avg;
+    pub use crate::chrono::{NaiveDate, NaiveDateTime};
     pub use crate::concat_with;
+    pub use crate::date::{
+        convert_tz, date, date_add, date_diff, date_value, eomonth, networkdays, now, serial,
+        today, weekday,
+    };
     pub use crate::div;
-    pub use crate::med;
+    pub use crate::lookup::*;
     pub use crate::mul;
+    pub use crate::number::Number;
+    pub use crate::power;
+    pub use crate::range::{CellValue, Range, Slice};
+    pub use crate::statistics::*;
     pub use crate::sub;
-    pub use crate::sum;
+    pub use crate::text::*;
 
     #[cfg(feature = "fetch")]
     pub use crate::fetch::fetch_get_json_path;
@@ -17,54 +87,35 @@ pub mod prelude {
 #[cfg(feature = "fetch")]
 mod fetch;
 
-#[wasm_bindgen]
-pub fn add(a: f32, b: f32) -> f32 {
-    a + b
-}
-
-#[wasm_bindgen]
-pub fn sub(a: f32, b: f32) -> f32 {
-    a - b
-}
-
-#[wasm_bindgen]
-pub fn div(a: f32, b: f32) -> f32 {
-    a / b
+#[sheeet::function]
+pub fn add(a: Number, b: Number) -> Result<Number, String> {
+    a.try_add(b).ok_or_else(|| "add overflowed".into())
 }
 
-#[wasm_bindgen]
-pub fn mul(a: f32, b: f32) -> f32 {
-    a * b
+#[sheeet::function]
+pub fn sub(a: Number, b: Number) -> Result<Number, String> {
+    a.try_sub(b).ok_or_else(|| "sub overflowed".into())
 }
 
-#[wasm_bindgen]
-pub fn pow(a: f32, n: f32) -> f32 {
-    a.powf(n)
+#[sheeet::function]
+pub fn div(a: Number, b: Number) -> Result<Number, String> {
+    if b == Number::default() {
+        return Err("division by zero".into());
+    }
+    a.try_div(b).ok_or_else(|| "div overflowed".into())
 }
 
-#[wasm_bindgen]
-pub fn sum(vec: Vec<f32>) -> f32 {
-    vec.into_iter().sum()
+#[sheeet::function]
+pub fn mul(a: Number, b: Number) -> Result<Number, String> {
+    a.try_mul(b).ok_or_else(|| "mul overflowed".into())
 }
 
-#[wasm_bindgen]
-pub fn avg(vec: Vec<f32>) -> f32 {
-    if vec.len() == 0 {
-        return 0.0;
-    }
-    let len = vec.len() as f32;
-    vec.into_iter().sum::<f32>() / len
-}
-
-/// Discrete implementation of median.
-#[wasm_bindgen]
-pub fn med(vec: Vec<f32>) -> f32 {
-    if vec.len() == 0 {
-        return 0.0;
-    }
-    vec.get(vec.len() / 2)
-        .expect("expected median position to exist in the vector")
-        .clone()
+/// The number raised to the power.
+// Not `pow`, the `pow` export would replace the libm `pow` that `f64::powf` links to.
+#[sheeet::function]
+pub fn power(a: Number, n: Number) -> Result<Number, String> {
+    a.try_pow(n)
+        .ok_or_else(|| format!("power of {a} to {n} is not a finite number"))
 }
 
 #[wasm_bindgen]
@@ -84,19 +135,44 @@ pub fn concat_with(vec: Vec<String>, sep: &str) -> String {
 mod tests {
     use crate::*;
 
+    fn number(text: &str) -> Number {
+        text.parse().unwrap()
+    }
+
     #[test]
-    fn test_avg() {
-        assert_eq!(avg(vec![]), 0.0);
-        assert_eq!(avg(vec![1.0, 2.0, 3.0]), 2.0);
-        assert_eq!(avg(vec![1.0, 1.0, 7.0]), 3.0);
+    fn test_arithmetic() {
+        assert_eq!(add(number("1"), number("2")), Ok(number("3")));
+        assert_eq!(sub(number("1"), number("2.5")), Ok(number("-1.5")));
+        assert_eq!(mul(number("1.5"), number("4")), Ok(number("6")));
+        assert_eq!(div(number("1"), number("4")), Ok(number("0.25")));
+        assert_eq!(power(number("2"), number("10")), Ok(number("1024")));
+        assert_eq!(
+            div(number("1"), number("0")),
+            Err("division by zero".into())
+        );
+        // Beyond the `f32` precision.
+        assert_eq!(add(number("16777216"), number("1")), Ok(number("16777217")));
+    }
+
+    #[cfg(not(feature = "decimal"))]
+    #[test]
+    fn test_arithmetic_overflow() {
+        assert_eq!(
+            mul(number("1e300"), number("1e300")),
+            Err("mul overflowed".into())
+        );
+        assert_eq!(
+            power(number("-8"), number("0.5")),
+            Err("power of -8 to 0.5 is not a finite number".into())
+        );
     }
 
+    #[cfg(feature = "decimal")]
     #[test]
-    fn test_med() {
-        assert_eq!(med(vec![]), 0.0);
-        assert_eq!(med(vec![1.0, 2.0, 3.0, 4.0, 5.0]), 3.0);
-        assert_eq!(med(vec![1.0, 1.0, 2.0, 2.0, 2.0]), 2.0);
-        assert_eq!(med(vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0]), 4.0); // Discrete implementation.
+    fn test_decimal_arithmetic() {
+        assert_eq!(add(number("0.1"), number("0.2")), Ok(number("0.3")));
+        let max = number("79228162514264337593543950335");
+        assert_eq!(mul(max, number("2")), Err("mul overflowed".into()));
     }
 
     #[test]
@@ -0,0 +1,89 @@
+//! Logs of the sheet functions, written by the [`log!`](crate::log) and [`trace!`](crate::trace)
+//! macros. The GUI takes them after every call and lists them with the cell that made the call.
+
+use js_sys::{Array, Object, Reflect};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt::{Display, Formatter};
+use wasm_bindgen::JsValue;
+use wasm_bindgen::prelude::*;
+
+/// Logs kept until the GUI takes them, the oldest are dropped, e.g. when the functions are
+/// called outside the sheet.
+const MAX_LOGS: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Level {
+    Trace,
+    Info,
+}
+
+impl Display for Level {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Level::Trace => "trace",
+            Level::Info => "info",
+        })
+    }
+}
+
+thread_local! {
+    static LOGS: RefCell<VecDeque<(Level, String)>> = const { RefCell::new(VecDeque::new()) };
+}
+
+/// Records the log, outside the WASM (e.g. in the user crate's tests) it's printed to stderr.
+pub fn write(level: Level, message: String) {
+    if !cfg!(target_arch = "wasm32") {
+        eprintln!("[{level}] {message}");
+        return;
+    }
+    LOGS.with_borrow_mut(|logs| {
+        if logs.len() == MAX_LOGS {
+            logs.pop_front();
+        }
+        logs.push_back((level, message));
+    });
+}
+
+/// Logs written since the last call as `{level, message}` objects, taken by the GUI.
+#[wasm_bindgen]
+pub fn __sheeet_take_logs() -> Array {
+    LOGS.take()
+        .into_iter()
+        .map(|(level, message)| {
+            let log = Object::new();
+            // Setting a property of a plain object can't fail.
+            _ = Reflect::set(&log, &"level".into(), &level.to_string().into());
+            _ = Reflect::set(&log, &"message".into(), &message.into());
+            JsValue::from(log)
+        })
+        .collect()
+}
+
+/// Logs the message to the GUI's log console, with the cell calling the function.
+/// Takes the `format!` arguments.
+///
+/// ```
+/// use sheeet_funcs::prelude::*;
+///
+/// #[sheeet::function]
+/// pub fn half(a: f64) -> f64 {
+///     sheeet_funcs::log!("halving {a}");
+///     a / 2.0
+/// }
+/// ```
+#[macro_export]
+macro_rules! log {
+    ($($arg:tt)*) => {
+        $crate::logging::write($crate::logging::Level::Info, ::std::format!($($arg)*))
+    };
+}
+
+/// Logs the detailed message, the GUI's log console can filter them out. Takes
+/// the `format!` arguments.
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)*) => {
+        $crate::logging::write($crate::logging::Level::Trace, ::std::format!($($arg)*))
+    };
+}
@@ -0,0 +1,510 @@
+//! Lookup functions on the 2D ranges. The positions are 1-based. The texts are matched
+//! case-insensitively and the numeric texts as the numbers, see [`CellValue::compare`]. The found
+//! parts of the sheet's ranges are returned as the references, so the cell depends on
+//! the found cells.
+
+use crate as sheeet;
+use crate::range::{CellValue, Range, Slice};
+use std::cmp::Ordering;
+
+/// How the value is looked up.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum MatchMode {
+    Exact,
+    /// The exact match or the largest smaller value.
+    ExactOrSmaller,
+    /// The exact match or the smallest larger value.
+    ExactOrLarger,
+}
+
+impl MatchMode {
+    /// `0` is the exact match (by default), `-1` the exact or the next smaller value and `1`
+    /// the exact or the next larger value.
+    fn parse(mode: Option<i32>) -> Result<Self, String> {
+        match mode.unwrap_or(0) {
+            0 => Ok(MatchMode::Exact),
+            -1 => Ok(MatchMode::ExactOrSmaller),
+            1 => Ok(MatchMode::ExactOrLarger),
+            mode => Err(format!("unknown match mode {mode}, expected 0, -1 or 1")),
+        }
+    }
+}
+
+/// Index (0-based) of the value, the values needn't be sorted for the approximate match.
+fn position(value: &CellValue, values: &[CellValue], mode: MatchMode) -> Option<usize> {
+    if let Some(found) = values.iter().position(|candidate| candidate.matches(value)) {
+        return Some(found);
+    }
+    let wanted = match mode {
+        MatchMode::Exact => return None,
+        MatchMode::ExactOrSmaller => Ordering::Less,
+        MatchMode::ExactOrLarger => Ordering::Greater,
+    };
+    values
+        .iter()
+        .enumerate()
+        .filter(|(_, candidate)| candidate.comparable(value) && candidate.compare(value) == wanted)
+        // The closest one, the first of the equal ones.
+        .reduce(
+            |closest, candidate| match candidate.1.compare(closest.1) == wanted {
+                true => closest,
+                false if candidate.1.matches(closest.1) => closest,
+                false => candidate,
+            },
+        )
+        .map(|(found, _)| found)
+}
+
+fn not_found(value: &CellValue) -> String {
+    match value {
+        CellValue::Text(text) => format!(r#""{text}" not found"#),
+        CellValue::Number(number) => format!("{number} not found"),
+        value => format!("{value:?} not found"),
+    }
+}
+
+/// Position of the value in the single row or column range. The `mode` is `0` for the exact
+/// match (by default), `-1` for the exact match or the next smaller value and `1` for the exact
+/// match or the next larger value.
+#[sheeet::function(name = "match")]
+pub fn match_position(value: CellValue, range: Range, mode: Option<i32>) -> Result<usize, String> {
+    position(&value, &range.vector()?, MatchMode::parse(mode)?)
+        .map(|found| found + 1)
+        .ok_or_else(|| not_found(&value))
+}
+
+/// The cell of the range at the `row` and the `column`. The `row` or the `column` 0 gives
+/// the whole column or row. The `column` can be left out for the single column range,
+/// the `row` of the single row range is then its column.
+#[sheeet::function]
+pub fn index(range: Range, row: usize, column: Option<usize>) -> Result<Slice, String> {
+    let (row, column) = match column {
+        Some(column) => (row, column),
+        None if range.height() == 1 && range.width() > 1 => (1, row),
+        None if range.width() == 1 => (row, 1),
+        None => (row, 0),
+    };
+    let rows = match row {
+        0 => 0..range.height(),
+        row => row - 1..row,
+    };
+    let cols = match column {
+        0 => 0..range.width(),
+        column => column - 1..column,
+    };
+    range.slice(rows, cols)
+}
+
+/// Looks the value up in the single row or column `lookup_range` and returns the matching row
+/// (or column) of the `return_range`, `if_not_found` (an error by default) if there's no match.
+/// The `mode` is as of [`match`](match_position).
+#[sheeet::function]
+pub fn xlookup(
+    value: CellValue,
+    lookup_range: Range,
+    return_range: Range,
+    if_not_found: Option<CellValue>,
+    mode: Option<i32>,
+) -> Result<Slice, String> {
+    let found = position(&value, &lookup_range.vector()?, MatchMode::parse(mode)?);
+    let Some(found) = found else {
+        return if_not_found
+            .map(Slice::value)
+            .ok_or_else(|| not_found(&value));
+    };
+    // The column looks up the rows, the row looks up the columns.
+    match lookup_range.height() == 1 && lookup_range.width() > 1 {
+        false if return_range.height() == lookup_range.height() => {
+            return_range.slice(found..found + 1, 0..return_range.width())
+        }
+        true if return_range.width() == lookup_range.width() => {
+            return_range.slice(0..return_range.height(), found..found + 1)
+        }
+        false => Err(format!(
+            "the return range has {} rows, the lookup range {}",
+            return_range.height(),
+            lookup_range.height()
+        )),
+        true => Err(format!(
+            "the return range has {} columns, the lookup range {}",
+            return_range.width(),
+            lookup_range.width()
+        )),
+    }
+}
+
+fn lookup_mode(approximate: Option<bool>) -> MatchMode {
+    match approximate.unwrap_or(false) {
+        true => MatchMode::ExactOrSmaller,
+        false => MatchMode::Exact,
+    }
+}
+
+/// Looks the value up in the table's first column and returns the `column` of the matching row.
+/// The `approximate` match finds the largest value not greater than the value.
+#[sheeet::function]
+pub fn vlookup(
+    value: CellValue,
+    table: Range,
+    column: usize,
+    approximate: Option<bool>,
+) -> Result<Slice, String> {
+    let keys: Vec<CellValue> = table
+        .rows()
+        .iter()
+        .map(|row| row.first().cloned().unwrap_or(CellValue::Empty))
+        .collect();
+    let found =
+        position(&value, &keys, lookup_mode(approximate)).ok_or_else(|| not_found(&value))?;
+    match column {
+        0 => Err("column must be at least 1, got 0".into()),
+        column => table.slice(found..found + 1, column - 1..column),
+    }
+}
+
+/// Looks the value up in the table's first row and returns the `row` of the matching column.
+/// The `approximate` match finds the largest value not greater than the value.
+#[sheeet::function]
+pub fn hlookup(
+    value: CellValue,
+    table: Range,
+    row: usize,
+    approximate: Option<bool>,
+) -> Result<Slice, String> {
+    let keys = table.rows().first().cloned().unwrap_or_default();
+    let found =
+        position(&value, &keys, lookup_mode(approximate)).ok_or_else(|| not_found(&value))?;
+    match row {
+        0 => Err("row must be at least 1, got 0".into()),
+        row => table.slice(row - 1..row, found..found + 1),
+    }
+}
+
+fn truthy(value: &CellValue) -> Result<bool, String> {
+    match value {
+        CellValue::Empty => Ok(false),
+        CellValue::Bool(boolean) => Ok(*boolean),
+        CellValue::Text(text) if text.eq_ignore_ascii_case("true") => Ok(true),
+        CellValue::Text(text) if text.eq_ignore_ascii_case("false") => Ok(false),
+        CellValue::Error(message) => Err(message.clone()),
+        value => match value.compare(&CellValue::Number(0.0)) {
+            Ordering::Equal => Ok(false),
+            _ if value.comparable(&CellValue::Number(0.0)) => Ok(true),
+            _ => Err(format!("expected true or false, got {value:?}")),
+        },
+    }
+}
+
+/// Keys of the range's rows (or the columns of the single row range), errors if there's not
+/// one for each.
+fn keys_of(range: &Range, keys: &Range) -> Result<Vec<CellValue>, String> {
+    let keys = keys.vector()?;
+    let (count, unit) = match range.height() {
+        1 => (range.width(), "columns"),
+        height => (height, "rows"),
+    };
+    match keys.len() == count {
+        true => Ok(keys),
+        false => Err(format!(
+            "expected {count} values for the {count} {unit}, got {}",
+            keys.len()
+        )),
+    }
+}
+
+/// Transposes the single row range to a column and back, the row's values are then handled
+/// as the rows.
+fn by_rows(
+    range: Range,
+    handle: impl FnOnce(Vec<Vec<CellValue>>) -> Vec<Vec<CellValue>>,
+) -> Vec<Vec<CellValue>> {
+    match range.height() {
+        1 => {
+            let column = range
+                .into_rows()
+                .into_iter()
+                .flatten()
+                .map(|value| vec![value])
+                .collect();
+            vec![handle(column).into_iter().flatten().collect()]
+        }
+        _ => handle(range.into_rows()),
+    }
+}
+
+/// Rows of the range (or the columns of the single row range) with `include` true.
+/// The result spills into the cells below.
+#[sheeet::function]
+pub fn filter(range: Range, include: Range) -> Result<Vec<Vec<CellValue>>, String> {
+    let include = keys_of(&range, &include)?
+        .iter()
+        .map(truthy)
+        .collect::<Result<Vec<_>, _>>()?;
+    if !include.contains(&true) {
+        return Err("no rows match the filter".into());
+    }
+    Ok(by_rows(range, |rows| {
+        rows.into_iter()
+            .zip(include)
+            .filter_map(|(row, include)| include.then_some(row))
+            .collect()
+    }))
+}
+
+/// Distinct rows of the range (or the values of the single row range), in their order.
+#[sheeet::function]
+pub fn unique(range: Range) -> Vec<Vec<CellValue>> {
+    by_rows(range, |rows| {
+        let mut unique: Vec<Vec<CellValue>> = Vec::new();
+        for row in rows {
+            let seen = unique.iter().any(|other| {
+                other
+                    .iter()
+                    .zip(&row)
+                    .all(|(value, other)| value.matches(other))
+            });
+            if !seen {
+                unique.push(row);
+            }
+        }
+        unique
+    })
+}
+
+fn sort_rows(rows: &mut [(CellValue, Vec<CellValue>)], descending: Option<bool>) {
+    let descending = descending.unwrap_or(false);
+    rows.sort_by(
+        |(key, _), (other, _)| match (key.is_empty(), other.is_empty()) {
+            // The empty cells are last in both orders.
+            (true, false) => Ordering::Greater,
+            (false, true) => Ordering::Less,
+            _ if descending => other.compare(key),
+            _ => key.compare(other),
+        },
+    );
+}
+
+/// Sorts the rows of the range by their `column` (the first by default), the values
+/// of the single row range. The empty cells are last.
+#[sheeet::function]
+pub fn sort(
+    range: Range,
+    column: Option<usize>,
+    descending: Option<bool>,
+) -> Result<Vec<Vec<CellValue>>, String> {
+    let column = match column.unwrap_or(1) {
+        column if (1..=range.width().max(1)).contains(&column) => column - 1,
+        column => {
+            return Err(format!(
+                "column {column} out of the range's {} columns",
+                range.width()
+            ));
+        }
+    };
+    let column = match range.height() {
+        1 => 0,
+        _ => column,
+    };
+    Ok(by_rows(range, |rows| {
+        let mut keyed: Vec<_> = rows
+            .into_iter()
+            .map(|row| (row.get(column).cloned().unwrap_or(CellValue::Empty), row))
+            .collect();
+        sort_rows(&mut keyed, descending);
+        keyed.into_iter().map(|(_, row)| row).collect()
+    }))
+}
+
+/// Sorts the rows of the range (or the values of the single row range) by the `keys`, one for
+/// each. The empty keys are last.
+#[sheeet::function]
+pub fn sort_by(
+    range: Range,
+    keys: Range,
+    descending: Option<bool>,
+) -> Result<Vec<Vec<CellValue>>, String> {
+    let keys = keys_of(&range, &keys)?;
+    Ok(by_rows(range, |rows| {
+        let mut keyed: Vec<_> = keys.into_iter().zip(rows).collect();
+        sort_rows(&mut keyed, descending);
+        keyed.into_iter().map(|(_, row)| row).collect()
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use CellValue::{Empty, Number, Text};
+
+    fn text(text: &str) -> CellValue {
+        Text(text.into())
+    }
+
+    fn table(origin: Option<(usize, usize)>) -> Range {
+        Range::new(
+            vec![
+                vec![text("id"), text("name"), text("price")],
+                vec![text("1"), text("Apple"), text("12.5")],
+                vec![text("2"), text("banana"), Empty],
+                vec![text("3"), text("Cherry"), text("30")],
+            ],
+            origin,
+        )
+    }
+
+    fn column(values: &[CellValue]) -> Range {
+        Range::new(
+            values.iter().map(|value| vec![value.clone()]).collect(),
+            None,
+        )
+    }
+
+    #[test]
+    fn test_match() {
+        let numbers = column(&[Number(10.0), text("20"), Number(30.0), text("x")]);
+        assert_eq!(match_position(Number(20.0), numbers.clone(), None), Ok(2));
+        assert_eq!(match_position(text("X"), numbers.clone(), None), Ok(4));
+        // The non-finite words are the texts, as in the sheet's comparisons.
+        let words = column(&[text("inf"), text("NaN")]);
+        assert_eq!(match_position(text("nan"), words.clone(), None), Ok(2));
+        assert_eq!(
+            match_position(Number(f64::INFINITY), words, None),
+            Err("inf not found".into())
+        );
+        assert_eq!(
+            match_position(Number(25.0), numbers.clone(), Some(-1)),
+            Ok(2)
+        );
+        assert_eq!(
+            match_position(Number(25.0), numbers.clone(), Some(1)),
+            Ok(3)
+        );
+        assert_eq!(
+            match_position(Number(5.0), numbers.clone(), Some(-1)),
+            Err("5 not found".into())
+        );
+        assert_eq!(
+            match_position(Number(1.0), table(None), None),
+            Err("expected a single row or column, got 4 rows and 3 columns".into())
+        );
+        assert_eq!(
+            match_position(Number(1.0), numbers, Some(2)),
+            Err("unknown match mode 2, expected 0, -1 or 1".into())
+        );
+    }
+
+    #[test]
+    fn test_index() {
+        let cell = index(table(Some((2, 3))), 2, Some(3)).unwrap();
+        assert_eq!(cell.rows, vec![vec![text("12.5")]]);
+        // B3 is the top left cell, the price of the apple is in D4.
+        assert_eq!(cell.reference, Some(((4, 4), (4, 4))));
+        let row = index(table(Some((2, 3))), 4, Some(0)).unwrap();
+        assert_eq!(row.reference, Some(((2, 6), (4, 6))));
+        assert_eq!(
+            index(table(None), 3, Some(2)).unwrap(),
+            Slice::value(text("banana"))
+        );
+        assert_eq!(
+            index(table(None), 5, Some(1)),
+            Err("row 5 out of the range's 4 rows".into())
+        );
+        let values = Range::new(vec![vec![text("a"), text("b")]], None);
+        assert_eq!(index(values, 2, None), Ok(Slice::value(text("b"))));
+    }
+
+    #[test]
+    fn test_lookups() {
+        let names = column(&[text("id"), text("1"), text("2"), text("3")]);
+        let found = xlookup(Number(2.0), names.clone(), table(Some((1, 1))), None, None).unwrap();
+        assert_eq!(found.reference, Some(((1, 3), (3, 3))));
+        assert_eq!(
+            xlookup(
+                Number(9.0),
+                names.clone(),
+                table(None),
+                Some(text("none")),
+                None
+            ),
+            Ok(Slice::value(text("none")))
+        );
+        assert_eq!(
+            xlookup(Number(9.0), names, table(None), None, None),
+            Err("9 not found".into())
+        );
+        assert_eq!(
+            vlookup(text("3"), table(None), 2, None),
+            Ok(Slice::value(text("Cherry")))
+        );
+        assert_eq!(
+            vlookup(Number(2.7), table(None), 2, Some(true)),
+            Ok(Slice::value(text("banana")))
+        );
+        assert_eq!(
+            vlookup(text("1"), table(None), 0, None),
+            Err("column must be at least 1, got 0".into())
+        );
+        assert_eq!(
+            hlookup(text("NAME"), table(None), 3, None),
+            Ok(Slice::value(text("banana")))
+        );
+    }
+
+    #[test]
+    fn test_filter_unique_sort() {
+        let include = column(&[Empty, CellValue::Bool(true), text("false"), Number(1.0)]);
+        assert_eq!(
+            filter(table(None), include),
+            Ok(vec![
+                table(None).rows()[1].clone(),
+                table(None).rows()[3].clone()
+            ])
+        );
+        assert_eq!(
+            filter(table(None), column(&[Empty])),
+            Err("expected 4 values for the 4 rows, got 1".into())
+        );
+        let row = Range::new(
+            vec![vec![text("b"), text("a"), text("B"), Number(2.0), Empty]],
+            None,
+        );
+        assert_eq!(
+            unique(row.clone()),
+            vec![vec![text("b"), text("a"), Number(2.0), Empty]]
+        );
+        assert_eq!(
+            sort(row.clone(), None, None),
+            Ok(vec![vec![
+                Number(2.0),
+                text("a"),
+                text("b"),
+                text("B"),
+                Empty
+            ]])
+        );
+        assert_eq!(
+            sort(row, None, Some(true)),
+            Ok(vec![vec![
+                text("b"),
+                text("B"),
+                text("a"),
+                Number(2.0),
+                Empty
+            ]])
+        );
+        let sorted = sort(table(None), Some(3), Some(true)).unwrap();
+        let names: Vec<_> = sorted.iter().map(|row| row[1].clone()).collect();
+        // The header is a text, greater than the numbers.
+        assert_eq!(
+            names,
+            [text("name"), text("Cherry"), text("Apple"), text("banana")]
+        );
+        let by = sort_by(
+            table(None),
+            column(&[Number(4.0), Number(3.0), Number(2.0), Number(1.0)]),
+            None,
+        )
+        .unwrap();
+        assert_eq!(by[0][1], text("Cherry"));
+    }
+}
@@ -0,0 +1,194 @@
+//! Numeric type of the arithmetic functions, `f64` by default. The `decimal` feature switches
+//! it to the exact decimal for the money math, e.g. `add(0.1, 0.2)` is then exactly `0.3`.
+//!
+//! [`Number`] has the same API with and without the feature, so the crates using it build
+//! either way, the feature enabled anywhere in the build changes only the precision.
+
+#[cfg(feature = "decimal")]
+use crate::value::parse_decimal;
+use crate::value::parse_number;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+#[cfg(not(feature = "decimal"))]
+type Repr = f64;
+
+#[cfg(feature = "decimal")]
+type Repr = rust_decimal::Decimal;
+
+/// Number of the arithmetic functions, the exact decimal with the `decimal` feature.
+#[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd)]
+pub struct Number(pub(crate) Repr);
+
+impl Number {
+    /// `None` for the NaN and the infinities, and the numbers out of the decimal's range.
+    #[cfg(not(feature = "decimal"))]
+    pub fn from_f64(number: f64) -> Option<Self> {
+        number.is_finite().then_some(Number(number))
+    }
+
+    /// `None` for the NaN and the infinities, and the numbers out of the decimal's range.
+    /// The decimal is the number's shortest representation, e.g. `0.1` and not the closest
+    /// binary fraction.
+    #[cfg(feature = "decimal")]
+    pub fn from_f64(number: f64) -> Option<Self> {
+        if !number.is_finite() {
+            return None;
+        }
+        parse_decimal(&number.to_string()).ok().map(Number)
+    }
+
+    #[cfg(not(feature = "decimal"))]
+    pub fn to_f64(self) -> f64 {
+        self.0
+    }
+
+    /// The closest `f64`, the decimal's digits beyond its precision are lost.
+    #[cfg(feature = "decimal")]
+    pub fn to_f64(self) -> f64 {
+        rust_decimal::prelude::ToPrimitive::to_f64(&self.0).unwrap_or(f64::NAN)
+    }
+
+    /// Whether the `f64` holds the number exactly, e.g. to return it as the JS number.
+    pub fn is_exact_f64(self) -> bool {
+        Number::from_f64(self.to_f64()) == Some(self)
+    }
+}
+
+/// Parses the numeric text, exactly with the `decimal` feature, the empty text is zero.
+impl FromStr for Number {
+    type Err = String;
+
+    #[cfg(not(feature = "decimal"))]
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let number = parse_number(text)?;
+        Number::from_f64(number).ok_or_else(|| format!(r#"expected a finite number, got "{text}""#))
+    }
+
+    #[cfg(feature = "decimal")]
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        // Validated as the number first, for the same error messages.
+        parse_number(text)?;
+        parse_decimal(text).map(Number)
+    }
+}
+
+impl Display for Number {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        #[cfg(feature = "decimal")]
+        let number = self.0.normalize();
+        #[cfg(not(feature = "decimal"))]
+        let number = self.0;
+        Display::fmt(&number, f)
+    }
+}
+
+/// Arithmetic of the [`Number`], `None` instead of the overflows and the non-finite results.
+pub trait CheckedArithmetic: Sized {
+    fn try_add(self, other: Self) -> Option<Self>;
+    fn try_sub(self, other: Self) -> Option<Self>;
+    fn try_mul(self, other: Self) -> Option<Self>;
+    /// `None` also for the division by zero.
+    fn try_div(self, other: Self) -> Option<Self>;
+    fn try_pow(self, exponent: Self) -> Option<Self>;
+}
+
+fn finite(number: f64) -> Option<f64> {
+    number.is_finite().then_some(number)
+}
+
+impl CheckedArithmetic for f64 {
+    fn try_add(self, other: Self) -> Option<Self> {
+        finite(self + other)
+    }
+
+    fn try_sub(self, other: Self) -> Option<Self> {
+        finite(self - other)
+    }
+
+    fn try_mul(self, other: Self) -> Option<Self> {
+        finite(self * other)
+    }
+
+    fn try_div(self, other: Self) -> Option<Self> {
+        finite(self / other)
+    }
+
+    fn try_pow(self, exponent: Self) -> Option<Self> {
+        finite(self.powf(exponent))
+    }
+}
+
+#[cfg(feature = "decimal")]
+impl CheckedArithmetic for rust_decimal::Decimal {
+    fn try_add(self, other: Self) -> Option<Self> {
+        self.checked_add(other)
+    }
+
+    fn try_sub(self, other: Self) -> Option<Self> {
+        self.checked_sub(other)
+    }
+
+    fn try_mul(self, other: Self) -> Option<Self> {
+        self.checked_mul(other)
+    }
+
+    fn try_div(self, other: Self) -> Option<Self> {
+        self.checked_div(other)
+    }
+
+    fn try_pow(self, exponent: Self) -> Option<Self> {
+        rust_decimal::MathematicalOps::checked_powd(&self, exponent)
+    }
+}
+
+impl CheckedArithmetic for Number {
+    fn try_add(self, other: Self) -> Option<Self> {
+        self.0.try_add(other.0).map(Number)
+    }
+
+    fn try_sub(self, other: Self) -> Option<Self> {
+        self.0.try_sub(other.0).map(Number)
+    }
+
+    fn try_mul(self, other: Self) -> Option<Self> {
+        self.0.try_mul(other.0).map(Number)
+    }
+
+    fn try_div(self, other: Self) -> Option<Self> {
+        self.0.try_div(other.0).map(Number)
+    }
+
+    fn try_pow(self, exponent: Self) -> Option<Self> {
+        self.0.try_pow(exponent.0).map(Number)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_number() {
+        let number = |text: &str| text.parse::<Number>().unwrap();
+        assert_eq!(number(" 2.50 ").to_string(), "2.5");
+        assert_eq!(number(""), Number::default());
+        assert_eq!(number("-1.5e2").to_f64(), -150.0);
+        assert_eq!(Number::from_f64(0.1), Some(number("0.1")));
+        assert_eq!(Number::from_f64(f64::NAN), None);
+        assert!(number("1") < number("1.5"));
+        assert!(number("0.1").is_exact_f64());
+        assert_eq!(
+            "abc".parse::<Number>(),
+            Err(r#"expected a number, got "abc""#.into())
+        );
+    }
+
+    #[cfg(feature = "decimal")]
+    #[test]
+    fn test_decimal_number() {
+        let number = |text: &str| text.parse::<Number>().unwrap();
+        assert_eq!(number("0.10").to_string(), "0.1");
+        assert!(!number("0.1000000000000000000000000001").is_exact_f64());
+    }
+}
@@ -0,0 +1,295 @@
+//! Ranges of the cells for the lookup functions. The sheet passes the range as the rows of its
+//! values (the empty cells are `null`) with the position of its top left cell, so the function
+//! can return a part of the range as the reference, see [`Slice`].
+
+use crate::date;
+use crate::value::{FromSheetValue, IntoSheetValue, parse_number};
+use chrono::NaiveDateTime;
+use js_sys::{Array, Reflect};
+use std::cmp::Ordering;
+use wasm_bindgen::{JsCast, JsValue};
+
+/// Property of the range value with the column and the row of its top left cell.
+const ORIGIN_PROPERTY: &str = "__sheeet_origin";
+/// Property of the returned object with the referenced cells, the columns and the rows
+/// of the top left and the bottom right cell.
+const REFERENCE_PROPERTY: &str = "__sheeet_reference";
+
+/// Value of the range's cell.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CellValue {
+    Empty,
+    Number(f64),
+    Text(String),
+    Bool(bool),
+    Date(NaiveDateTime),
+    Error(String),
+}
+
+impl CellValue {
+    /// The numbers, the numeric texts and the dates (their serial numbers) are compared
+    /// as the numbers.
+    fn number(&self) -> Option<f64> {
+        match self {
+            CellValue::Number(number) => Some(*number),
+            CellValue::Date(date_time) => Some(date::to_serial(*date_time)),
+            CellValue::Text(text) if !text.trim().is_empty() => parse_number(text).ok(),
+            _ => None,
+        }
+    }
+
+    fn rank(&self) -> u8 {
+        match self {
+            _ if self.number().is_some() => 0,
+            CellValue::Text(_) => 1,
+            CellValue::Bool(_) => 2,
+            CellValue::Error(_) => 3,
+            _ => 4,
+        }
+    }
+
+    /// Whether the values are of the same kind, the lookups compare only those.
+    pub fn comparable(&self, other: &Self) -> bool {
+        self.rank() == other.rank()
+    }
+
+    /// Order of the lookups and the sorting: the numbers (and the dates) first, then
+    /// the texts (case-insensitive), the booleans, the errors and the empty cells last.
+    pub fn compare(&self, other: &Self) -> Ordering {
+        if let (Some(number), Some(other)) = (self.number(), other.number()) {
+            return number.total_cmp(&other);
+        }
+        self.rank()
+            .cmp(&other.rank())
+            .then_with(|| match (self, other) {
+                (CellValue::Text(text), CellValue::Text(other)) => {
+                    text.to_lowercase().cmp(&other.to_lowercase())
+                }
+                (CellValue::Bool(boolean), CellValue::Bool(other)) => boolean.cmp(other),
+                (CellValue::Error(message), CellValue::Error(other)) => message.cmp(other),
+                _ => Ordering::Equal,
+            })
+    }
+
+    /// Whether the lookup finds the value, e.g. `"42"` matches `42` and `"abc"` matches `"ABC"`.
+    pub fn matches(&self, other: &Self) -> bool {
+        self.compare(other) == Ordering::Equal
+    }
+
+    pub fn is_empty(&self) -> bool {
+        matches!(self, CellValue::Empty)
+    }
+}
+
+impl FromSheetValue for CellValue {
+    fn from_sheet_value(value: JsValue) -> Result<Self, String> {
+        if value.is_null() || value.is_undefined() {
+            return Ok(CellValue::Empty);
+        }
+        if let Some(number) = value.as_f64() {
+            return Ok(CellValue::Number(number));
+        }
+        if let Some(boolean) = value.as_bool() {
+            return Ok(CellValue::Bool(boolean));
+        }
+        if let Some(text) = value.as_string() {
+            return Ok(match text.is_empty() {
+                true => CellValue::Empty,
+                false => CellValue::Text(text),
+            });
+        }
+        if value.is_instance_of::<js_sys::Date>() {
+            return NaiveDateTime::from_sheet_value(value).map(CellValue::Date);
+        }
+        match value.dyn_ref::<js_sys::Error>() {
+            Some(error) => Ok(CellValue::Error(error.message().into())),
+            None => Err(format!("expected a cell value, got {value:?}")),
+        }
+    }
+}
+
+impl IntoSheetValue for CellValue {
+    fn into_sheet_value(self) -> JsValue {
+        match self {
+            CellValue::Empty => JsValue::null(),
+            CellValue::Number(number) => number.into_sheet_value(),
+            CellValue::Text(text) => text.into_sheet_value(),
+            CellValue::Bool(boolean) => boolean.into_sheet_value(),
+            CellValue::Date(date_time) => date_time.into_sheet_value(),
+            CellValue::Error(message) => js_sys::Error::new(&message).into(),
+        }
+    }
+}
+
+/// Rectangular range of the cells, row by row. The single value is the range of one cell,
+/// the flat array (e.g. of `split`) is one row.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Range {
+    rows: Vec<Vec<CellValue>>,
+    /// Column and row of the top left cell in the sheet, `None` for the values which aren't
+    /// the sheet's range, e.g. the array returned by the function.
+    origin: Option<(usize, usize)>,
+}
+
+impl Range {
+    /// The shorter rows are filled with the empty cells.
+    pub fn new(mut rows: Vec<Vec<CellValue>>, origin: Option<(usize, usize)>) -> Self {
+        let width = rows.iter().map(Vec::len).max().unwrap_or(0);
+        for row in &mut rows {
+            row.resize(width, CellValue::Empty);
+        }
+        Range { rows, origin }
+    }
+
+    pub fn height(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn width(&self) -> usize {
+        self.rows.first().map_or(0, Vec::len)
+    }
+
+    pub fn rows(&self) -> &[Vec<CellValue>] {
+        &self.rows
+    }
+
+    pub fn into_rows(self) -> Vec<Vec<CellValue>> {
+        self.rows
+    }
+
+    /// Values of the single row or the single column range.
+    pub fn vector(&self) -> Result<Vec<CellValue>, String> {
+        match (self.height(), self.width()) {
+            (1, _) => Ok(self.rows[0].clone()),
+            (_, 1) => Ok(self.rows.iter().map(|row| row[0].clone()).collect()),
+            (0, _) | (_, 0) => Ok(Vec::new()),
+            (height, width) => Err(format!(
+                "expected a single row or column, got {height} rows and {width} columns"
+            )),
+        }
+    }
+
+    /// The rows and the columns (0-based) of the range, the reference to them if the range
+    /// is the sheet's one.
+    pub fn slice(
+        &self,
+        rows: std::ops::Range<usize>,
+        cols: std::ops::Range<usize>,
+    ) -> Result<Slice, String> {
+        if rows.is_empty() || rows.end > self.height() {
+            return Err(format!(
+                "row {} out of the range's {} rows",
+                rows.end,
+                self.height()
+            ));
+        }
+        if cols.is_empty() || cols.end > self.width() {
+            return Err(format!(
+                "column {} out of the range's {} columns",
+                cols.end,
+                self.width()
+            ));
+        }
+        let reference = self.origin.map(|(col, row)| {
+            (
+                (col + cols.start, row + rows.start),
+                (col + cols.end - 1, row + rows.end - 1),
+            )
+        });
+        Ok(Slice {
+            rows: self.rows[rows]
+                .iter()
+                .map(|row| row[cols.clone()].to_vec())
+                .collect(),
+            reference,
+        })
+    }
+}
+
+fn origin(value: &JsValue) -> Option<(usize, usize)> {
+    let origin = Reflect::get(value, &ORIGIN_PROPERTY.into()).ok()?;
+    if !Array::is_array(&origin) {
+        return None;
+    }
+    let origin = Array::from(&origin);
+    Some((
+        origin.get(0).as_f64()? as usize,
+        origin.get(1).as_f64()? as usize,
+    ))
+}
+
+impl FromSheetValue for Range {
+    fn from_sheet_value(value: JsValue) -> Result<Self, String> {
+        if !Array::is_array(&value) {
+            return Ok(Range::new(
+                vec![vec![CellValue::from_sheet_value(value)?]],
+                None,
+            ));
+        }
+        let array = Array::from(&value);
+        let rows = match array.iter().any(|row| Array::is_array(&row)) {
+            true => array
+                .iter()
+                .map(|row| match Array::is_array(&row) {
+                    true => Array::from(&row)
+                        .iter()
+                        .map(CellValue::from_sheet_value)
+                        .collect(),
+                    false => CellValue::from_sheet_value(row).map(|value| vec![value]),
+                })
+                .collect::<Result<_, _>>()?,
+            false => vec![
+                array
+                    .iter()
+                    .map(CellValue::from_sheet_value)
+                    .collect::<Result<_, _>>()?,
+            ],
+        };
+        Ok(Range::new(rows, origin(&value)))
+    }
+}
+
+impl IntoSheetValue for Range {
+    fn into_sheet_value(self) -> JsValue {
+        self.rows.into_sheet_value()
+    }
+}
+
+/// Part of the [`Range`] returned by the function. The part of the sheet's range is returned
+/// as the reference, the sheet shows the referenced cells' values and recalculates
+/// the function's cell once they change, the part of the other values as the values.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Slice {
+    pub rows: Vec<Vec<CellValue>>,
+    /// Column and row of the top left and the bottom right cell.
+    pub reference: Option<((usize, usize), (usize, usize))>,
+}
+
+impl Slice {
+    /// The single value which isn't a part of the sheet's range.
+    pub fn value(value: CellValue) -> Self {
+        Slice {
+            rows: vec![vec![value]],
+            reference: None,
+        }
+    }
+}
+
+impl IntoSheetValue for Slice {
+    fn into_sheet_value(self) -> JsValue {
+        if let Some(((col, row), (end_col, end_row))) = self.reference {
+            let corners: Array = [col, row, end_col, end_row]
+                .into_iter()
+                .map(|corner| JsValue::from(corner as u32))
+                .collect();
+            let reference = js_sys::Object::new();
+            // Setting a property of a plain object can't fail.
+            _ = Reflect::set(&reference, &REFERENCE_PROPERTY.into(), &corners);
+            return reference.into();
+        }
+        match (self.rows.len(), self.rows.first().map_or(0, Vec::len)) {
+            (1, 1) => self.rows.into_iter().flatten().next().into_sheet_value(),
+            _ => self.rows.into_sheet_value(),
+        }
+    }
+}
@@ -0,0 +1,429 @@
+//! Statistics of the ranges. The functions taking a single collection are variadic, so
+//! the ranges and the single values can be mixed, e.g. `=median(A1:A10, B1, 4)`. The functions
+//! undefined for the empty input (or too few values) return the error value, the counts and
+//! the sum are zero.
+//!
+//! The texts in the ranges, e.g. the column's header, are skipped, as in the spreadsheets, the
+//! text given directly must be a number. The paired functions skip them in each range alone,
+//! their texts have to be in the same rows.
+//!
+//! Only the sum takes the [`Number`], so the money totals are exact with the `decimal` feature.
+//! The other statistics compute in `f64`, their means, roots and interpolations aren't exact
+//! in the decimals either.
+
+use crate as sheeet;
+use crate::date::parse_date_time;
+use crate::number::{CheckedArithmetic, Number};
+use crate::value::parse_number;
+
+fn no_numbers(function: &str) -> String {
+    format!("{function} of no numbers")
+}
+
+fn not_enough_numbers(function: &str, min: usize, got: usize) -> String {
+    format!("{function} needs at least {min} numbers, got {got}")
+}
+
+fn sorted(mut numbers: Vec<f64>) -> Vec<f64> {
+    numbers.sort_by(f64::total_cmp);
+    numbers
+}
+
+fn mean(numbers: &[f64]) -> f64 {
+    numbers.iter().sum::<f64>() / numbers.len() as f64
+}
+
+/// Sum of the squared deviations from the mean.
+fn squared_deviations(numbers: &[f64]) -> f64 {
+    let mean = mean(numbers);
+    numbers.iter().map(|number| (number - mean).powi(2)).sum()
+}
+
+/// Sum of the products of the paired deviations from the means.
+fn co_deviations(xs: &[f64], ys: &[f64]) -> f64 {
+    let (x_mean, y_mean) = (mean(xs), mean(ys));
+    xs.iter()
+        .zip(ys)
+        .map(|(x, y)| (x - x_mean) * (y - y_mean))
+        .sum()
+}
+
+/// Checks the paired collections have the same length and at least `min` pairs.
+fn pairs(function: &str, xs: &[f64], ys: &[f64], min: usize) -> Result<(), String> {
+    if xs.len() != ys.len() {
+        return Err(format!(
+            "{function} needs the same number of values, got {} and {}",
+            xs.len(),
+            ys.len()
+        ));
+    }
+    if xs.len() < min {
+        return Err(not_enough_numbers(function, min, xs.len()));
+    }
+    Ok(())
+}
+
+/// Linearly interpolated value at the position `q` (between 0 and 1) of the sorted numbers.
+fn interpolate(sorted: &[f64], q: f64) -> f64 {
+    let position = q * (sorted.len() - 1) as f64;
+    let lower = position.floor() as usize;
+    let upper = position.ceil() as usize;
+    sorted[lower] + (sorted[upper] - sorted[lower]) * (position - lower as f64)
+}
+
+/// Sum of the numbers, exact with the `decimal` feature.
+#[sheeet::function(variadic)]
+pub fn sum(numbers: Vec<Number>) -> Result<Number, String> {
+    numbers
+        .into_iter()
+        .try_fold(Number::default(), Number::try_add)
+        .ok_or_else(|| "sum overflowed".into())
+}
+
+/// Arithmetic mean of the numbers.
+#[sheeet::function(variadic)]
+pub fn avg(numbers: Vec<f64>) -> Result<f64, String> {
+    if numbers.is_empty() {
+        return Err(no_numbers("avg"));
+    }
+    Ok(mean(&numbers))
+}
+
+/// Median of the numbers, the mean of the two middle ones for an even count.
+#[sheeet::function(variadic)]
+pub fn median(numbers: Vec<f64>) -> Result<f64, String> {
+    if numbers.is_empty() {
+        return Err(no_numbers("median"));
+    }
+    Ok(interpolate(&sorted(numbers), 0.5))
+}
+
+/// Median of the numbers, the older name of `median`.
+#[sheeet::function(variadic)]
+pub fn med(numbers: Vec<f64>) -> Result<f64, String> {
+    median(numbers)
+}
+
+/// Most frequent of the numbers, the first one of them on a tie.
+#[sheeet::function(variadic)]
+pub fn mode(numbers: Vec<f64>) -> Result<f64, String> {
+    let mut counts: Vec<(f64, usize)> = Vec::new();
+    for number in numbers {
+        // The zero and the negative zero are the same value.
+        match counts.iter_mut().find(|(counted, _)| *counted == number) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((number, 1)),
+        }
+    }
+    // `max_by_key` takes the last maximum, the first one is expected.
+    let most_frequent = counts
+        .into_iter()
+        .rev()
+        .max_by_key(|(_, count)| *count)
+        .ok_or_else(|| no_numbers("mode"))?;
+    match most_frequent {
+        (number, count) if count > 1 => Ok(number),
+        _ => Err("mode of no repeated numbers".into()),
+    }
+}
+
+/// The smallest of the numbers.
+#[sheeet::function(variadic)]
+pub fn min(numbers: Vec<f64>) -> Result<f64, String> {
+    numbers
+        .into_iter()
+        .min_by(f64::total_cmp)
+        .ok_or_else(|| no_numbers("min"))
+}
+
+/// The largest of the numbers.
+#[sheeet::function(variadic)]
+pub fn max(numbers: Vec<f64>) -> Result<f64, String> {
+    numbers
+        .into_iter()
+        .max_by(f64::total_cmp)
+        .ok_or_else(|| no_numbers("max"))
+}
+
+/// Number of the numeric values and the dates, which are numbers as well, the other texts
+/// are not counted.
+#[sheeet::function(variadic)]
+pub fn count(values: Vec<String>) -> usize {
+    values
+        .iter()
+        .filter(|value| {
+            !value.trim().is_empty()
+                && (parse_number(value).is_ok() || parse_date_time(value).is_ok())
+        })
+        .count()
+}
+
+/// Number of the non-empty values.
+#[sheeet::function(variadic)]
+pub fn counta(values: Vec<String>) -> usize {
+    values.iter().filter(|value| !value.is_empty()).count()
+}
+
+/// Variance of the sample of the population.
+#[sheeet::function(variadic)]
+pub fn var_s(numbers: Vec<f64>) -> Result<f64, String> {
+    if numbers.len() < 2 {
+        return Err(not_enough_numbers("var_s", 2, numbers.len()));
+    }
+    Ok(squared_deviations(&numbers) / (numbers.len() - 1) as f64)
+}
+
+/// Variance of the whole population.
+#[sheeet::function(variadic)]
+pub fn var_p(numbers: Vec<f64>) -> Result<f64, String> {
+    if numbers.is_empty() {
+        return Err(no_numbers("var_p"));
+    }
+    Ok(squared_deviations(&numbers) / numbers.len() as f64)
+}
+
+/// Standard deviation of the sample of the population.
+#[sheeet::function(variadic)]
+pub fn stdev_s(numbers: Vec<f64>) -> Result<f64, String> {
+    if numbers.len() < 2 {
+        return Err(not_enough_numbers("stdev_s", 2, numbers.len()));
+    }
+    Ok(var_s(numbers)?.sqrt())
+}
+
+/// Standard deviation of the whole population.
+#[sheeet::function(variadic)]
+pub fn stdev_p(numbers: Vec<f64>) -> Result<f64, String> {
+    if numbers.is_empty() {
+        return Err(no_numbers("stdev_p"));
+    }
+    Ok(var_p(numbers)?.sqrt())
+}
+
+/// Value below which the fraction `q` (between 0 and 1) of the numbers lies, linearly
+/// interpolated between the closest ones.
+#[sheeet::function]
+pub fn quantile(numbers: Vec<f64>, q: f64) -> Result<f64, String> {
+    if !(0.0..=1.0).contains(&q) {
+        return Err(format!("quantile must be between 0 and 1, got {q}"));
+    }
+    if numbers.is_empty() {
+        return Err(no_numbers("quantile"));
+    }
+    Ok(interpolate(&sorted(numbers), q))
+}
+
+/// Value below which the percentage `p` (between 0 and 100) of the numbers lies, linearly
+/// interpolated between the closest ones.
+#[sheeet::function]
+pub fn percentile(numbers: Vec<f64>, p: f64) -> Result<f64, String> {
+    if !(0.0..=100.0).contains(&p) {
+        return Err(format!("percentile must be between 0 and 100, got {p}"));
+    }
+    if numbers.is_empty() {
+        return Err(no_numbers("percentile"));
+    }
+    Ok(interpolate(&sorted(numbers), p / 100.0))
+}
+
+/// Covariance of the paired samples.
+#[sheeet::function]
+pub fn covar_s(xs: Vec<f64>, ys: Vec<f64>) -> Result<f64, String> {
+    pairs("covar_s", &xs, &ys, 2)?;
+    Ok(co_deviations(&xs, &ys) / (xs.len() - 1) as f64)
+}
+
+/// Covariance of the paired populations.
+#[sheeet::function]
+pub fn covar_p(xs: Vec<f64>, ys: Vec<f64>) -> Result<f64, String> {
+    pairs("covar_p", &xs, &ys, 1)?;
+    Ok(co_deviations(&xs, &ys) / xs.len() as f64)
+}
+
+/// Pearson correlation coefficient of the paired values.
+#[sheeet::function]
+pub fn correl(xs: Vec<f64>, ys: Vec<f64>) -> Result<f64, String> {
+    pairs("correl", &xs, &ys, 2)?;
+    let deviations = (squared_deviations(&xs) * squared_deviations(&ys)).sqrt();
+    if deviations == 0.0 {
+        return Err("correl of constant values".into());
+    }
+    Ok(co_deviations(&xs, &ys) / deviations)
+}
+
+/// Slope of the least squares regression line of `ys` on `xs`.
+#[sheeet::function]
+pub fn slope(ys: Vec<f64>, xs: Vec<f64>) -> Result<f64, String> {
+    pairs("slope", &xs, &ys, 2)?;
+    let x_deviations = squared_deviations(&xs);
+    if x_deviations == 0.0 {
+        return Err("slope of constant xs".into());
+    }
+    Ok(co_deviations(&xs, &ys) / x_deviations)
+}
+
+/// Intercept of the least squares regression line of `ys` on `xs` with the y axis.
+#[sheeet::function]
+pub fn intercept(ys: Vec<f64>, xs: Vec<f64>) -> Result<f64, String> {
+    let (x_mean, y_mean) = (mean(&xs), mean(&ys));
+    let slope = slope(ys, xs).map_err(|err| err.replacen("slope", "intercept", 1))?;
+    Ok(y_mean - slope * x_mean)
+}
+
+/// Coefficient of determination (r²) of the least squares regression line of `ys` on `xs`.
+#[sheeet::function]
+pub fn rsq(ys: Vec<f64>, xs: Vec<f64>) -> Result<f64, String> {
+    correl(xs, ys)
+        .map(|correl| correl.powi(2))
+        .map_err(|err| err.replacen("correl", "rsq", 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: Result<f64, String>, expected: f64) {
+        let actual = actual.unwrap();
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "expected {expected}, got {actual}"
+        );
+    }
+
+    #[test]
+    fn test_central_tendency() {
+        let number = |text: &str| text.parse::<Number>().unwrap();
+        assert_eq!(sum(vec![]), Ok(Number::default()));
+        assert_eq!(
+            sum(vec![number("0.5"), number("1"), number("2.5")]),
+            Ok(number("4"))
+        );
+        assert_eq!(avg(vec![1.0, 1.0, 7.0]), Ok(3.0));
+        assert_eq!(median(vec![5.0, 1.0, 3.0]), Ok(3.0));
+        assert_eq!(median(vec![4.0, 1.0, 3.0, 2.0]), Ok(2.5));
+        assert_eq!(median(vec![-1.0]), Ok(-1.0));
+        assert_eq!(median(vec![]), Err("median of no numbers".into()));
+        assert_eq!(med(vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0]), Ok(3.5));
+        assert_eq!(mode(vec![3.0, 1.0, 1.0, 3.0, 2.0]), Ok(3.0));
+        assert_eq!(mode(vec![0.0, -0.0, 1.0]), Ok(0.0));
+        assert_eq!(
+            mode(vec![1.0, 2.0]),
+            Err("mode of no repeated numbers".into())
+        );
+        assert_eq!(mode(vec![]), Err("mode of no numbers".into()));
+        assert_eq!(avg(vec![]), Err("avg of no numbers".into()));
+        assert_eq!(min(vec![2.0, -3.0, 1.0]), Ok(-3.0));
+        assert_eq!(max(vec![]), Err("max of no numbers".into()));
+        assert_eq!(max(vec![2.0, -3.0, 1.0]), Ok(2.0));
+    }
+
+    #[test]
+    fn test_count() {
+        let values = ["1", " 2.5 ", "abc", "", "true", "-3e2"].map(String::from);
+        assert_eq!(count(values.to_vec()), 3);
+        assert_eq!(counta(values.to_vec()), 5);
+        // The dates come as the texts the sheet shows.
+        let dates = ["2025-03-01", "2025-03-01 14:30", "next March"].map(String::from);
+        assert_eq!(count(dates.to_vec()), 2);
+        assert_eq!(count(vec![]), 0);
+        assert_eq!(counta(vec![]), 0);
+    }
+
+    #[test]
+    fn test_dispersion() {
+        let numbers = vec![2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0];
+        assert_close(var_p(numbers.clone()), 4.0);
+        assert_close(stdev_p(numbers.clone()), 2.0);
+        assert_close(var_s(numbers.clone()), 32.0 / 7.0);
+        assert_close(stdev_s(numbers), (32.0f64 / 7.0).sqrt());
+        assert_eq!(var_p(vec![3.0]), Ok(0.0));
+        assert_eq!(
+            var_s(vec![3.0]),
+            Err("var_s needs at least 2 numbers, got 1".into())
+        );
+        assert_eq!(
+            stdev_s(vec![]),
+            Err("stdev_s needs at least 2 numbers, got 0".into())
+        );
+    }
+
+    #[test]
+    fn test_quantiles() {
+        let numbers = vec![4.0, 1.0, 3.0, 2.0, 5.0];
+        assert_eq!(quantile(numbers.clone(), 0.0), Ok(1.0));
+        assert_eq!(quantile(numbers.clone(), 0.25), Ok(2.0));
+        assert_eq!(quantile(numbers.clone(), 1.0), Ok(5.0));
+        assert_close(quantile(vec![1.0, 2.0], 0.3), 1.3);
+        assert_eq!(percentile(numbers.clone(), 50.0), Ok(3.0));
+        assert_close(percentile(numbers.clone(), 90.0), 4.6);
+        assert_eq!(
+            quantile(numbers.clone(), 1.5),
+            Err("quantile must be between 0 and 1, got 1.5".into())
+        );
+        assert_eq!(
+            percentile(numbers, -1.0),
+            Err("percentile must be between 0 and 100, got -1".into())
+        );
+        assert_eq!(
+            percentile(vec![], 50.0),
+            Err("percentile of no numbers".into())
+        );
+    }
+
+    #[test]
+    fn test_regression() {
+        let xs = vec![1.0, 2.0, 3.0, 4.0];
+        let ys = vec![3.0, 5.0, 7.0, 9.0];
+        assert_close(covar_p(xs.clone(), ys.clone()), 2.5);
+        assert_close(covar_s(xs.clone(), ys.clone()), 10.0 / 3.0);
+        assert_close(correl(xs.clone(), ys.clone()), 1.0);
+        assert_close(slope(ys.clone(), xs.clone()), 2.0);
+        assert_close(intercept(ys.clone(), xs.clone()), 1.0);
+        assert_close(rsq(ys.clone(), xs.clone()), 1.0);
+        assert_close(correl(vec![1.0, 2.0, 3.0], vec![1.0, 3.0, 2.0]), 0.5);
+        assert_eq!(
+            slope(ys, vec![1.0, 2.0]),
+            Err("slope needs the same number of values, got 2 and 4".into())
+        );
+        assert_eq!(
+            intercept(vec![1.0, 2.0], vec![5.0, 5.0]),
+            Err("intercept of constant xs".into())
+        );
+        assert_eq!(
+            rsq(vec![1.0], vec![1.0]),
+            Err("rsq needs at least 2 numbers, got 1".into())
+        );
+        assert_eq!(
+            correl(vec![], vec![]),
+            Err("correl needs at least 2 numbers, got 0".into())
+        );
+    }
+
+    /// Called the way the engine calls the variadic function, with the array of the arguments.
+    #[cfg(target_arch = "wasm32")]
+    #[wasm_bindgen_test::wasm_bindgen_test]
+    fn test_texts_of_ranges() {
+        use js_sys::{Array, Error};
+        use wasm_bindgen::{JsCast, JsValue};
+
+        let range = |cells: &[JsValue]| Array::of1(&cells.iter().collect::<Array>());
+        let header = range(&["price".into(), "2".into(), JsValue::from_f64(4.0)]);
+        let arguments = Array::of2(&header, &"6".into());
+        assert_eq!(__sheeet_function_avg(arguments.clone().into()), 4.0);
+        assert_eq!(__sheeet_function_max(arguments.into()), 6.0);
+        let texts = range(&["a".into(), "b".into()]);
+        let no_numbers = __sheeet_function_median(Array::of1(&texts).into());
+        assert_eq!(
+            no_numbers.dyn_into::<Error>().unwrap().message(),
+            "median of no numbers"
+        );
+        // The text given directly isn't skipped.
+        let direct = __sheeet_function_avg(Array::of2(&header, &"abc".into()).into());
+        assert_eq!(
+            direct.dyn_into::<Error>().unwrap().message(),
+            r#"argument 'numbers': expected a number, got "abc""#
+        );
+        let quantile = __sheeet_function_quantile(header.get(0), JsValue::from_f64(0.5));
+        assert_eq!(quantile, 3.0);
+    }
+}
@@ -0,0 +1,280 @@
+//! Text functions. The lengths and the positions (1-based) count the user-perceived characters
+//! (the grapheme clusters), e.g. `len("👍🏽")` is 1. The regex functions need the default
+//! `regex` feature.
+
+use crate as sheeet;
+use unicode_segmentation::UnicodeSegmentation;
+
+fn graphemes(text: &str) -> Vec<&str> {
+    text.graphemes(true).collect()
+}
+
+/// Byte offset of the `position`-th (1-based) character, the text length past the end.
+fn byte_offset(text: &str, position: usize) -> usize {
+    text.grapheme_indices(true)
+        .nth(position - 1)
+        .map_or(text.len(), |(offset, _)| offset)
+}
+
+fn position_argument(name: &str, position: usize) -> Result<usize, String> {
+    match position {
+        0 => Err(format!("{name} must be at least 1, got 0")),
+        position => Ok(position),
+    }
+}
+
+/// Number of the characters.
+#[sheeet::function]
+pub fn len(text: &str) -> usize {
+    text.graphemes(true).count()
+}
+
+#[sheeet::function]
+pub fn upper(text: &str) -> String {
+    text.to_uppercase()
+}
+
+#[sheeet::function]
+pub fn lower(text: &str) -> String {
+    text.to_lowercase()
+}
+
+/// Capitalizes the first letter of every word, lowercases the rest.
+#[sheeet::function]
+pub fn proper(text: &str) -> String {
+    text.split_word_bounds()
+        .map(|word| {
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(first) if first.is_alphabetic() => first
+                    .to_uppercase()
+                    .chain(chars.as_str().to_lowercase().chars())
+                    .collect(),
+                _ => word.to_lowercase(),
+            }
+        })
+        .collect()
+}
+
+/// Removes the leading and trailing whitespace, collapses the inner one to single spaces.
+#[sheeet::function]
+pub fn trim(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// The first `count` characters, the first one by default.
+#[sheeet::function]
+pub fn left(text: &str, count: Option<usize>) -> String {
+    text.graphemes(true).take(count.unwrap_or(1)).collect()
+}
+
+/// The last `count` characters, the last one by default.
+#[sheeet::function]
+pub fn right(text: &str, count: Option<usize>) -> String {
+    let graphemes = graphemes(text);
+    let count = count.unwrap_or(1).min(graphemes.len());
+    graphemes[graphemes.len() - count..].concat()
+}
+
+/// The `count` characters from the position `start` (1-based).
+#[sheeet::function]
+pub fn mid(text: &str, start: usize, count: usize) -> Result<String, String> {
+    let start = position_argument("start", start)?;
+    Ok(text.graphemes(true).skip(start - 1).take(count).collect())
+}
+
+/// Position (1-based) of the first occurrence of the `search` in the text, from the position
+/// `start`, the first one by default. Case-sensitive.
+#[sheeet::function]
+pub fn find(search: &str, text: &str, start: Option<usize>) -> Result<usize, String> {
+    let start = position_argument("start", start.unwrap_or(1))?;
+    let offset = byte_offset(text, start);
+    text[offset..]
+        .find(search)
+        .map(|found| text[..offset + found].graphemes(true).count() + 1)
+        .ok_or_else(|| format!(r#""{search}" not found"#))
+}
+
+/// Replaces all the occurrences of the `search` in the text.
+#[sheeet::function]
+pub fn replace(text: &str, search: &str, replacement: &str) -> Result<String, String> {
+    if search.is_empty() {
+        return Err("search text can't be empty".into());
+    }
+    Ok(text.replace(search, replacement))
+}
+
+/// Splits the text by the separator, into the characters if it's empty. The parts spill
+/// into the cells to the right.
+#[sheeet::function]
+pub fn split(text: &str, separator: &str) -> Vec<String> {
+    match separator {
+        "" => text.graphemes(true).map(String::from).collect(),
+        separator => text.split(separator).map(String::from).collect(),
+    }
+}
+
+/// Joins the texts with the separator, the empty ones are left out if `skip_empty`.
+#[sheeet::function(variadic)]
+pub fn text_join(separator: &str, skip_empty: bool, texts: Vec<String>) -> String {
+    texts
+        .into_iter()
+        .filter(|text| !skip_empty || !text.is_empty())
+        .collect::<Vec<_>>()
+        .join(separator)
+}
+
+/// Formats the number with the `decimals` (2 by default) and the thousands `separator`
+/// (none by default), e.g. `format_number(1234.5, 2, ",")` is `1,234.50`.
+#[sheeet::function]
+pub fn format_number(number: f64, decimals: Option<usize>, separator: Option<String>) -> String {
+    let decimals = decimals.unwrap_or(2);
+    // Rounded half away from zero, `format!` rounds half to even.
+    let factor = 10f64.powi(decimals.min(300) as i32);
+    let rounded = match (number.abs() * factor).round() / factor {
+        rounded if rounded.is_finite() => rounded,
+        _ => number.abs(),
+    };
+    let formatted = format!("{rounded:.decimals$}");
+    let (integer, fraction) = match formatted.split_once('.') {
+        Some((integer, fraction)) => (integer, Some(fraction)),
+        None => (formatted.as_str(), None),
+    };
+    let separator = separator.unwrap_or_default();
+    let mut grouped = String::new();
+    for (i, digit) in integer.chars().enumerate() {
+        if i > 0 && (integer.len() - i) % 3 == 0 {
+            grouped.push_str(&separator);
+        }
+        grouped.push(digit);
+    }
+    // The rounded negative zero is shown as the zero.
+    let sign = match number.is_sign_negative() && formatted.chars().any(|c| matches!(c, '1'..='9'))
+    {
+        true => "-",
+        false => "",
+    };
+    match fraction {
+        Some(fraction) => format!("{sign}{grouped}.{fraction}"),
+        None => format!("{sign}{grouped}"),
+    }
+}
+
+#[cfg(feature = "regex")]
+fn regex(pattern: &str) -> Result<regex::Regex, String> {
+    regex::Regex::new(pattern).map_err(|err| format!("invalid regex: {err}"))
+}
+
+/// Whether the regex matches (a part of) the text.
+#[cfg(feature = "regex")]
+#[sheeet::function]
+pub fn regex_match(text: &str, pattern: &str) -> Result<bool, String> {
+    Ok(regex(pattern)?.is_match(text))
+}
+
+/// The first match of the regex, or of its capture `group` (by index, 0 is the whole match).
+#[cfg(feature = "regex")]
+#[sheeet::function]
+pub fn regex_extract(text: &str, pattern: &str, group: Option<usize>) -> Result<String, String> {
+    let captures = regex(pattern)?
+        .captures(text)
+        .ok_or_else(|| format!("no match of /{pattern}/"))?;
+    let group = group.unwrap_or(0);
+    captures
+        .get(group)
+        .map(|found| found.as_str().into())
+        .ok_or_else(|| format!("no group {group} in the match of /{pattern}/"))
+}
+
+/// Replaces all the matches of the regex, the `replacement` can refer to the capture groups
+/// as `$1` or `${name}`.
+#[cfg(feature = "regex")]
+#[sheeet::function]
+pub fn regex_replace(text: &str, pattern: &str, replacement: &str) -> Result<String, String> {
+    Ok(regex(pattern)?.replace_all(text, replacement).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_length_and_case() {
+        assert_eq!(len(""), 0);
+        assert_eq!(len("héllo"), 5);
+        // Combining accent, skin tone modifier and flag.
+        assert_eq!(len("e\u{301}👍🏽🇨🇿"), 3);
+        assert_eq!(upper("straße"), "STRASSE");
+        assert_eq!(lower("ÁBC Σ"), "ábc σ");
+        assert_eq!(proper("élan VITAL, o'neil 2nd"), "Élan Vital, O'neil 2nd");
+        assert_eq!(trim("  a \t b\u{3000}\n c  "), "a b c");
+    }
+
+    #[test]
+    fn test_substrings() {
+        assert_eq!(left("e\u{301}tude", None), "e\u{301}");
+        assert_eq!(left("žluťoučký", Some(4)), "žluť");
+        assert_eq!(left("ab", Some(5)), "ab");
+        assert_eq!(right("kůň🐎", Some(2)), "ň🐎");
+        assert_eq!(right("ab", Some(5)), "ab");
+        assert_eq!(mid("příliš", 2, 3), Ok("říl".into()));
+        assert_eq!(mid("abc", 3, 5), Ok("c".into()));
+        assert_eq!(
+            mid("abc", 0, 1),
+            Err("start must be at least 1, got 0".into())
+        );
+        assert_eq!(find("ň", "kůň kůň", None), Ok(3));
+        assert_eq!(find("ň", "kůň kůň", Some(4)), Ok(7));
+        assert_eq!(find("x", "abc", None), Err(r#""x" not found"#.into()));
+        assert_eq!(replace("a-b-c", "-", "–"), Ok("a–b–c".into()));
+        assert_eq!(
+            replace("abc", "", "x"),
+            Err("search text can't be empty".into())
+        );
+    }
+
+    #[test]
+    fn test_split_and_join() {
+        assert_eq!(split("a,b,,c", ","), ["a", "b", "", "c"]);
+        assert_eq!(split("že🇨🇿", ""), ["ž", "e", "🇨🇿"]);
+        let texts = vec!["a".into(), "".into(), "b".into()];
+        assert_eq!(text_join(", ", true, texts.clone()), "a, b");
+        assert_eq!(text_join(", ", false, texts), "a, , b");
+    }
+
+    #[test]
+    fn test_format_number() {
+        assert_eq!(
+            format_number(1234567.891, None, Some(",".into())),
+            "1,234,567.89"
+        );
+        assert_eq!(format_number(-1234.5, Some(0), Some(" ".into())), "-1 235");
+        assert_eq!(format_number(999.999, Some(1), None), "1000.0");
+        assert_eq!(format_number(-0.001, None, None), "0.00");
+        assert_eq!(format_number(12.0, Some(3), Some(",".into())), "12.000");
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn test_regex() {
+        assert_eq!(regex_match("order 42", r"\d+"), Ok(true));
+        assert_eq!(regex_match("čaj", r"^\w+$"), Ok(true));
+        assert_eq!(
+            regex_extract("id: ab-12", r"(\w+)-(\d+)", Some(2)),
+            Ok("12".into())
+        );
+        assert_eq!(
+            regex_extract("abc", r"\d", None),
+            Err(r"no match of /\d/".into())
+        );
+        assert_eq!(
+            regex_replace("2024-05-01", r"(\d+)-(\d+)-(\d+)", "$3.$2.$1"),
+            Ok("01.05.2024".into())
+        );
+        assert!(
+            regex_match("a", "(")
+                .unwrap_err()
+                .starts_with("invalid regex")
+        );
+    }
+}
@@ -0,0 +1,531 @@
+//! Coercion of the sheet values to the Rust types and back, used by the wrappers
+//! the [`function`](crate::function) attribute generates.
+
+use crate::date;
+use crate::number::Number;
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+use js_sys::{Array, ArrayBuffer};
+use std::cell::RefCell;
+use std::fmt::{Display, Formatter};
+use std::panic;
+use std::sync::Once;
+use std::sync::atomic::{AtomicBool, Ordering};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::{JsCast, JsValue};
+
+/// Error value of the cell, e.g. of the `Err` returned by the sheet function. The sheet gets
+/// it as the JS `Error`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SheetError(pub String);
+
+impl Display for SheetError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Rust type the sheet function takes, coerced from the argument's value.
+pub trait FromSheetValue: Sized {
+    fn from_sheet_value(value: JsValue) -> Result<Self, String>;
+
+    /// Coerces the value of the range's cell, `None` skips it. The numbers skip the texts,
+    /// as in the spreadsheets, the other types take the cells as the single values.
+    fn from_range_value(value: JsValue) -> Result<Option<Self>, String> {
+        Self::from_sheet_value(value).map(Some)
+    }
+}
+
+/// Rust type the sheet function returns, converted to the cell's value.
+pub trait IntoSheetValue {
+    fn into_sheet_value(self) -> JsValue;
+}
+
+fn describe(value: &JsValue) -> String {
+    match value.as_string() {
+        Some(text) => format!(r#""{text}""#),
+        None => format!("{value:?}"),
+    }
+}
+
+/// Parses the numeric text, the empty text is zero as the empty cell. Only the digits with
+/// the sign, the decimal point and the exponent are numbers, as in the sheet's comparisons,
+/// the texts as `inf` or `NaN` aren't.
+pub fn parse_number(text: &str) -> Result<f64, String> {
+    let trimmed = text.trim();
+    if trimmed.is_empty() {
+        return Ok(0.0);
+    }
+    trimmed
+        .bytes()
+        .all(|byte| byte.is_ascii_digit() || matches!(byte, b'+' | b'-' | b'.' | b'e' | b'E'))
+        .then(|| trimmed.parse().ok())
+        .flatten()
+        .ok_or_else(|| format!(r#"expected a number, got "{text}""#))
+}
+
+/// Parses the decimal text exactly, also in the scientific notation, the empty text is zero.
+#[cfg(feature = "decimal")]
+pub fn parse_decimal(text: &str) -> Result<rust_decimal::Decimal, String> {
+    let trimmed = text.trim();
+    if trimmed.is_empty() {
+        return Ok(rust_decimal::Decimal::ZERO);
+    }
+    rust_decimal::Decimal::from_str_exact(trimmed)
+        .or_else(|_| rust_decimal::Decimal::from_scientific(trimmed))
+        .map_err(|_| format!(r#"expected a decimal number, got "{text}""#))
+}
+
+/// Whether the value is a text that isn't a number, e.g. the column's header in the range.
+fn non_numeric_text(value: &JsValue) -> bool {
+    value
+        .as_string()
+        .is_some_and(|text| parse_number(&text).is_err())
+}
+
+/// Skips the non-numeric text of the range's cell, coerces the others.
+fn numeric_range_value<T: FromSheetValue>(value: JsValue) -> Result<Option<T>, String> {
+    match non_numeric_text(&value) {
+        true => Ok(None),
+        false => T::from_sheet_value(value).map(Some),
+    }
+}
+
+fn number(value: &JsValue) -> Result<f64, String> {
+    if let Some(number) = value.as_f64() {
+        return Ok(number);
+    }
+    if value.is_null() || value.is_undefined() {
+        return Ok(0.0);
+    }
+    if let Some(boolean) = value.as_bool() {
+        return Ok(if boolean { 1.0 } else { 0.0 });
+    }
+    // The date is its serial number.
+    if let Some(js_date) = value.dyn_ref::<js_sys::Date>() {
+        return date::from_timestamp(js_date.get_time()).map(date::to_serial);
+    }
+    match value.as_string() {
+        Some(text) => parse_number(&text),
+        None => Err(format!("expected a number, got {}", describe(value))),
+    }
+}
+
+macro_rules! float_sheet_value {
+    ($($ty:ty),*) => {$(
+        impl FromSheetValue for $ty {
+            fn from_sheet_value(value: JsValue) -> Result<Self, String> {
+                number(&value).map(|number| number as $ty)
+            }
+
+            fn from_range_value(value: JsValue) -> Result<Option<Self>, String> {
+                numeric_range_value(value)
+            }
+        }
+
+        impl IntoSheetValue for $ty {
+            fn into_sheet_value(self) -> JsValue {
+                JsValue::from_f64(self as f64)
+            }
+        }
+    )*};
+}
+
+macro_rules! integer_sheet_value {
+    ($($ty:ty),*) => {$(
+        impl FromSheetValue for $ty {
+            fn from_sheet_value(value: JsValue) -> Result<Self, String> {
+                let number = number(&value)?;
+                if number.fract() != 0.0 || number < <$ty>::MIN as f64 || number > <$ty>::MAX as f64 {
+                    return Err(format!(
+                        "expected a whole number between {} and {}, got {number}",
+                        <$ty>::MIN,
+                        <$ty>::MAX
+                    ));
+                }
+                Ok(number as $ty)
+            }
+
+            fn from_range_value(value: JsValue) -> Result<Option<Self>, String> {
+                numeric_range_value(value)
+            }
+        }
+
+        impl IntoSheetValue for $ty {
+            fn into_sheet_value(self) -> JsValue {
+                JsValue::from_f64(self as f64)
+            }
+        }
+    )*};
+}
+
+float_sheet_value!(f32, f64);
+integer_sheet_value!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+/// Decimals are read from the texts exactly. They're returned as the JS numbers, unless
+/// the number can't hold all their digits, then as the numeric texts.
+#[cfg(feature = "decimal")]
+impl FromSheetValue for rust_decimal::Decimal {
+    fn from_sheet_value(value: JsValue) -> Result<Self, String> {
+        // The JS number's shortest representation, e.g. `0.1` and not the closest binary fraction.
+        if let Some(number) = value.as_f64() {
+            return parse_decimal(&number.to_string());
+        }
+        match value.as_string() {
+            Some(text) => parse_decimal(&text),
+            None => number(&value).and_then(|number| parse_decimal(&number.to_string())),
+        }
+    }
+
+    fn from_range_value(value: JsValue) -> Result<Option<Self>, String> {
+        numeric_range_value(value)
+    }
+}
+
+#[cfg(feature = "decimal")]
+impl IntoSheetValue for rust_decimal::Decimal {
+    fn into_sheet_value(self) -> JsValue {
+        Number(self).into_sheet_value()
+    }
+}
+
+/// Read as the [`f64`] or, with the `decimal` feature, as the decimal.
+impl FromSheetValue for Number {
+    #[cfg(not(feature = "decimal"))]
+    fn from_sheet_value(value: JsValue) -> Result<Self, String> {
+        let number = number(&value)?;
+        Number::from_f64(number).ok_or_else(|| format!("expected a finite number, got {number}"))
+    }
+
+    #[cfg(feature = "decimal")]
+    fn from_sheet_value(value: JsValue) -> Result<Self, String> {
+        rust_decimal::Decimal::from_sheet_value(value).map(Number)
+    }
+
+    fn from_range_value(value: JsValue) -> Result<Option<Self>, String> {
+        numeric_range_value(value)
+    }
+}
+
+/// Returned as the JS number, the decimal as the numeric text if the number can't hold all
+/// its digits, the engine compares those exactly.
+impl IntoSheetValue for Number {
+    fn into_sheet_value(self) -> JsValue {
+        match self.is_exact_f64() {
+            true => JsValue::from_f64(self.to_f64()),
+            false => JsValue::from_str(&self.to_string()),
+        }
+    }
+}
+
+/// Dates are read from the `Date`s (e.g. of the date literals), the serial numbers and
+/// the texts in the ISO or the common formats, see [`parse_date_time`](date::parse_date_time).
+/// They're returned as the `Date`s.
+impl FromSheetValue for NaiveDateTime {
+    fn from_sheet_value(value: JsValue) -> Result<Self, String> {
+        if let Some(js_date) = value.dyn_ref::<js_sys::Date>() {
+            return date::from_timestamp(js_date.get_time());
+        }
+        if let Some(number) = value.as_f64() {
+            return date::from_serial(number);
+        }
+        match value.as_string() {
+            Some(text) => date::parse_date_time(&text),
+            None => Err(format!("expected a date, got {}", describe(&value))),
+        }
+    }
+}
+
+impl IntoSheetValue for NaiveDateTime {
+    fn into_sheet_value(self) -> JsValue {
+        js_sys::Date::new(&JsValue::from_f64(date::to_timestamp(self))).into()
+    }
+}
+
+/// The time of day is left out.
+impl FromSheetValue for NaiveDate {
+    fn from_sheet_value(value: JsValue) -> Result<Self, String> {
+        NaiveDateTime::from_sheet_value(value).map(|date_time| date_time.date())
+    }
+}
+
+impl IntoSheetValue for NaiveDate {
+    fn into_sheet_value(self) -> JsValue {
+        self.and_time(NaiveTime::MIN).into_sheet_value()
+    }
+}
+
+impl FromSheetValue for String {
+    fn from_sheet_value(value: JsValue) -> Result<Self, String> {
+        if let Some(text) = value.as_string() {
+            return Ok(text);
+        }
+        if let Some(number) = value.as_f64() {
+            return Ok(number.to_string());
+        }
+        if let Some(boolean) = value.as_bool() {
+            return Ok(boolean.to_string());
+        }
+        if value.is_null() || value.is_undefined() {
+            return Ok(String::new());
+        }
+        if let Some(js_date) = value.dyn_ref::<js_sys::Date>() {
+            return date::from_timestamp(js_date.get_time()).map(date::format_date_time);
+        }
+        Err(format!("expected a text, got {}", describe(&value)))
+    }
+}
+
+impl IntoSheetValue for String {
+    fn into_sheet_value(self) -> JsValue {
+        JsValue::from_str(&self)
+    }
+}
+
+impl IntoSheetValue for &str {
+    fn into_sheet_value(self) -> JsValue {
+        JsValue::from_str(self)
+    }
+}
+
+impl FromSheetValue for char {
+    fn from_sheet_value(value: JsValue) -> Result<Self, String> {
+        let text = String::from_sheet_value(value)?;
+        let mut chars = text.chars();
+        match (chars.next(), chars.next()) {
+            (Some(char), None) => Ok(char),
+            _ => Err(format!(r#"expected a single character, got "{text}""#)),
+        }
+    }
+}
+
+impl IntoSheetValue for char {
+    fn into_sheet_value(self) -> JsValue {
+        JsValue::from_str(self.encode_utf8(&mut [0; 4]))
+    }
+}
+
+impl FromSheetValue for bool {
+    fn from_sheet_value(value: JsValue) -> Result<Self, String> {
+        if let Some(boolean) = value.as_bool() {
+            return Ok(boolean);
+        }
+        if let Some(number) = value.as_f64() {
+            return Ok(number != 0.0);
+        }
+        if value.is_null() || value.is_undefined() {
+            return Ok(false);
+        }
+        match value.as_string() {
+            Some(text) if text.eq_ignore_ascii_case("true") => Ok(true),
+            Some(text) if text.eq_ignore_ascii_case("false") => Ok(false),
+            // The numeric texts as in the sheet's conditions, e.g. the decimals.
+            Some(text) => match parse_number(&text) {
+                Ok(number) if number.is_finite() => Ok(number != 0.0),
+                _ => Err(format!("expected true or false, got {}", describe(&value))),
+            },
+            None => Err(format!("expected true or false, got {}", describe(&value))),
+        }
+    }
+}
+
+impl IntoSheetValue for bool {
+    fn into_sheet_value(self) -> JsValue {
+        JsValue::from_bool(self)
+    }
+}
+
+impl FromSheetValue for JsValue {
+    fn from_sheet_value(value: JsValue) -> Result<Self, String> {
+        Ok(value)
+    }
+}
+
+impl IntoSheetValue for JsValue {
+    fn into_sheet_value(self) -> JsValue {
+        self
+    }
+}
+
+impl IntoSheetValue for () {
+    fn into_sheet_value(self) -> JsValue {
+        JsValue::undefined()
+    }
+}
+
+/// Left out argument or the empty cell is `None`.
+impl<T: FromSheetValue> FromSheetValue for Option<T> {
+    fn from_sheet_value(value: JsValue) -> Result<Self, String> {
+        if value.is_null() || value.is_undefined() {
+            return Ok(None);
+        }
+        T::from_sheet_value(value).map(Some)
+    }
+}
+
+impl<T: IntoSheetValue> IntoSheetValue for Option<T> {
+    fn into_sheet_value(self) -> JsValue {
+        match self {
+            Some(value) => value.into_sheet_value(),
+            None => JsValue::null(),
+        }
+    }
+}
+
+fn is_array(value: &JsValue) -> bool {
+    Array::is_array(value) || ArrayBuffer::is_view(value)
+}
+
+fn flatten(value: JsValue, values: &mut Vec<JsValue>) {
+    if is_array(&value) {
+        for value in Array::from(&value) {
+            flatten(value, values);
+        }
+    } else if !value.is_null() && !value.is_undefined() {
+        values.push(value);
+    }
+}
+
+/// Ranges (and the nested arrays) are flattened, their cells coerced by
+/// [`from_range_value`](FromSheetValue::from_range_value), a single value is a one element
+/// `Vec`.
+impl<T: FromSheetValue> FromSheetValue for Vec<T> {
+    fn from_sheet_value(value: JsValue) -> Result<Self, String> {
+        let range = is_array(&value);
+        let mut values = Vec::new();
+        flatten(value, &mut values);
+        match range {
+            true => values
+                .into_iter()
+                .filter_map(|value| T::from_range_value(value).transpose())
+                .collect(),
+            false => values.into_iter().map(T::from_sheet_value).collect(),
+        }
+    }
+}
+
+impl<T: IntoSheetValue> IntoSheetValue for Vec<T> {
+    fn into_sheet_value(self) -> JsValue {
+        self.into_iter()
+            .map(IntoSheetValue::into_sheet_value)
+            .collect::<Array>()
+            .into()
+    }
+}
+
+impl IntoSheetValue for SheetError {
+    fn into_sheet_value(self) -> JsValue {
+        js_sys::Error::new(&self.0).into()
+    }
+}
+
+impl<T: IntoSheetValue, E: Display> IntoSheetValue for Result<T, E> {
+    fn into_sheet_value(self) -> JsValue {
+        match self {
+            Ok(value) => value.into_sheet_value(),
+            Err(err) => SheetError(err.to_string()).into_sheet_value(),
+        }
+    }
+}
+
+/// Coerces the argument of the sheet function.
+pub fn argument<T: FromSheetValue>(value: JsValue, name: &str) -> Result<T, SheetError> {
+    T::from_sheet_value(value).map_err(|err| SheetError(format!("argument '{name}': {err}")))
+}
+
+/// Coerces the arguments collected by the variadic sheet function, each one as the `Vec`, so
+/// the values given directly aren't skipped as the cells of the ranges.
+pub fn variadic_argument<T: FromSheetValue>(
+    value: JsValue,
+    name: &str,
+) -> Result<Vec<T>, SheetError> {
+    if !Array::is_array(&value) {
+        return argument(value, name);
+    }
+    let mut values = Vec::new();
+    for value in Array::from(&value) {
+        values.extend(argument::<Vec<T>>(value, name)?);
+    }
+    Ok(values)
+}
+
+thread_local! {
+    static LAST_PANIC: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Set by the first panic, the aborted call could leave the instance's memory inconsistent.
+static POISONED: AtomicBool = AtomicBool::new(false);
+
+/// Calls the sheet function, its coercion errors are returned as the error values. Refuses
+/// the calls once a function panicked, the module has to be instantiated again.
+pub fn call(function: impl FnOnce() -> Result<JsValue, SheetError>) -> JsValue {
+    if POISONED.load(Ordering::Relaxed) {
+        return SheetError("a function panicked, the module has to be reloaded".into())
+            .into_sheet_value();
+    }
+    capture_panics();
+    function().unwrap_or_else(IntoSheetValue::into_sheet_value)
+}
+
+/// Panics abort the WASM call (it traps), the message is kept for the GUI to show it as
+/// the error value, the instance is marked poisoned.
+fn capture_panics() {
+    static HOOK: Once = Once::new();
+    HOOK.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            let payload = info.payload();
+            let message = payload
+                .downcast_ref::<&str>()
+                .map(|message| message.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| info.to_string());
+            POISONED.store(true, Ordering::Relaxed);
+            LAST_PANIC.set(Some(format!("panicked: {message}")));
+            previous(info);
+        }));
+    });
+}
+
+/// Message of the sheet function's panic, taken by the GUI once the aborted call throws,
+/// before it instantiates the module again.
+#[wasm_bindgen]
+pub fn __sheeet_take_panic() -> Option<String> {
+    LAST_PANIC.take()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_number() {
+        assert_eq!(parse_number(" 42 "), Ok(42.0));
+        assert_eq!(parse_number("-1.5e2"), Ok(-150.0));
+        assert_eq!(parse_number(""), Ok(0.0));
+        assert_eq!(
+            parse_number("abc"),
+            Err(r#"expected a number, got "abc""#.into())
+        );
+        assert_eq!(parse_number("+.5E1"), Ok(5.0));
+        for text in ["inf", "-Infinity", "NaN", "1e", "1.2.3"] {
+            assert_eq!(
+                parse_number(text),
+                Err(format!(r#"expected a number, got "{text}""#))
+            );
+        }
+    }
+
+    #[cfg(feature = "decimal")]
+    #[test]
+    fn test_parse_decimal() {
+        use rust_decimal::Decimal;
+
+        assert_eq!(parse_decimal(" 0.1 "), Ok(Decimal::new(1, 1)));
+        assert_eq!(parse_decimal("-1.5e2"), Ok(Decimal::new(-150, 0)));
+        assert_eq!(parse_decimal(""), Ok(Decimal::ZERO));
+        assert_eq!(
+            parse_decimal("abc"),
+            Err(r#"expected a decimal number, got "abc""#.into())
+        );
+    }
+}
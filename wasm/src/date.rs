@@ -0,0 +1,131 @@
+//! Date literals of the sheet, e.g. `2025-03-01` or `2025-03-01 14:30`. They resolve to the JS
+//! `Date`s, so they compare and sort by the time and the functions get them as the dates.
+//! The sheet has no time zone, the wall-clock time is kept in the `Date`'s UTC fields.
+
+const MILLIS_PER_MINUTE: i64 = 60 * 1000;
+const MILLIS_PER_DAY: i64 = 24 * 60 * MILLIS_PER_MINUTE;
+
+/// Days since 1970-01-01 of the proleptic Gregorian date.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Year, month and day of the days since 1970-01-01.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Parses the fixed width number, e.g. the `MM` of the date.
+fn digits(text: &str, width: usize) -> Option<i64> {
+    match text.len() == width && text.bytes().all(|byte| byte.is_ascii_digit()) {
+        true => text.parse().ok(),
+        false => None,
+    }
+}
+
+/// Milliseconds since the Unix epoch of the ISO date (`YYYY-MM-DD`), optionally with the time
+/// (`HH:MM` or `HH:MM:SS`) after a space or `T`. `None` if the text isn't the date.
+pub fn parse(text: &str) -> Option<f64> {
+    let (date, time) = match text.split_once(['T', ' ']) {
+        Some((date, time)) => (date, Some(time)),
+        None => (text, None),
+    };
+    let mut parts = date.splitn(3, '-');
+    let year = digits(parts.next()?, 4)?;
+    let month = digits(parts.next()?, 2)?;
+    let day = digits(parts.next()?, 2)?;
+    if !(1..=12).contains(&month) || !(1..=days_in_month(year, month)).contains(&day) {
+        return None;
+    }
+    let mut millis = days_from_civil(year, month, day) * MILLIS_PER_DAY;
+    if let Some(time) = time {
+        let mut parts = time.splitn(3, ':');
+        let hours = digits(parts.next()?, 2)?;
+        let minutes = digits(parts.next()?, 2)?;
+        let seconds = match parts.next() {
+            Some(seconds) => digits(seconds, 2)?,
+            None => 0,
+        };
+        if hours > 23 || minutes > 59 || seconds > 59 {
+            return None;
+        }
+        millis += ((hours * 60 + minutes) * 60 + seconds) * 1000;
+    }
+    Some(millis as f64)
+}
+
+/// Formats the `Date`'s time as the ISO date, with the time of day if it isn't midnight.
+/// The milliseconds are left out.
+pub fn format(timestamp: f64) -> String {
+    let millis = timestamp.floor() as i64;
+    let (year, month, day) = civil_from_days(millis.div_euclid(MILLIS_PER_DAY));
+    let seconds = millis.rem_euclid(MILLIS_PER_DAY) / 1000;
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    match (hours, minutes, seconds) {
+        (0, 0, 0) => format!("{year:04}-{month:02}-{day:02}"),
+        (hours, minutes, 0) => format!("{year:04}-{month:02}-{day:02} {hours:02}:{minutes:02}"),
+        (hours, minutes, seconds) => {
+            format!("{year:04}-{month:02}-{day:02} {hours:02}:{minutes:02}:{seconds:02}")
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_format() {
+        assert_eq!(parse("1970-01-01"), Some(0.0));
+        assert_eq!(parse("2025-03-01"), Some(1_740_787_200_000.0));
+        assert_eq!(parse("2025-03-01T14:30"), Some(1_740_839_400_000.0));
+        assert_eq!(parse("2025-03-01 14:30:15"), Some(1_740_839_415_000.0));
+        assert_eq!(parse("1900-02-28"), Some(-2_203_977_600_000.0));
+        assert_eq!(parse("2024-02-29").map(format), Some("2024-02-29".into()));
+        for invalid in [
+            "2025-3-01",
+            "2025-02-29",
+            "2025-13-01",
+            "2025-03-01 24:00",
+            "2025-03-01T",
+            "2025-03-01 1:30",
+            "+2025-03-01",
+            "01.03.2025",
+            "20250301",
+        ] {
+            assert_eq!(parse(invalid), None, "{invalid}");
+        }
+        assert_eq!(format(1_740_787_200_000.0), "2025-03-01");
+        assert_eq!(format(1_740_839_400_000.0), "2025-03-01 14:30");
+        assert_eq!(format(1_740_839_415_999.0), "2025-03-01 14:30:15");
+        assert_eq!(format(-2_203_977_600_000.0), "1900-02-28");
+    }
+}
@@ -1,3 +1,4 @@
+use crate::date;
 use crate::reference::{COLON, Reference, usize_to_column_name};
 use std::fmt::{Display, Formatter, Write};
 
@@ -18,6 +19,47 @@ pub enum Expression {
     },
     Reference(Reference),
     Value(String),
+    /// ISO date literal, e.g. `2025-03-01`, as the milliseconds since the Unix epoch.
+    Date(f64),
+    /// =A1 >= 10
+    Comparison {
+        operator: Operator,
+        left: Box<Expression>,
+        right: Box<Expression>,
+    },
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Operator {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+impl Operator {
+    /// The two-char operators first, `<=` isn't `<`.
+    const ALL: [Operator; 6] = [
+        Operator::NotEqual,
+        Operator::LessOrEqual,
+        Operator::GreaterOrEqual,
+        Operator::Equal,
+        Operator::Less,
+        Operator::Greater,
+    ];
+
+    fn symbol(&self) -> &'static str {
+        match self {
+            Operator::Equal => "=",
+            Operator::NotEqual => "<>",
+            Operator::Less => "<",
+            Operator::LessOrEqual => "<=",
+            Operator::Greater => ">",
+            Operator::GreaterOrEqual => ">=",
+        }
+    }
 }
 
 const EQUAL_SIGN: char = '=';
@@ -29,7 +71,6 @@ const DOUBLE_QUOTE: char = '"';
 #[derive(Default)]
 struct Taken {
     content: String,
-    is_literal: bool,
 }
 
 impl Taken {
@@ -43,12 +84,41 @@ impl Taken {
 
 impl Display for Taken {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        if self.is_literal {
-            f.write_str("lit'")?;
+        f.write_str(&self.content)
+    }
+}
+
+/// Text of the quoted literal, e.g. `"a, b"`.
+fn unquote(input: &str) -> Option<&str> {
+    let text = input
+        .strip_prefix(DOUBLE_QUOTE)?
+        .strip_suffix(DOUBLE_QUOTE)?;
+    match text.contains(DOUBLE_QUOTE) {
+        true => None,
+        false => Some(text),
+    }
+}
+
+/// Splits the input at its first comparison operator outside the quotes and the brackets.
+fn split_comparison(input: &str) -> Option<(&str, Operator, &str)> {
+    let mut inside_quoted = false;
+    let mut opening_bracket_count: usize = 0;
+    for (i, c) in input.char_indices() {
+        match c {
+            DOUBLE_QUOTE => inside_quoted = !inside_quoted,
+            _ if inside_quoted => {}
+            OPENING_BRACKET => opening_bracket_count += 1,
+            CLOSING_BRACKET => opening_bracket_count = opening_bracket_count.saturating_sub(1),
+            '<' | '>' | EQUAL_SIGN if opening_bracket_count == 0 => {
+                let operator = Operator::ALL
+                    .into_iter()
+                    .find(|operator| input[i..].starts_with(operator.symbol()))?;
+                return Some((&input[..i], operator, &input[i + operator.symbol().len()..]));
+            }
+            _ => {}
         }
-        f.write_str(&self.content)?;
-        Ok(())
     }
+    None
 }
 
 impl Expression {
@@ -62,12 +132,23 @@ impl Expression {
             Some(input) => input,
             None => {
                 if root {
-                    return Ok(Expression::Value(input.to_string()));
+                    return Ok(Self::literal(input));
                 }
                 input
             }
         };
 
+        if let Some((left, operator, right)) = split_comparison(input) {
+            if left.trim().is_empty() || right.trim().is_empty() {
+                return Err("missing operand of the comparison");
+            }
+            return Ok(Expression::Comparison {
+                operator,
+                left: Box::new(Self::parse_inner(left, false)?),
+                right: Box::new(Self::parse_inner(right, false)?),
+            });
+        }
+
         let mut taken = Taken::default();
         let mut inside_quoted = false;
         let mut function_expr: Option<Expression> = None;
@@ -77,14 +158,16 @@ impl Expression {
                 r#"-char: '{c}' | bracket_count: {opening_bracket_count} | taken: "{taken}" | inside_quoted: {inside_quoted}"#
             );
 
+            // The quotes are kept, also for the nested functions, the literal is unquoted once
+            // the argument is parsed.
             if c == DOUBLE_QUOTE {
                 if !inside_quoted {
-                    taken.is_literal = true;
                     test_log!("start quoted");
                 } else {
                     test_log!(r#"ending quoted: "{taken}""#);
                 }
                 inside_quoted = !inside_quoted;
+                taken.push(c);
                 continue;
             }
 
@@ -112,10 +195,7 @@ impl Expression {
                 if taken.empty() {
                     return Err("unexpected comma, no arguments between");
                 }
-                let expr = match &taken.is_literal {
-                    true => Expression::Value(taken.content),
-                    false => Self::parse_inner(&taken.content, false)?,
-                };
+                let expr = Self::parse_inner(&taken.content, false)?;
                 if let Some(Expression::Function { inputs, .. }) = &mut function_expr {
                     inputs.push(expr);
                 }
@@ -145,12 +225,11 @@ impl Expression {
                     taken.push(c);
                     continue;
                 }
-                let expr = match &taken.is_literal {
-                    true => Expression::Value(taken.content),
-                    false => Self::parse_inner(&taken.content, false)?,
-                };
                 if let Some(Expression::Function { inputs, .. }) = &mut function_expr {
-                    inputs.push(expr);
+                    // Function called without arguments, e.g. `=now()`.
+                    if !inputs.is_empty() || !taken.empty() {
+                        inputs.push(Self::parse_inner(&taken.content, false)?);
+                    }
                 }
                 return function_expr.ok_or("expected function expression to be present");
             }
@@ -159,17 +238,31 @@ impl Expression {
             taken.push(c);
         }
 
+        if inside_quoted {
+            return Err("unclosed quotes");
+        }
         if opening_bracket_count > 0 {
             return Err("unclosed function");
         }
 
         test_log!(r#"return value: "{taken}""#);
+        if let Some(text) = unquote(&taken.content) {
+            return Ok(Expression::Value(text.to_string()));
+        }
         Ok(match Reference::parse(&taken.content) {
             Ok(reference) => Expression::Reference(reference),
-            Err(_) => Expression::Value(taken.content),
+            Err(_) => Self::literal(&taken.content),
         })
     }
 
+    /// The date if the text is the ISO date, the quoted literals stay texts.
+    fn literal(text: &str) -> Expression {
+        match date::parse(text) {
+            Some(timestamp) => Expression::Date(timestamp),
+            None => Expression::Value(text.to_string()),
+        }
+    }
+
     pub fn copy_with_distance(&self, distance: (isize, isize)) -> Self {
         match self {
             Expression::Function { inputs, name } => {
@@ -210,10 +303,45 @@ impl Expression {
                     Expression::Value(value.clone())
                 }
             }
+            Expression::Date(timestamp) => Expression::Date(*timestamp),
+            Expression::Comparison {
+                operator,
+                left,
+                right,
+            } => Expression::Comparison {
+                operator: *operator,
+                left: Box::new(left.copy_with_distance(distance)),
+                right: Box::new(right.copy_with_distance(distance)),
+            },
+        }
+    }
+}
+
+impl Expression {
+    /// Writes the argument or the operand, quoting the texts which wouldn't be parsed back
+    /// as the same text, e.g. `", "` or `"A1"`.
+    fn fmt_nested(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Expression::Value(value)
+                if !value.contains(DOUBLE_QUOTE)
+                    && Self::parse_inner(value, false).as_ref() != Ok(self) =>
+            {
+                write!(f, "{DOUBLE_QUOTE}{value}{DOUBLE_QUOTE}")
+            }
+            _ => self.fmt(f),
         }
     }
 }
 
+/// Displays the expression as the argument or the operand.
+struct Nested<'a>(&'a Expression);
+
+impl Display for Nested<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt_nested(f)
+    }
+}
+
 impl Display for Expression {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -222,7 +350,7 @@ impl Display for Expression {
                 f.write_str(name)?;
                 f.write_char(OPENING_BRACKET)?;
                 for (i, input) in inputs.iter().enumerate() {
-                    input.fmt(f)?;
+                    input.fmt_nested(f)?;
                     if i < inputs.len() - 1 {
                         f.write_char(COMMA)?;
                     }
@@ -253,6 +381,24 @@ impl Display for Expression {
             Expression::Value(value) => {
                 f.write_str(&value)?;
             }
+            Expression::Date(timestamp) => {
+                f.write_str(&date::format(*timestamp))?;
+            }
+            Expression::Comparison {
+                operator,
+                left,
+                right,
+            } => {
+                // The operands are written without their own equal signs.
+                f.write_char(EQUAL_SIGN)?;
+                for (i, operand) in [left, right].into_iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(operator.symbol())?;
+                    }
+                    let operand = Nested(operand).to_string();
+                    f.write_str(operand.strip_prefix(EQUAL_SIGN).unwrap_or(&operand))?;
+                }
+            }
         }
         Ok(())
     }
@@ -370,6 +516,120 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_parse_expression_without_arguments() {
+        assert_eq!(
+            Expression::parse("=now()"),
+            Ok(Function {
+                name: String::from("now"),
+                inputs: vec![],
+            })
+        );
+        assert_eq!(
+            Expression::parse(r#"=trim("")"#),
+            Ok(Function {
+                name: String::from("trim"),
+                inputs: vec![Value(String::new())],
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_date() {
+        assert_eq!(
+            Expression::parse("2025-03-01"),
+            Ok(Expression::Date(1_740_787_200_000.0))
+        );
+        assert_eq!(
+            Expression::parse(r#"=date_add(2025-03-01, 1, "2025-03-01")"#),
+            Ok(Function {
+                name: String::from("date_add"),
+                inputs: vec![
+                    Expression::Date(1_740_787_200_000.0),
+                    Value(String::from("1")),
+                    Value(String::from("2025-03-01")),
+                ],
+            })
+        );
+        assert_eq!(
+            Expression::parse("2025-03-01T14:30").map(|expr| expr.to_string()),
+            Ok(String::from("2025-03-01 14:30"))
+        );
+        assert_eq!(
+            Expression::parse("2025-02-30"),
+            Ok(Value(String::from("2025-02-30")))
+        );
+    }
+
+    #[test]
+    fn test_parse_comparison() {
+        let reference = |col, row| {
+            Box::new(Expression::Reference(Reference::Single(CellPointer(
+                col, row,
+            ))))
+        };
+        assert_eq!(
+            Expression::parse("=A1 >= 10"),
+            Ok(Expression::Comparison {
+                operator: Operator::GreaterOrEqual,
+                left: reference(1, 1),
+                right: Box::new(Value(String::from("10"))),
+            })
+        );
+        assert_eq!(
+            Expression::parse(r#"=if(B2<>"a, b", len(A1)>3, "=")"#),
+            Ok(Function {
+                name: String::from("if"),
+                inputs: vec![
+                    Expression::Comparison {
+                        operator: Operator::NotEqual,
+                        left: reference(2, 2),
+                        right: Box::new(Value(String::from("a, b"))),
+                    },
+                    Expression::Comparison {
+                        operator: Operator::Greater,
+                        left: Box::new(Function {
+                            name: String::from("len"),
+                            inputs: vec![*reference(1, 1)],
+                        }),
+                        right: Box::new(Value(String::from("3"))),
+                    },
+                    Value(String::from("=")),
+                ],
+            })
+        );
+        assert_eq!(
+            Expression::parse(r#"=concat(upper("a,b"), "c")"#),
+            Ok(Function {
+                name: String::from("concat"),
+                inputs: vec![
+                    Function {
+                        name: String::from("upper"),
+                        inputs: vec![Value(String::from("a,b"))],
+                    },
+                    Value(String::from("c")),
+                ],
+            })
+        );
+        assert_eq!(
+            Expression::parse("=A1<"),
+            Err("missing operand of the comparison")
+        );
+        assert_eq!(
+            Expression::parse(r#"=concat("a, b)"#),
+            Err("unclosed quotes")
+        );
+        for input in [
+            r#"=if(A1>=3,"a, b",len(B1)<>0)"#,
+            r#"=A1="A1""#,
+            r#"=concat(A1:A,", ")"#,
+            "=add(1,2)<=A2",
+        ] {
+            let expr = Expression::parse(input).expect("parsing failed");
+            assert_eq!(Expression::parse(&expr.to_string()), Ok(expr));
+        }
+    }
+
     #[test]
     fn test_parse_expression_two_commas() {
         let input = "=add(2,, 4)";
@@ -0,0 +1,218 @@
+use crate::expression::Expression;
+use crate::logic::SpecialForm;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// Function of the manifest the API generates from the build, only the fields needed to check
+/// the calls are read.
+#[derive(Deserialize)]
+struct ManifestFunction {
+    name: String,
+    params: Vec<ManifestParam>,
+    #[serde(default)]
+    volatile: bool,
+    #[serde(default)]
+    variadic: bool,
+}
+
+#[derive(Deserialize)]
+struct ManifestParam {
+    #[serde(default)]
+    ty: String,
+    optional: bool,
+}
+
+impl ManifestParam {
+    /// Whether the param is a flat array, e.g. the `Vec<String>` (`string[]`) or `Vec<f32>`
+    /// (`Float32Array`) of the plain `#[wasm_bindgen]` functions, which can't take the rows.
+    fn is_flat_array(&self) -> bool {
+        let ty = self
+            .ty
+            .trim_end_matches(" | undefined")
+            .trim_end_matches(" | null");
+        (ty.ends_with("[]") && !ty.ends_with("[][]")) || ty.ends_with("Array")
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Signature {
+    /// Accepted argument counts, the trailing optional params can be left out.
+    min: usize,
+    max: usize,
+    /// Index of the last param, which collects all the remaining arguments.
+    variadic: Option<usize>,
+    /// Recalculated on every sheet change.
+    volatile: bool,
+    /// Indexes of the params taking the ranges flattened, without the empty cells.
+    flattened: Vec<usize>,
+}
+
+/// Functions exported by the loaded build, the sheet can call.
+#[derive(Debug, Default, PartialEq)]
+pub struct Functions(HashMap<String, Signature>);
+
+impl Functions {
+    pub fn from_manifest(manifest: &str) -> Result<Self, String> {
+        let functions: Vec<ManifestFunction> =
+            serde_json::from_str(manifest).map_err(|err| format!("invalid manifest: {err}"))?;
+        Ok(Functions(
+            functions
+                .into_iter()
+                .map(|function| {
+                    let variadic = match function.variadic {
+                        true => function.params.len().checked_sub(1),
+                        false => None,
+                    };
+                    // The variadic param can collect no arguments.
+                    let fixed = &function.params[..variadic.unwrap_or(function.params.len())];
+                    let min = fixed
+                        .iter()
+                        .rposition(|param| !param.optional)
+                        .map_or(0, |position| position + 1);
+                    let signature = Signature {
+                        min,
+                        max: match variadic {
+                            Some(_) => usize::MAX,
+                            None => function.params.len(),
+                        },
+                        variadic,
+                        volatile: function.volatile,
+                        // The variadic param's array collects the arguments, not a range.
+                        flattened: fixed
+                            .iter()
+                            .enumerate()
+                            .filter(|(_, param)| param.is_flat_array())
+                            .map(|(index, _)| index)
+                            .collect(),
+                    };
+                    (function.name, signature)
+                })
+                .collect(),
+        ))
+    }
+
+    /// Checks the function exists and accepts the number of arguments.
+    pub fn check_call(&self, name: &str, arguments: usize) -> Result<(), String> {
+        if let Some(form) = SpecialForm::parse(name) {
+            return form.check_call(name, arguments);
+        }
+        let Some(signature) = self.0.get(name) else {
+            return Err(format!("unknown function '{name}'"));
+        };
+        if (signature.min..=signature.max).contains(&arguments) {
+            return Ok(());
+        }
+        let expected = match (signature.min, signature.max) {
+            (min, usize::MAX) => format!("at least {min}"),
+            (min, max) if min == max => max.to_string(),
+            (min, max) => format!("{min} to {max}"),
+        };
+        Err(format!(
+            "function '{name}' expects {expected} arguments, got {arguments}"
+        ))
+    }
+
+    /// Index of the function's param collecting all the remaining arguments, if it's variadic.
+    pub fn variadic_param(&self, name: &str) -> Option<usize> {
+        self.0.get(name)?.variadic
+    }
+
+    /// Whether the function's param takes the ranges flattened, without the empty cells.
+    pub fn flattens_param(&self, name: &str, param: usize) -> bool {
+        self.0
+            .get(name)
+            .is_some_and(|signature| signature.flattened.contains(&param))
+    }
+
+    /// Whether the expression calls a volatile function.
+    pub fn is_volatile(&self, expression: &Expression) -> bool {
+        match expression {
+            Expression::Function { name, inputs } => {
+                self.0.get(name).is_some_and(|signature| signature.volatile)
+                    || inputs.iter().any(|input| self.is_volatile(input))
+            }
+            Expression::Comparison { left, right, .. } => {
+                self.is_volatile(left) || self.is_volatile(right)
+            }
+            _ => false,
+        }
+    }
+
+    /// Checks all the function calls of the expression.
+    pub fn check(&self, expression: &Expression) -> Result<(), String> {
+        match expression {
+            Expression::Function { name, inputs } => {
+                self.check_call(name, inputs.len())?;
+                for input in inputs {
+                    self.check(input)?;
+                }
+            }
+            Expression::Comparison { left, right, .. } => {
+                self.check(left)?;
+                self.check(right)?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let functions = Functions::from_manifest(
+            r#"[
+                {"name": "add", "params": [{"name": "a", "ty": "number", "optional": false}, {"name": "b", "ty": "number", "optional": false}], "return_type": "number", "is_async": false, "doc": ""},
+                {"name": "round", "params": [{"name": "a", "ty": "number", "optional": false}, {"name": "digits", "ty": "number | null", "optional": true}], "return_type": "number", "is_async": false, "doc": ""},
+                {"name": "join", "params": [{"name": "sep", "ty": "string", "optional": false}, {"name": "texts", "ty": "string[]", "optional": false}], "return_type": "string", "is_async": false, "doc": "", "variadic": true},
+                {"name": "now", "params": [], "return_type": "number", "is_async": false, "doc": "", "volatile": true},
+                {"name": "med", "params": [{"name": "vec", "ty": "Float32Array", "optional": false}, {"name": "table", "ty": "any[][]", "optional": false}], "return_type": "number", "is_async": false, "doc": ""}
+            ]"#,
+        )
+        .expect("invalid manifest");
+
+        let check = |input: &str| functions.check(&Expression::parse(input).unwrap());
+        assert_eq!(check("=add(1, round(2.5))"), Ok(()));
+        assert_eq!(check("=round(2.5, 1)"), Ok(()));
+        assert_eq!(check("some text"), Ok(()));
+        assert_eq!(
+            check("=add(1, sub(2, 1))"),
+            Err("unknown function 'sub'".into())
+        );
+        assert_eq!(
+            check("=add(1)"),
+            Err("function 'add' expects 2 arguments, got 1".into())
+        );
+        assert_eq!(
+            check("=round(1, 2, 3)"),
+            Err("function 'round' expects 1 to 2 arguments, got 3".into())
+        );
+        assert_eq!(check(r#"=join(", ", A1:A, "x", B2)"#), Ok(()));
+        assert_eq!(check(r#"=join(", ")"#), Ok(()));
+        assert_eq!(
+            check("=join()"),
+            Err("function 'join' expects at least 1 arguments, got 0".into())
+        );
+        assert_eq!(functions.variadic_param("join"), Some(1));
+        assert_eq!(functions.variadic_param("add"), None);
+        assert!(functions.flattens_param("med", 0));
+        assert!(!functions.flattens_param("med", 1));
+        assert!(!functions.flattens_param("join", 1));
+        assert!(!functions.flattens_param("add", 0));
+        assert_eq!(check(r#"=if(add(1, 2) > 2, "yes", now())"#), Ok(()));
+        assert_eq!(
+            check("=if(1 = sub(2, 1), 1)"),
+            Err("unknown function 'sub'".into())
+        );
+        assert_eq!(
+            check("=iferror(1)"),
+            Err("function 'iferror' expects 2 arguments, got 1".into())
+        );
+        assert!(functions.is_volatile(&Expression::parse("=add(1, now())").unwrap()));
+        assert!(functions.is_volatile(&Expression::parse("=now() > A1").unwrap()));
+        assert!(!functions.is_volatile(&Expression::parse("=add(1, 2)").unwrap()));
+    }
+}
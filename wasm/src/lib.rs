@@ -1,3 +1,6 @@
+pub mod date;
 pub mod expression;
+pub mod function;
+pub mod logic;
 pub mod reference;
 pub mod state;
@@ -0,0 +1,408 @@
+//! Conditional logic of the sheet: the comparisons and the special forms, the functions
+//! the sheet evaluates itself, resolving only the arguments they need.
+
+use crate::date;
+use crate::expression::Operator;
+use js_sys::Array;
+use std::cmp::Ordering;
+use wasm_bindgen::{JsCast, JsValue};
+
+/// Serial number (days since 1899-12-30) of the Unix epoch, the dates are compared
+/// to the numbers as the serial numbers, as in the functions.
+const UNIX_EPOCH_SERIAL: f64 = 25569.0;
+const MILLIS_PER_DAY: f64 = 24.0 * 60.0 * 60.0 * 1000.0;
+
+/// Lazily evaluated function, e.g. `if` resolves only the branch it returns, `and` stops
+/// at the first false argument.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum SpecialForm {
+    /// if(condition, then, else), else is false by default.
+    If,
+    /// ifs(condition, value, condition, value...), the value of the first true condition.
+    Ifs,
+    /// switch(value, case, result, case, result..., default).
+    Switch,
+    /// iferror(value, fallback), the fallback if the value is an error.
+    IfError,
+    And,
+    Or,
+}
+
+impl SpecialForm {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "if" => Some(SpecialForm::If),
+            "ifs" => Some(SpecialForm::Ifs),
+            "switch" => Some(SpecialForm::Switch),
+            "iferror" => Some(SpecialForm::IfError),
+            "and" => Some(SpecialForm::And),
+            "or" => Some(SpecialForm::Or),
+            _ => None,
+        }
+    }
+
+    pub fn check_call(&self, name: &str, arguments: usize) -> Result<(), String> {
+        let expected = match self {
+            SpecialForm::If if !(2..=3).contains(&arguments) => "2 to 3 arguments",
+            SpecialForm::Ifs if arguments < 2 || arguments % 2 == 1 => {
+                "pairs of conditions and values"
+            }
+            SpecialForm::Switch if arguments < 3 => "at least 3 arguments",
+            SpecialForm::IfError if arguments != 2 => "2 arguments",
+            SpecialForm::And | SpecialForm::Or if arguments < 1 => "at least 1 arguments",
+            _ => return Ok(()),
+        };
+        Err(format!(
+            "function '{name}' expects {expected}, got {arguments}"
+        ))
+    }
+}
+
+/// Exact number of the numeric text, e.g. the decimals of the `decimal` feature of the
+/// functions, which the `f64` would round.
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct Decimal {
+    /// False for zero.
+    negative: bool,
+    /// Significant digits without the leading and the trailing zeros, empty for zero.
+    digits: Vec<u8>,
+    /// The number is `0.digits` times ten to the exponent.
+    exponent: i64,
+}
+
+impl Decimal {
+    /// Parses the texts Rust parses as `f64`, except for `inf` and `nan`, e.g. `-1.5e2`. The
+    /// functions' `sheeet_funcs::value::parse_number` reads the same texts as the numbers.
+    pub fn parse(text: &str) -> Option<Self> {
+        let text = text.trim();
+        let (negative, text) = match text.strip_prefix('-') {
+            Some(text) => (true, text),
+            None => (false, text.strip_prefix('+').unwrap_or(text)),
+        };
+        let (mantissa, exponent) = match text.split_once(['e', 'E']) {
+            Some((mantissa, exponent)) => (mantissa, exponent.parse::<i64>().ok()?),
+            None => (text, 0),
+        };
+        let (integer, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+        let digits = integer.bytes().chain(fraction.bytes());
+        if integer.len() + fraction.len() == 0 || !digits.clone().all(|d| d.is_ascii_digit()) {
+            return None;
+        }
+        let mut digits: Vec<u8> = digits.map(|digit| digit - b'0').collect();
+        let leading_zeros = digits.iter().take_while(|digit| **digit == 0).count();
+        digits.drain(..leading_zeros);
+        while digits.last() == Some(&0) {
+            digits.pop();
+        }
+        if digits.is_empty() {
+            return Some(Decimal::default());
+        }
+        let exponent = (integer.len() as i64 - leading_zeros as i64).checked_add(exponent)?;
+        Some(Decimal {
+            negative,
+            digits,
+            exponent,
+        })
+    }
+
+    /// The shortest decimal of the finite number, e.g. `0.1` and not the closest binary
+    /// fraction.
+    pub fn from_f64(number: f64) -> Option<Self> {
+        match number.is_finite() {
+            true => Decimal::parse(&format!("{number:e}")),
+            false => None,
+        }
+    }
+
+    fn is_zero(&self) -> bool {
+        self.digits.is_empty()
+    }
+
+    fn cmp_magnitude(&self, other: &Self) -> Ordering {
+        match (self.is_zero(), other.is_zero()) {
+            (true, true) => Ordering::Equal,
+            (true, false) => Ordering::Less,
+            (false, true) => Ordering::Greater,
+            (false, false) => self
+                .exponent
+                .cmp(&other.exponent)
+                .then_with(|| self.digits.cmp(&other.digits)),
+        }
+    }
+}
+
+impl Ord for Decimal {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, false) => self.cmp_magnitude(other),
+            (true, true) => other.cmp_magnitude(self),
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+        }
+    }
+}
+
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Value of the comparison's operand or the condition.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Operand {
+    Empty,
+    Number(f64),
+    /// The numeric text, compared exactly.
+    Decimal(Decimal),
+    Text(String),
+    Bool(bool),
+}
+
+impl Operand {
+    /// The numeric and the ISO date texts are the numbers, `true` and `false` (in any case)
+    /// the booleans, e.g. of the literals compared to the function's result.
+    pub fn from_text(text: &str) -> Self {
+        if let Some(decimal) = Decimal::parse(text) {
+            return Operand::Decimal(decimal);
+        }
+        match text.trim() {
+            text if text.eq_ignore_ascii_case("true") => return Operand::Bool(true),
+            text if text.eq_ignore_ascii_case("false") => return Operand::Bool(false),
+            _ => {}
+        }
+        match date::parse(text) {
+            Some(timestamp) => Operand::Number(serial(timestamp)),
+            None => Operand::Text(text.to_string()),
+        }
+    }
+
+    /// Order of the different kinds.
+    fn rank(&self) -> u8 {
+        match self {
+            Operand::Number(_) | Operand::Decimal(_) => 0,
+            Operand::Text(_) => 1,
+            Operand::Bool(_) => 2,
+            Operand::Empty => 3,
+        }
+    }
+
+    /// The empty cell compared to the operand, e.g. zero to the number.
+    fn empty_of_kind(&self) -> Self {
+        match self {
+            Operand::Number(_) => Operand::Number(0.0),
+            Operand::Decimal(_) => Operand::Decimal(Decimal::default()),
+            Operand::Text(_) => Operand::Text(String::new()),
+            Operand::Bool(_) => Operand::Bool(false),
+            Operand::Empty => Operand::Empty,
+        }
+    }
+}
+
+fn serial(timestamp: f64) -> f64 {
+    timestamp / MILLIS_PER_DAY + UNIX_EPOCH_SERIAL
+}
+
+pub fn error_value(message: &str) -> JsValue {
+    js_sys::Error::new(message).into()
+}
+
+pub fn is_error(value: &JsValue) -> bool {
+    value.is_instance_of::<js_sys::Error>()
+}
+
+/// Operand of the cell's value, `Err` with the error value if it's an error or a range.
+pub fn operand(value: &JsValue) -> Result<Operand, JsValue> {
+    if is_error(value) {
+        return Err(value.clone());
+    }
+    if value.is_null() || value.is_undefined() {
+        return Ok(Operand::Empty);
+    }
+    if let Some(boolean) = value.as_bool() {
+        return Ok(Operand::Bool(boolean));
+    }
+    if let Some(number) = value.as_f64() {
+        return Ok(Operand::Number(number));
+    }
+    if let Some(text) = value.as_string() {
+        return Ok(Operand::from_text(&text));
+    }
+    if let Some(js_date) = value.dyn_ref::<js_sys::Date>() {
+        return Ok(Operand::Number(serial(js_date.get_time())));
+    }
+    match Array::is_array(value) {
+        true => Err(error_value("expected a single value, got a range")),
+        false => Err(error_value(&format!("can't compare {value:?}"))),
+    }
+}
+
+/// Values of the argument, the ranges' (and the arrays') values without the empty cells.
+pub fn flatten(value: JsValue, values: &mut Vec<JsValue>) {
+    if Array::is_array(&value) {
+        for value in Array::from(&value) {
+            flatten(value, values);
+        }
+    } else if !value.is_null() && !value.is_undefined() {
+        values.push(value);
+    }
+}
+
+fn ordering(left: &Operand, right: &Operand) -> Ordering {
+    match (left, right) {
+        (Operand::Empty, Operand::Empty) => Ordering::Equal,
+        (Operand::Empty, other) => ordering(&other.empty_of_kind(), other),
+        (other, Operand::Empty) => ordering(other, &other.empty_of_kind()),
+        (Operand::Number(left), Operand::Number(right)) => left.total_cmp(right),
+        (Operand::Decimal(left), Operand::Decimal(right)) => left.cmp(right),
+        (Operand::Number(left), Operand::Decimal(right)) => number_ordering(*left, right),
+        (Operand::Decimal(left), Operand::Number(right)) => number_ordering(*right, left).reverse(),
+        (Operand::Text(left), Operand::Text(right)) => {
+            left.to_lowercase().cmp(&right.to_lowercase())
+        }
+        (Operand::Bool(left), Operand::Bool(right)) => left.cmp(right),
+        (left, right) => left.rank().cmp(&right.rank()),
+    }
+}
+
+fn number_ordering(number: f64, decimal: &Decimal) -> Ordering {
+    match Decimal::from_f64(number) {
+        Some(number) => number.cmp(decimal),
+        // The infinities and the NaN are beyond all the finite numbers.
+        None => number.total_cmp(&0.0),
+    }
+}
+
+/// Compares as the other spreadsheets: the texts case-insensitively, the empty cell as
+/// the zero, the empty text or false, the numbers before the texts before the booleans.
+pub fn compare(operator: Operator, left: &Operand, right: &Operand) -> bool {
+    let ordering = ordering(left, right);
+    match operator {
+        Operator::Equal => ordering.is_eq(),
+        Operator::NotEqual => ordering.is_ne(),
+        Operator::Less => ordering.is_lt(),
+        Operator::LessOrEqual => ordering.is_le(),
+        Operator::Greater => ordering.is_gt(),
+        Operator::GreaterOrEqual => ordering.is_ge(),
+    }
+}
+
+/// Truth of the condition, the non-zero numbers are true, the texts must be `true` or `false`.
+pub fn truthy(operand: &Operand) -> Result<bool, String> {
+    match operand {
+        Operand::Empty => Ok(false),
+        Operand::Bool(boolean) => Ok(*boolean),
+        Operand::Number(number) => Ok(*number != 0.0),
+        Operand::Decimal(decimal) => Ok(!decimal.is_zero()),
+        Operand::Text(text) if text.is_empty() => Ok(false),
+        Operand::Text(text) => Err(format!(r#"expected true or false, got "{text}""#)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_compare() {
+        let number = |number| Operand::Number(number);
+        let text = |text: &str| Operand::from_text(text);
+        assert!(compare(Operator::Equal, &text(" 42 "), &number(42.0)));
+        assert_eq!(text("nan"), Operand::Text("nan".into()));
+        assert_eq!(text("2025-03-01"), number(45717.0));
+        assert!(compare(Operator::Equal, &text("Abc"), &text("aBC")));
+        assert!(compare(Operator::Less, &text("10"), &number(9.5 + 1.0)));
+        assert!(compare(Operator::Greater, &text("b"), &text("A")));
+        // The numbers before the texts before the booleans.
+        assert!(compare(Operator::Less, &number(1e9), &text("a")));
+        assert!(compare(Operator::Less, &text("z"), &Operand::Bool(false)));
+        assert_eq!(text(" False "), Operand::Bool(false));
+        assert!(compare(
+            Operator::Equal,
+            &Operand::Bool(true),
+            &text("TRUE")
+        ));
+        assert!(compare(
+            Operator::NotEqual,
+            &Operand::Bool(false),
+            &text("true")
+        ));
+        assert!(compare(Operator::Equal, &Operand::Empty, &number(0.0)));
+        assert!(compare(Operator::Equal, &text(""), &Operand::Empty));
+        assert!(compare(
+            Operator::Equal,
+            &Operand::Bool(false),
+            &Operand::Empty
+        ));
+        assert!(compare(Operator::NotEqual, &number(0.0), &text("")));
+        assert!(compare(
+            Operator::GreaterOrEqual,
+            &number(2.0),
+            &number(2.0)
+        ));
+        assert!(!compare(Operator::LessOrEqual, &number(3.0), &number(2.0)));
+    }
+
+    #[test]
+    fn test_decimal() {
+        let decimal = |text| Decimal::parse(text).unwrap();
+        assert_eq!(decimal(" +1.50e1 "), decimal("15"));
+        assert_eq!(decimal("-0.0"), Decimal::default());
+        assert_eq!(decimal(".5"), decimal("5e-1"));
+        assert_eq!(Decimal::from_f64(0.1), Some(decimal("0.1")));
+        assert_eq!(Decimal::parse("1.2.3"), None);
+        assert_eq!(Decimal::parse("inf"), None);
+        assert!(decimal("0.1000000000000000000001") > decimal("0.1"));
+        assert!(decimal("12345678901234567891") > decimal("12345678901234567890"));
+        assert!(decimal("-2") < decimal("-1.5"));
+        assert!(decimal("-0.001") < decimal("0"));
+        assert!(decimal("99") < decimal("100"));
+        // Beyond the f64 precision, the numeric texts still compare exactly.
+        let text = |text: &str| Operand::from_text(text);
+        assert!(compare(
+            Operator::NotEqual,
+            &text("0.1000000000000000000001"),
+            &Operand::Number(0.1)
+        ));
+        assert!(compare(
+            Operator::Equal,
+            &text("0.10"),
+            &Operand::Number(0.1)
+        ));
+        assert!(compare(
+            Operator::Less,
+            &text("1e400"),
+            &Operand::Number(f64::INFINITY)
+        ));
+    }
+
+    #[test]
+    fn test_truthy() {
+        assert_eq!(truthy(&Operand::Empty), Ok(false));
+        assert_eq!(truthy(&Operand::Number(-1.0)), Ok(true));
+        assert_eq!(truthy(&Operand::from_text("0")), Ok(false));
+        assert_eq!(truthy(&Operand::from_text("0.000")), Ok(false));
+        assert_eq!(truthy(&Operand::from_text("1e-30")), Ok(true));
+        assert_eq!(truthy(&Operand::from_text("TRUE")), Ok(true));
+        assert_eq!(
+            truthy(&Operand::from_text("yes")),
+            Err(r#"expected true or false, got "yes""#.into())
+        );
+    }
+
+    #[test]
+    fn test_check_call() {
+        let check = |name| SpecialForm::parse(name).unwrap().check_call(name, 3);
+        assert_eq!(check("if"), Ok(()));
+        assert_eq!(
+            check("ifs"),
+            Err("function 'ifs' expects pairs of conditions and values, got 3".into())
+        );
+        assert_eq!(check("switch"), Ok(()));
+        assert_eq!(
+            check("iferror"),
+            Err("function 'iferror' expects 2 arguments, got 3".into())
+        );
+        assert_eq!(SpecialForm::parse("sum"), None);
+    }
+}
@@ -1,4 +1,5 @@
 use sheeet_wasm::expression::Expression;
+use sheeet_wasm::function::Functions;
 use sheeet_wasm::reference::{CellPointer, usize_to_column_name};
 use sheeet_wasm::state::{
     Dependencies, SerializableState, State, dispatch_display_cell_value_event, log,
@@ -13,7 +14,7 @@ pub fn run_evaluate(input: &str) -> JsValue {
         .with_borrow_mut(|state| {
             let expression = Expression::parse(input).map_err(|err| JsValue::from_str(err))?;
             let mut dependencies = Dependencies::default();
-            state.resolve_expression_value_and_dependencies(&mut dependencies, &expression)
+            state.resolve_expression_value_and_dependencies(&mut dependencies, None, &expression)
         })
         .unwrap_or_else(|err| err)
 }
@@ -27,7 +28,7 @@ pub fn get_cell_raw_value(id: &str) -> String {
 #[wasm_bindgen]
 pub fn get_cell_resolved_value(id: &str) -> JsValue {
     let key = CellPointer::from_serializable(id);
-    STATE.with_borrow(|state| state.get_cell_resolved_value(key).unwrap_or_default())
+    STATE.with_borrow(|state| state.get_cell_display_value(key).unwrap_or_default())
 }
 
 #[wasm_bindgen]
@@ -38,11 +39,17 @@ pub fn set_cell_raw_value(id: &str, raw: &str) -> Result<JsValue, JsValue> {
             // Remove.
             0 => {
                 state.remove_cell(cell_pointer)?;
-                Ok(JsValue::null())
+                // An array value can spill into the emptied cell.
+                Ok(state
+                    .get_cell_display_value(cell_pointer)
+                    .unwrap_or(JsValue::null()))
             }
             // Upsert.
             _ => {
-                let resolved_value = state.upsert_cell(cell_pointer, raw)?;
+                state.upsert_cell(cell_pointer, raw)?;
+                let resolved_value = state
+                    .get_cell_display_value(cell_pointer)
+                    .unwrap_or(JsValue::null());
                 if resolved_value.is_null() {
                     // Show the raw value if we can't find reference.
                     Ok(JsValue::from_str(
@@ -66,6 +73,14 @@ pub fn copy_cell_get_raw_value(from_id: &str, to_id: &str) -> Result<JsValue, Js
     })
 }
 
+#[wasm_bindgen]
+pub fn serialize_app_state() -> Result<String, JsValue> {
+    STATE.with_borrow(|state| {
+        serde_json::to_string(&state.to_serializable_state())
+            .map_err(|err| JsValue::from(err.to_string()))
+    })
+}
+
 #[wasm_bindgen]
 pub fn save_app_state_to_local_storage() -> Result<(), JsValue> {
     let window = window().ok_or("could not get window")?;
@@ -73,10 +88,7 @@ pub fn save_app_state_to_local_storage() -> Result<(), JsValue> {
         .local_storage()?
         .ok_or("could not get local storage")?;
 
-    let serialized = STATE.with_borrow(|state| {
-        serde_json::to_string(&state.to_serializable_state())
-            .map_err(|err| JsValue::from(err.to_string()))
-    })?;
+    let serialized = serialize_app_state()?;
 
     local_storage.set_item("sheet-data", &serialized)?;
 
@@ -90,10 +102,17 @@ fn main() {
     log("log from wasm main");
 }
 
+/// Initializes the sheet from the `sheet_data` if given (e.g. read-only shared snapshot),
+/// otherwise from the local storage. The function calls are checked against the build's
+/// `functions` manifest, if given.
 #[wasm_bindgen]
-pub fn init_app() -> Result<(), JsValue> {
+pub fn init_app(sheet_data: Option<String>, functions: Option<String>) -> Result<(), JsValue> {
+    let mut functions = functions
+        .map(|manifest| Functions::from_manifest(&manifest))
+        .transpose()?;
     if STATE.with_borrow_mut(|state| {
         if state.initialized {
+            state.set_functions(functions.take());
             state.recalculate()?;
         }
         Ok::<bool, JsValue>(state.initialized)
@@ -110,17 +129,21 @@ pub fn init_app() -> Result<(), JsValue> {
     let local_storage = window
         .local_storage()?
         .ok_or("could not get local storage")?;
-    let (columns, rows) = match local_storage.get_item("sheet-data")? {
+    let sheet_data = match sheet_data {
+        Some(data) => Some(data),
+        None => local_storage.get_item("sheet-data")?,
+    };
+    let (columns, rows) = match sheet_data {
         Some(data) => {
             let saved_state: SerializableState =
                 serde_json::from_str(&data).map_err(|err| JsValue::from(err.to_string()))?;
-            let state = saved_state.to_memory_state()?;
+            let state = saved_state.to_memory_state(functions)?;
             let bounds = state.sheet_bounds;
             STATE.set(state);
             bounds
         }
         None => {
-            let state = State::new();
+            let state = State::new(functions);
             let bounds = state.sheet_bounds;
             STATE.set(state);
             bounds
@@ -167,7 +190,7 @@ pub fn init_app() -> Result<(), JsValue> {
                             0 => td.set_text_content(Some(&row.to_string())),
                             column => {
                                 td.set_id(&format!("{}-{}", column, row));
-                                cell_value = match state.get_cell_resolved_value(key) {
+                                cell_value = match state.get_cell_display_value(key) {
                                     Some(value) => Some(value),
                                     None => match state.get_cell_raw_value(key) {
                                         None => None,
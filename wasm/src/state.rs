@@ -1,12 +1,16 @@
 use super::debug_log;
-use crate::expression::Expression;
+use crate::date;
+use crate::expression::{Expression, Operator};
+use crate::function::Functions;
+use crate::logic::{self, SpecialForm};
 use crate::reference::{CellPointer, Reference};
 use js_sys::Array;
 use serde::{Deserialize, Serialize};
 use std::cmp::{max, min};
 use std::collections::{HashMap, HashSet, LinkedList};
-use wasm_bindgen::JsValue;
+use std::ops::RangeInclusive;
 use wasm_bindgen::prelude::wasm_bindgen;
+use wasm_bindgen::{JsCast, JsValue};
 use web_sys::{CustomEvent, CustomEventInit, window};
 
 #[wasm_bindgen]
@@ -17,8 +21,13 @@ extern "C" {
     #[wasm_bindgen(js_namespace = console)]
     pub fn debug(s: &str);
 
+    /// Calls the user function, the cell making the call is shown with the function's logs.
     #[wasm_bindgen(catch, js_namespace = window)]
-    pub fn js_evaluate(fn_name: &str, vars: &Array) -> Result<JsValue, JsValue>;
+    pub fn js_evaluate(
+        fn_name: &str,
+        vars: &Array,
+        cell: Option<String>,
+    ) -> Result<JsValue, JsValue>;
 }
 
 #[macro_export]
@@ -37,6 +46,12 @@ struct Cell {
     resolved_dependencies: Option<Dependencies>,
 }
 
+/// Element of the array value spilled from the origin cell into the empty cell.
+struct Spilled {
+    origin: CellPointer,
+    value: JsValue,
+}
+
 #[derive(Default)]
 pub struct State {
     pub initialized: bool,
@@ -45,10 +60,14 @@ pub struct State {
     reverse_index_singles: HashMap<CellPointer, HashSet<CellPointer>>,
     reverse_index_cols: HashMap<usize, HashSet<CellPointer>>,
     reverse_index_rows: HashMap<usize, HashSet<CellPointer>>,
+    /// Empty cells the array values spill into.
+    spilled: HashMap<CellPointer, Spilled>,
+    /// Functions of the loaded build, the calls aren't checked if unknown.
+    functions: Option<Functions>,
 }
 
 impl State {
-    pub fn new() -> Self {
+    pub fn new(functions: Option<Functions>) -> Self {
         State {
             initialized: true,
             sheet_bounds: (27, 65),
@@ -56,9 +75,15 @@ impl State {
             reverse_index_singles: HashMap::new(),
             reverse_index_cols: HashMap::new(),
             reverse_index_rows: HashMap::new(),
+            spilled: HashMap::new(),
+            functions,
         }
     }
 
+    pub fn set_functions(&mut self, functions: Option<Functions>) {
+        self.functions = functions;
+    }
+
     pub fn to_serializable_state(&self) -> SerializableState {
         let mut serializable_state = SerializableState {
             sheet_bounds: self.sheet_bounds,
@@ -93,7 +118,7 @@ pub struct SerializableState {
 }
 
 impl SerializableState {
-    pub fn to_memory_state(self) -> Result<State, JsValue> {
+    pub fn to_memory_state(self, functions: Option<Functions>) -> Result<State, JsValue> {
         let mut new_state = State {
             initialized: true,
             sheet_bounds: self.sheet_bounds,
@@ -101,6 +126,8 @@ impl SerializableState {
             reverse_index_singles: HashMap::new(),
             reverse_index_cols: HashMap::new(),
             reverse_index_rows: HashMap::new(),
+            spilled: HashMap::new(),
+            functions,
         };
         for (k, v) in self.data {
             new_state.insert_cell(k.clone(), &v)?;
@@ -150,6 +177,17 @@ impl State {
         cell.resolved_value.clone()
     }
 
+    /// Value shown in the cell, the first element of the array value or the spilled one.
+    pub fn get_cell_display_value(&self, key: CellPointer) -> Option<JsValue> {
+        match self.cells.get(&key) {
+            Some(cell) => cell.resolved_value.as_ref().map(display_value),
+            None => self
+                .spilled
+                .get(&key)
+                .map(|spilled| display_value(&spilled.value)),
+        }
+    }
+
     pub fn insert_cell(&mut self, key: CellPointer, raw: &str) -> Result<(), JsValue> {
         debug_log!("insert_cell: {key} -> {raw}");
         let expr = Expression::parse(raw)?;
@@ -199,11 +237,36 @@ impl State {
                 }
             });
         self.check_circular_dependency(key, &expr)?;
-        self.resolve_cell_value_and_dependencies(key, ResolveDisplay::UpdateNext)
+        let resolved_value =
+            self.resolve_cell_value_and_dependencies(key, ResolveDisplay::UpdateNext)?;
+        self.recalculate_volatile(key)?;
+        Ok(resolved_value)
+    }
+
+    /// Volatile cells (calling e.g. the current time) are recalculated on every sheet change,
+    /// except the changed cell, which is resolved already.
+    fn recalculate_volatile(&mut self, changed: CellPointer) -> Result<(), JsValue> {
+        let Some(functions) = &self.functions else {
+            return Ok(());
+        };
+        let volatile: Vec<CellPointer> = self
+            .cells
+            .iter()
+            .filter(|(key, cell)| {
+                **key != changed && functions.is_volatile(&cell.parsed_expression)
+            })
+            .map(|(key, _)| *key)
+            .collect();
+        for key in volatile {
+            debug_log!("recalculate_volatile: {key}");
+            self.resolve_cell_value_and_dependencies(key, ResolveDisplay::Update)?;
+        }
+        Ok(())
     }
 
     pub fn remove_cell(&mut self, key: CellPointer) -> Result<(), JsValue> {
         debug_log!("remove_cell: {key}");
+        self.set_spill(key, Vec::new(), ResolveDisplay::Update)?;
         if let Some(mut cell) = self.cells.remove(&key) {
             if let Some(dependencies) = cell.resolved_dependencies.take() {
                 for dependency in dependencies.singles {
@@ -223,7 +286,12 @@ impl State {
                 }
             }
         };
-        if let Some(dependents) = self.reverse_index_singles.remove(&key) {
+        // The dependents stay, the cell can be filled again (e.g. by a spill).
+        if let Some(dependents) = self
+            .reverse_index_singles
+            .get(&key)
+            .map(|dependents| dependents.clone())
+        {
             for dependent in dependents {
                 debug_log!("remove_cell: update single dependent: {dependent}");
                 self.resolve_cell_value_and_dependencies(dependent, ResolveDisplay::Update)?;
@@ -255,7 +323,7 @@ impl State {
                 _ = self.reverse_index_rows.remove(&key.1);
             }
         };
-        Ok(())
+        self.recalculate_volatile(key)
     }
 
     fn check_circular_dependency(
@@ -280,6 +348,10 @@ impl State {
                 }
                 Ok(())
             }
+            Expression::Comparison { left, right, .. } => {
+                self.check_circular_dependency_inner(left, visited)?;
+                self.check_circular_dependency_inner(right, visited)
+            }
             Expression::Reference(reference) => match reference {
                 Reference::Single(key) => {
                     self.check_circular_dependency_single_reference(key, visited)
@@ -330,7 +402,7 @@ impl State {
                     Ok(())
                 }
             },
-            Expression::Value(_) => Ok(()),
+            Expression::Value(_) | Expression::Date(_) => Ok(()),
         }
     }
 
@@ -370,16 +442,39 @@ impl State {
         let parsed_expression = cell.parsed_expression.clone();
 
         let mut new_dependencies = Dependencies::default();
-        let new_resolved_value = self
-            .resolve_expression_value_and_dependencies(&mut new_dependencies, &parsed_expression)
-            .unwrap_or_else(|err| format!("resolve error: {err:?}").into());
+        // The cell calling an unknown function is kept, its value is the error.
+        let mut new_resolved_value = match self
+            .functions
+            .as_ref()
+            .map(|functions| functions.check(&parsed_expression))
+        {
+            Some(Err(err)) => logic::error_value(&err),
+            _ => self
+                .resolve_expression_value_and_dependencies(
+                    &mut new_dependencies,
+                    Some(key),
+                    &parsed_expression,
+                )
+                .unwrap_or_else(|err| format!("resolve error: {err:?}").into()),
+        };
+
+        // The array value spills into the cells next to the cell, unless they are taken.
+        let mut spill = spill_layout(key, &new_resolved_value);
+        // Recalculated once the cells it spills into are filled or emptied.
+        new_dependencies
+            .singles
+            .extend(spill.iter().map(|(target, _)| *target));
+        if let Some(error) = self.spill_error(key, &spill) {
+            new_resolved_value = js_sys::Error::new(&error).into();
+            spill.clear();
+        }
 
         // Update cell's resolved values.
         debug_log!(
             "resolve_cell_value_and_dependencies: update resolved cell value: {key} -> {new_resolved_value:?}"
         );
         if let ResolveDisplay::Update = display {
-            dispatch_display_cell_value_event(key, new_resolved_value.clone())?;
+            dispatch_display_cell_value_event(key, display_value(&new_resolved_value))?;
         }
         self.cells.entry(key).and_modify(|entry| {
             entry.resolved_value = Some(new_resolved_value.clone());
@@ -463,6 +558,8 @@ impl State {
                 });
         }
 
+        self.set_spill(key, spill, display.next())?;
+
         // Compare the old and new resolved values, and only if they differ
         // update recursively all dependents.
         if let Some(old_resolved_value) = old_resolved_value {
@@ -472,61 +569,160 @@ impl State {
             }
         }
 
-        if let Some(dependents) = self
-            .reverse_index_singles
-            .get(&key)
-            .map(|dependents| dependents.clone())
-        {
-            for dependent in dependents {
-                debug_log!(
-                    "resolve_cell_value_and_dependencies: update single dependent: {dependent}"
-                );
-                self.resolve_cell_value_and_dependencies(dependent, display.next())?;
+        self.resolve_dependents(key, None, display.next())?;
+
+        Ok(new_resolved_value)
+    }
+
+    /// Resolves the cells depending on the `key`, except the `origin` spilling into it.
+    fn resolve_dependents(
+        &mut self,
+        key: CellPointer,
+        origin: Option<CellPointer>,
+        display: ResolveDisplay,
+    ) -> Result<(), JsValue> {
+        let mut dependents = Vec::new();
+        if let Some(singles) = self.reverse_index_singles.get(&key) {
+            dependents.extend(singles.iter().copied());
+        }
+        if let Some(cols) = self.reverse_index_cols.get(&key.0) {
+            dependents.extend(cols.iter().copied());
+        }
+        if let Some(rows) = self.reverse_index_rows.get(&key.1) {
+            dependents.extend(rows.iter().copied());
+        }
+        for dependent in dependents {
+            if Some(dependent) == origin || !self.cells.contains_key(&dependent) {
+                continue;
             }
+            debug_log!("resolve_dependents: update dependent of {key}: {dependent}");
+            self.resolve_cell_value_and_dependencies(dependent, display)?;
         }
-        if let Some(dependents) = self
-            .reverse_index_cols
-            .get(&key.0)
-            .map(|dependents| dependents.clone())
-        {
-            for dependent in dependents {
-                debug_log!(
-                    "resolve_cell_value_and_dependencies: update col dependent: {dependent}"
-                );
-                self.resolve_cell_value_and_dependencies(dependent, display.next())?;
+        Ok(())
+    }
+
+    /// Why the origin can't spill into the cells, if it can't.
+    fn spill_error(&self, origin: CellPointer, spill: &[(CellPointer, JsValue)]) -> Option<String> {
+        spill.iter().find_map(|(target, _)| {
+            if target.0 >= self.sheet_bounds.0 || target.1 >= self.sheet_bounds.1 {
+                return Some(format!("spill out of the sheet at {target}"));
+            }
+            let taken = self.cells.contains_key(target)
+                || self
+                    .spilled
+                    .get(target)
+                    .is_some_and(|spilled| spilled.origin != origin);
+            taken.then(|| format!("spill blocked by {target}"))
+        })
+    }
+
+    /// Replaces the cells the origin spills into, the dependents of the changed ones
+    /// are resolved.
+    fn set_spill(
+        &mut self,
+        origin: CellPointer,
+        spill: Vec<(CellPointer, JsValue)>,
+        display: ResolveDisplay,
+    ) -> Result<(), JsValue> {
+        let removed: Vec<CellPointer> = self
+            .spilled
+            .iter()
+            .filter(|(target, spilled)| {
+                spilled.origin == origin && !spill.iter().any(|(kept, _)| kept == *target)
+            })
+            .map(|(target, _)| *target)
+            .collect();
+        let mut changed = Vec::new();
+        for target in removed {
+            self.spilled.remove(&target);
+            // The cell filled over the spill shows its own value.
+            if !self.cells.contains_key(&target) {
+                if let ResolveDisplay::Update = display {
+                    dispatch_display_cell_value_event(target, JsValue::from_str(""))?;
+                }
+                changed.push(target);
             }
         }
-        if let Some(dependents) = self
-            .reverse_index_rows
-            .get(&key.1)
-            .map(|dependents| dependents.clone())
-        {
-            for dependent in dependents {
-                debug_log!(
-                    "resolve_cell_value_and_dependencies: update row dependent: {dependent}"
-                );
-                self.resolve_cell_value_and_dependencies(dependent, display.next())?;
+        for (target, value) in spill {
+            if let ResolveDisplay::Update = display {
+                dispatch_display_cell_value_event(target, display_value(&value))?;
             }
+            if self
+                .spilled
+                .get(&target)
+                .is_none_or(|spilled| spilled.value != value)
+            {
+                changed.push(target);
+            }
+            self.spilled.insert(target, Spilled { origin, value });
         }
-
-        Ok(new_resolved_value)
+        for target in changed {
+            self.resolve_dependents(target, Some(origin), display)?;
+        }
+        Ok(())
     }
 
+    /// Resolves the expression of the `cell`, `None` if it isn't in the sheet (e.g. the test
+    /// expression).
     pub fn resolve_expression_value_and_dependencies(
         &mut self,
         dependencies: &mut Dependencies,
+        cell: Option<CellPointer>,
         expression: &Expression,
     ) -> Result<JsValue, JsValue> {
         match expression {
             Expression::Function { name, inputs } => {
+                if let Some(form) = SpecialForm::parse(name) {
+                    form.check_call(name, inputs.len())?;
+                    return self.resolve_special_form(dependencies, cell, form, inputs);
+                }
+                // Cells saved before the build changed may call the removed functions.
+                if let Some(functions) = &self.functions {
+                    functions.check_call(name, inputs.len())?;
+                }
+                let variadic = self
+                    .functions
+                    .as_ref()
+                    .and_then(|functions| functions.variadic_param(name));
                 let js_inputs = Array::new();
-                for input in inputs {
-                    let val =
-                        self.resolve_expression_value_and_dependencies(dependencies, input)?;
-                    js_inputs.push(&val);
+                let variadic_inputs = Array::new();
+                for (i, input) in inputs.iter().enumerate() {
+                    let mut val =
+                        self.resolve_expression_value_and_dependencies(dependencies, cell, input)?;
+                    // The plain `#[wasm_bindgen]` `Vec` params and the builds before the
+                    // manifest take the ranges flattened, the rows with the empty cells fail.
+                    let flattened = match &self.functions {
+                        Some(functions) => functions.flattens_param(name, i),
+                        None => true,
+                    };
+                    if flattened && Array::is_array(&val) {
+                        let mut values = Vec::new();
+                        logic::flatten(val, &mut values);
+                        val = values.into_iter().collect::<Array>().into();
+                    }
+                    match variadic {
+                        Some(param) if i >= param => variadic_inputs.push(&val),
+                        _ => js_inputs.push(&val),
+                    };
+                }
+                // The remaining arguments are passed to the variadic function as one array.
+                if let Some(param) = variadic {
+                    while (js_inputs.length() as usize) < param {
+                        js_inputs.push(&JsValue::undefined());
+                    }
+                    js_inputs.push(&variadic_inputs);
                 }
                 debug_log!("call '{name}' with {js_inputs:?}");
-                js_evaluate(&name, &js_inputs)
+                let value = js_evaluate(name, &js_inputs, cell.map(|cell| cell.to_string()))?;
+                // The referenced cells are the dependencies, as if the cell referenced them.
+                match returned_reference(&value) {
+                    Some(reference) => self.resolve_expression_value_and_dependencies(
+                        dependencies,
+                        cell,
+                        &Expression::Reference(reference),
+                    ),
+                    None => Ok(value),
+                }
             }
             Expression::Reference(reference) => match reference {
                 Reference::Single(key) => {
@@ -534,76 +730,216 @@ impl State {
                     self.resolve_single_reference_value_and_dependencies(&key)
                 }
                 Reference::BoundedRange(range_start, range_end) => {
-                    let min_col = min(range_start.0, range_end.0);
-                    let max_col = max(range_start.0, range_end.0);
-                    let min_row = min(range_start.1, range_end.1);
-                    let max_row = max(range_start.1, range_end.1);
-                    let ref_values = Array::new();
-                    for col in min_col..=max_col {
-                        for row in min_row..=max_row {
-                            let key = CellPointer(col, row);
-                            dependencies.singles.insert(key.clone());
-                            let ref_value =
-                                self.resolve_single_reference_value_and_dependencies(&key)?;
-                            if ref_value.is_null() || ref_value.is_undefined() {
-                                continue;
-                            }
-                            ref_values.push(&ref_value);
+                    let cols = min(range_start.0, range_end.0)..=max(range_start.0, range_end.0);
+                    let rows = min(range_start.1, range_end.1)..=max(range_start.1, range_end.1);
+                    for col in cols.clone() {
+                        for row in rows.clone() {
+                            dependencies.singles.insert(CellPointer(col, row));
                         }
                     }
-                    Ok(JsValue::from(ref_values))
+                    self.resolve_range_value(cols, rows)
                 }
                 Reference::UnboundedColRange(range_start, col) => {
-                    let min_col = range_start.0;
-                    let max_col = col.clone();
-                    let ref_values = Array::new();
-                    for col in min_col..=max_col {
-                        dependencies.cols.insert(col);
-                        let keys = self
-                            .cells
-                            .keys()
-                            .filter(|key| key.0 == col && key.1 >= range_start.1)
-                            .map(|key| key.clone())
-                            .collect::<Vec<CellPointer>>();
-                        for key in keys {
-                            let ref_value =
-                                self.resolve_single_reference_value_and_dependencies(&key)?;
-                            if ref_value.is_null() || ref_value.is_undefined() {
-                                continue;
-                            }
-                            ref_values.push(&ref_value);
+                    let cols = range_start.0..=*col;
+                    dependencies.cols.extend(cols.clone());
+                    // Down to the last filled row of the columns.
+                    let last_row = self
+                        .cells
+                        .keys()
+                        .chain(self.spilled.keys())
+                        .filter(|key| cols.contains(&key.0) && key.1 >= range_start.1)
+                        .map(|key| key.1)
+                        .max()
+                        .unwrap_or(0);
+                    self.resolve_range_value(cols, range_start.1..=last_row)
+                }
+                Reference::UnboundedRowRange(range_start, row) => {
+                    let rows = range_start.1..=*row;
+                    dependencies.rows.extend(rows.clone());
+                    // Right to the last filled column of the rows.
+                    let last_col = self
+                        .cells
+                        .keys()
+                        .chain(self.spilled.keys())
+                        .filter(|key| rows.contains(&key.1) && key.0 >= range_start.0)
+                        .map(|key| key.0)
+                        .max()
+                        .unwrap_or(0);
+                    self.resolve_range_value(range_start.0..=last_col, rows)
+                }
+            },
+            Expression::Value(val) => Ok(JsValue::from_str(&val)),
+            Expression::Date(timestamp) => {
+                Ok(js_sys::Date::new(&JsValue::from_f64(*timestamp)).into())
+            }
+            Expression::Comparison {
+                operator,
+                left,
+                right,
+            } => {
+                let left =
+                    self.resolve_expression_value_and_dependencies(dependencies, cell, left)?;
+                let right =
+                    self.resolve_expression_value_and_dependencies(dependencies, cell, right)?;
+                Ok(match (logic::operand(&left), logic::operand(&right)) {
+                    (Ok(left), Ok(right)) => {
+                        JsValue::from_bool(logic::compare(*operator, &left, &right))
+                    }
+                    (Err(error), _) | (_, Err(error)) => error,
+                })
+            }
+        }
+    }
+
+    /// Resolves only the arguments the special form needs, so the dependencies are only
+    /// the cells of the taken branch.
+    fn resolve_special_form(
+        &mut self,
+        dependencies: &mut Dependencies,
+        cell: Option<CellPointer>,
+        form: SpecialForm,
+        inputs: &[Expression],
+    ) -> Result<JsValue, JsValue> {
+        match form {
+            SpecialForm::If => match self.resolve_condition(dependencies, cell, &inputs[0])? {
+                Ok(true) => {
+                    self.resolve_expression_value_and_dependencies(dependencies, cell, &inputs[1])
+                }
+                Ok(false) => match inputs.get(2) {
+                    Some(input) => {
+                        self.resolve_expression_value_and_dependencies(dependencies, cell, input)
+                    }
+                    None => Ok(JsValue::FALSE),
+                },
+                Err(error) => Ok(error),
+            },
+            SpecialForm::Ifs => {
+                for pair in inputs.chunks(2) {
+                    match self.resolve_condition(dependencies, cell, &pair[0])? {
+                        Ok(true) => {
+                            return self.resolve_expression_value_and_dependencies(
+                                dependencies,
+                                cell,
+                                &pair[1],
+                            );
                         }
+                        Ok(false) => {}
+                        Err(error) => return Ok(error),
                     }
-                    Ok(JsValue::from(ref_values))
                 }
-                Reference::UnboundedRowRange(range_start, row) => {
-                    let min_row = range_start.1;
-                    let max_row = row.clone();
-                    let ref_values = Array::new();
-                    for col in min_row..=max_row {
-                        dependencies.rows.insert(col);
-                        let keys = self
-                            .cells
-                            .keys()
-                            .filter(|key| key.1 == col && key.0 >= range_start.0)
-                            .map(|key| key.clone())
-                            .collect::<Vec<CellPointer>>();
-                        for key in keys {
-                            let ref_value =
-                                self.resolve_single_reference_value_and_dependencies(&key)?;
-                            if ref_value.is_null() || ref_value.is_undefined() {
-                                continue;
+                Ok(logic::error_value("no condition of ifs is true"))
+            }
+            SpecialForm::Switch => {
+                let value =
+                    self.resolve_expression_value_and_dependencies(dependencies, cell, &inputs[0])?;
+                let value = match logic::operand(&value) {
+                    Ok(value) => value,
+                    Err(error) => return Ok(error),
+                };
+                for pair in inputs[1..].chunks(2) {
+                    // The last unpaired argument is the default.
+                    let [case, result] = pair else {
+                        return self.resolve_expression_value_and_dependencies(
+                            dependencies,
+                            cell,
+                            &pair[0],
+                        );
+                    };
+                    let case =
+                        self.resolve_expression_value_and_dependencies(dependencies, cell, case)?;
+                    match logic::operand(&case) {
+                        Ok(case) if logic::compare(Operator::Equal, &value, &case) => {
+                            return self.resolve_expression_value_and_dependencies(
+                                dependencies,
+                                cell,
+                                result,
+                            );
+                        }
+                        Ok(_) => {}
+                        Err(error) => return Ok(error),
+                    }
+                }
+                Ok(logic::error_value("no case of switch matches"))
+            }
+            SpecialForm::IfError => {
+                // The failed resolution (e.g. the function threw) is the error too.
+                match self.resolve_expression_value_and_dependencies(dependencies, cell, &inputs[0])
+                {
+                    Ok(value) if !logic::is_error(&value) => Ok(value),
+                    _ => self.resolve_expression_value_and_dependencies(
+                        dependencies,
+                        cell,
+                        &inputs[1],
+                    ),
+                }
+            }
+            SpecialForm::And | SpecialForm::Or => {
+                // The first false argument decides the `and`, the first true one the `or`.
+                let decisive = form == SpecialForm::Or;
+                for input in inputs {
+                    let value =
+                        self.resolve_expression_value_and_dependencies(dependencies, cell, input)?;
+                    let mut values = Vec::new();
+                    logic::flatten(value, &mut values);
+                    for value in values {
+                        let truth = logic::operand(&value).and_then(|operand| {
+                            logic::truthy(&operand).map_err(|err| logic::error_value(&err))
+                        });
+                        match truth {
+                            Ok(truth) if truth == decisive => {
+                                return Ok(JsValue::from_bool(decisive));
                             }
-                            ref_values.push(&ref_value);
+                            Ok(_) => {}
+                            Err(error) => return Ok(error),
                         }
                     }
-                    Ok(JsValue::from(ref_values))
                 }
-            },
-            Expression::Value(val) => Ok(JsValue::from_str(&val)),
+                Ok(JsValue::from_bool(!decisive))
+            }
         }
     }
 
+    /// Truth of the condition, `Err` with the error value if it's an error or not a boolean.
+    fn resolve_condition(
+        &mut self,
+        dependencies: &mut Dependencies,
+        cell: Option<CellPointer>,
+        condition: &Expression,
+    ) -> Result<Result<bool, JsValue>, JsValue> {
+        let value =
+            self.resolve_expression_value_and_dependencies(dependencies, cell, condition)?;
+        Ok(logic::operand(&value)
+            .and_then(|operand| logic::truthy(&operand).map_err(|err| logic::error_value(&err))))
+    }
+
+    /// Rows of the range's values, the empty cells are `null`. The range's top left cell is
+    /// set as its origin, the functions return the references into the range relative to it.
+    fn resolve_range_value(
+        &mut self,
+        cols: RangeInclusive<usize>,
+        rows: RangeInclusive<usize>,
+    ) -> Result<JsValue, JsValue> {
+        let range = Array::new();
+        for row in rows.clone() {
+            let values = Array::new();
+            for col in cols.clone() {
+                let value =
+                    self.resolve_single_reference_value_and_dependencies(&CellPointer(col, row))?;
+                match value.is_undefined() {
+                    true => values.push(&JsValue::null()),
+                    false => values.push(&value),
+                };
+            }
+            range.push(&values);
+        }
+        let origin = Array::of2(
+            &JsValue::from(*cols.start() as u32),
+            &JsValue::from(*rows.start() as u32),
+        );
+        js_sys::Reflect::set(&range, &ORIGIN_PROPERTY.into(), &origin)?;
+        Ok(range.into())
+    }
+
     fn resolve_single_reference_value_and_dependencies(
         &mut self,
         key: &CellPointer,
@@ -621,20 +957,105 @@ impl State {
                         let target_resolved_value = self
                             .resolve_expression_value_and_dependencies(
                                 &mut target_dependencies,
+                                Some(*key),
                                 &parsed_expression,
                             )?;
+                        // The dependencies are left for the cell's own resolution, which adds
+                        // them to the reverse index.
                         self.cells.entry(key.clone()).and_modify(|entry| {
                             entry.resolved_value = Some(target_resolved_value.clone());
-                            entry.resolved_dependencies = Some(target_dependencies);
                         });
                         Ok(target_resolved_value)
                     }
                 }
             }
-            // None => Err(JsValue::from_str(&format!("reference '{key}' not found"))),
-            None => Ok(JsValue::null()), // TODO: Goal is to coerce invalid ref to empty values.
+            None => match self.spilled.get(key) {
+                Some(spilled) => Ok(spilled.value.clone()),
+                // None => Err(JsValue::from_str(&format!("reference '{key}' not found"))),
+                None => Ok(JsValue::null()), // TODO: Goal is to coerce invalid ref to empty values.
+            },
+        }
+    }
+}
+
+/// Property of the range value with the column and the row of its top left cell.
+const ORIGIN_PROPERTY: &str = "__sheeet_origin";
+/// Property of the function's returned object with the referenced cells, the columns
+/// and the rows of the top left and the bottom right cell, e.g. `index()` returns a cell of
+/// its range.
+const REFERENCE_PROPERTY: &str = "__sheeet_reference";
+
+fn returned_reference(value: &JsValue) -> Option<Reference> {
+    if !value.is_object() || Array::is_array(value) {
+        return None;
+    }
+    let corners = js_sys::Reflect::get(value, &REFERENCE_PROPERTY.into()).ok()?;
+    if !Array::is_array(&corners) {
+        return None;
+    }
+    let corners: Vec<usize> = Array::from(&corners)
+        .iter()
+        .map(|corner| corner.as_f64().map(|corner| corner as usize))
+        .collect::<Option<_>>()?;
+    match corners[..] {
+        [col, row, end_col, end_row] if (col, row) == (end_col, end_row) => {
+            Some(Reference::Single(CellPointer(col, row)))
+        }
+        [col, row, end_col, end_row] => Some(Reference::BoundedRange(
+            CellPointer(col, row),
+            CellPointer(end_col, end_row),
+        )),
+        _ => None,
+    }
+}
+
+/// Cells the array value of the origin spills into, with their values. A flat array spills
+/// to the right of the origin, an array of arrays row by row below it, the origin shows
+/// the first value.
+fn spill_layout(origin: CellPointer, value: &JsValue) -> Vec<(CellPointer, JsValue)> {
+    if !Array::is_array(value) {
+        return Vec::new();
+    }
+    let array = Array::from(value);
+    let rows: Vec<Vec<JsValue>> = match array.iter().any(|row| Array::is_array(&row)) {
+        true => array
+            .iter()
+            .map(|row| match Array::is_array(&row) {
+                true => Array::from(&row).iter().collect(),
+                false => vec![row],
+            })
+            .collect(),
+        false => vec![array.iter().collect()],
+    };
+    let mut layout = Vec::new();
+    for (row_offset, row) in rows.into_iter().enumerate() {
+        for (col_offset, value) in row.into_iter().enumerate() {
+            if (row_offset, col_offset) != (0, 0) {
+                layout.push((
+                    CellPointer(origin.0 + col_offset, origin.1 + row_offset),
+                    value,
+                ));
+            }
         }
     }
+    layout
+}
+
+/// Value shown in the cell, the array value shows its first element, the rest spills.
+/// The dates are shown as the ISO texts.
+fn display_value(value: &JsValue) -> JsValue {
+    let value = match Array::is_array(value) {
+        true => match Array::from(value).get(0) {
+            first if Array::is_array(&first) => Array::from(&first).get(0),
+            first => first,
+        },
+        false => value.clone(),
+    };
+    match value.dyn_ref::<js_sys::Date>().map(js_sys::Date::get_time) {
+        Some(timestamp) if timestamp.is_finite() => JsValue::from_str(&date::format(timestamp)),
+        Some(_) => JsValue::from_str("invalid date"),
+        None => value,
+    }
 }
 
 pub fn dispatch_display_cell_value_event(key: CellPointer, value: JsValue) -> Result<(), JsValue> {
@@ -657,3 +1078,104 @@ pub fn dispatch_display_cell_value_event(key: CellPointer, value: JsValue) -> Re
 
     Ok(())
 }
+
+/// Engine tests, run by `wasm-bindgen-test-runner` in Node.js, e.g.
+/// `cargo test -p sheeet-wasm --target wasm32-unknown-unknown`.
+#[cfg(all(test, target_arch = "wasm32"))]
+mod test {
+    use super::*;
+    use js_sys::{Function, Reflect};
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    /// Sheet of the build exporting the `functions` (JS source of an object of them), called
+    /// the way the GUI calls them.
+    fn sheet(manifest: &str, functions: &str) -> State {
+        let global = js_sys::global();
+        let functions = Function::new_no_args(&format!("return {functions}")).call0(&global);
+        Reflect::set(&global, &"window".into(), &global).unwrap();
+        Reflect::set(
+            &global,
+            &"js_evaluate".into(),
+            &Function::new_with_args("fnName, vars", "return userFunctions[fnName](...vars)"),
+        )
+        .unwrap();
+        Reflect::set(&global, &"userFunctions".into(), &functions.unwrap()).unwrap();
+        State::new(Some(Functions::from_manifest(manifest).unwrap()))
+    }
+
+    #[wasm_bindgen_test]
+    fn test_upsert_cell_with_unknown_function() {
+        let mut state = sheet(
+            r#"[{"name": "add", "params": [{"optional": false}, {"optional": false}]}]"#,
+            "{add: (a, b) => Number(a) + Number(b)}",
+        );
+        let cell = CellPointer(1, 1);
+        state.upsert_cell(cell, "=add(1, 2)").unwrap();
+        assert_eq!(state.get_cell_resolved_value(cell), Some(JsValue::from(3)));
+
+        // The cell is saved, its value is the error.
+        for raw in ["=sub(2, 1)", "=if(true, 1, sub(2, 1))"] {
+            let value = state.upsert_cell(cell, raw).unwrap();
+            assert_eq!(state.get_cell_raw_value(cell).as_deref(), Some(raw));
+            assert!(logic::is_error(&value), "{raw}: {value:?}");
+            assert_eq!(
+                value.unchecked_into::<js_sys::Error>().message(),
+                "unknown function 'sub'"
+            );
+        }
+        let serialized = state.to_serializable_state();
+        assert_eq!(serialized.data[&cell], "=if(true, 1, sub(2, 1))");
+    }
+
+    #[wasm_bindgen_test]
+    fn test_call_with_range() {
+        // `concat_with(vec: Vec<String>, sep: &str)` fails on anything but the strings, as
+        // the wasm-bindgen glue does.
+        let mut state = sheet(
+            r#"[
+                {"name": "concat_with", "params": [{"ty": "string[]", "optional": false}, {"ty": "string", "optional": false}]},
+                {"name": "rows", "params": [{"ty": "any[][]", "optional": false}]}
+            ]"#,
+            r#"{
+                concat_with: (vec, sep) => vec.map((s) => {
+                    if (typeof s !== "string") throw new Error("expected a string");
+                    return s;
+                }).join(sep),
+                rows: (range) => JSON.stringify(range),
+            }"#,
+        );
+        state.upsert_cell(CellPointer(1, 1), "a").unwrap();
+        state.upsert_cell(CellPointer(2, 1), "b").unwrap();
+        state.upsert_cell(CellPointer(1, 3), "c").unwrap();
+
+        let value = state
+            .upsert_cell(CellPointer(4, 1), r#"=concat_with(A1:B3, "-")"#)
+            .unwrap();
+        assert_eq!(value.as_string().as_deref(), Some("a-b-c"));
+        let value = state
+            .upsert_cell(CellPointer(4, 2), "=rows(A1:B3)")
+            .unwrap();
+        assert_eq!(
+            value.as_string().as_deref(),
+            Some(r#"[["a","b"],[null,null],["c",null]]"#)
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn test_compare_returned_bool() {
+        let mut state = sheet(
+            r#"[{"name": "is_even", "params": [{"ty": "number", "optional": false}], "return_type": "boolean"}]"#,
+            "{is_even: (n) => Number(n) % 2 === 0}",
+        );
+        state.upsert_cell(CellPointer(1, 1), "4").unwrap();
+        for (raw, expected) in [
+            ("=is_even(A1) = true", true),
+            ("=is_even(A1) = FALSE", false),
+            ("=true = is_even(3)", false),
+            ("=is_even(3) <> true", true),
+        ] {
+            let value = state.upsert_cell(CellPointer(2, 1), raw).unwrap();
+            assert_eq!(value.as_bool(), Some(expected), "{raw}: {value:?}");
+        }
+    }
+}